@@ -7,7 +7,7 @@ struct Grid2D {
 }
 
 impl ToRule for Grid2D {
-    fn to_rule(&self) -> Rule {
+    fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
         rule![
             tf![
                 Replicate::n(self.rows, Tf::ty(1.1)),
@@ -7,11 +7,10 @@ use palette::encoding::pixel::Pixel;
 use palette::encoding::srgb::Srgb;
 use palette::rgb::Rgb;
 use rand::seq::SliceRandom;
-use rand::thread_rng;
 use rand::Rng;
 use std::fs::File;
 use std::io::BufWriter;
-use std::rc::Rc;
+use std::sync::Arc;
 
 const SPHERE_RESOLUTION: usize = 0;
 
@@ -32,8 +31,8 @@ fn hexcolor(hex: &str) -> Hsv {
     Hsv::from(color)
 }
 
-trait Tilable: Clone + 'static {
-    fn to_tile(&self, row: usize, col: usize) -> Rule;
+trait Tilable: Clone + Send + Sync + 'static {
+    fn to_tile(&self, row: usize, col: usize, ctx: &mut Context) -> Rule;
 }
 
 struct GridTile<T> {
@@ -43,8 +42,8 @@ struct GridTile<T> {
 }
 
 impl<T: Tilable> ToRule for GridTile<T> {
-    fn to_rule(&self) -> Rule {
-        self.tilable.to_tile(self.row, self.col)
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule {
+        self.tilable.to_tile(self.row, self.col, ctx)
     }
 }
 
@@ -67,11 +66,11 @@ fn grid(rows: usize, cols: usize, rule: impl Tilable) -> Rule {
 #[derive(Clone)]
 struct Pyramid {
     levels: usize,
-    sphere: Rc<Mesh>,
+    sphere: Arc<Mesh>,
 }
 
 impl ToRule for Pyramid {
-    fn to_rule(&self) -> Rule {
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule {
         (0..self.levels).fold(Rule::new(), |rule, i| {
             let target_downscale = 1.0 - ((i + 1) as f32 / self.levels as f32);
             rule.push(
@@ -82,12 +81,9 @@ impl ToRule for Pyramid {
                         target_downscale
                     ),
                     Tf::ty(i as f32),
-                    Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap())
+                    Tf::color(*PALETTE.choose(ctx).unwrap())
                 ],
-                (*&[cube(), self.sphere.to_rule()]
-                    .choose(&mut thread_rng())
-                    .unwrap())
-                    .clone(),
+                (*&[cube(), self.sphere.to_rule()].choose(ctx).unwrap()).clone(),
             )
         })
     }
@@ -97,16 +93,16 @@ impl ToRule for Pyramid {
 struct Tower;
 
 impl ToRule for Tower {
-    fn to_rule(&self) -> Rule {
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule {
         let thin = 0.002;
-        let bars = thread_rng().gen_range(4, 20);
+        let bars = ctx.gen_range(4, 20);
         let height = 0.03 * (1.0 / bars as f32);
         let bar = rule![
-            tf![Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap()), Tf::tx(-0.5), Tf::sby(thin, height, 1.0)] => cube(),
-            tf![Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap()), Tf::tx(0.5), Tf::sby(thin, height, 1.0)] => cube(),
-            tf![Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap()), Tf::tz(-0.5), Tf::sby(1.0, height, thin)] => cube(),
-            tf![Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap()), Tf::tz(0.5), Tf::sby(1.0, height, thin)] => cube(),
-            tf![Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap()), Tf::ty(0.0), Tf::s(0.2)] => icosphere(),
+            tf![Tf::color(*PALETTE.choose(ctx).unwrap()), Tf::tx(-0.5), Tf::sby(thin, height, 1.0)] => cube(),
+            tf![Tf::color(*PALETTE.choose(ctx).unwrap()), Tf::tx(0.5), Tf::sby(thin, height, 1.0)] => cube(),
+            tf![Tf::color(*PALETTE.choose(ctx).unwrap()), Tf::tz(-0.5), Tf::sby(1.0, height, thin)] => cube(),
+            tf![Tf::color(*PALETTE.choose(ctx).unwrap()), Tf::tz(0.5), Tf::sby(1.0, height, thin)] => cube(),
+            tf![Tf::color(*PALETTE.choose(ctx).unwrap()), Tf::ty(0.0), Tf::s(0.2)] => icosphere(),
         ];
         rule![
             Replicate::n(bars, Tf::ty(height * 13.0)) => bar,
@@ -116,13 +112,13 @@ impl ToRule for Tower {
 
 #[derive(Clone)]
 struct CityBlock {
-    sphere: Rc<Mesh>,
+    sphere: Arc<Mesh>,
     noise: Fbm,
     depth: usize,
 }
 
 impl Tilable for CityBlock {
-    fn to_tile(&self, row: usize, col: usize) -> Rule {
+    fn to_tile(&self, row: usize, col: usize, ctx: &mut Context) -> Rule {
         let division = (self.noise.get([row as f64, col as f64]).abs() * 10.0) as usize + 4;
         let mut candidates = vec![
             rule![None => Pyramid {
@@ -142,8 +138,7 @@ impl Tilable for CityBlock {
                 },
             )]);
         }
-        let mut rng = thread_rng();
-        (&candidates).choose(&mut rng).unwrap().clone()
+        (&candidates).choose(ctx).unwrap().clone()
     }
 }
 
@@ -151,10 +146,10 @@ impl Tilable for CityBlock {
 struct Wire;
 
 impl Tilable for Wire {
-    fn to_tile(&self, _: usize, _: usize) -> Rule {
+    fn to_tile(&self, _: usize, _: usize, ctx: &mut Context) -> Rule {
         let height = 0.03;
         let thin = 0.05;
-        rule![Tf::color(*PALETTE.choose(&mut thread_rng()).unwrap()) => rule![
+        rule![Tf::color(*PALETTE.choose(ctx).unwrap()) => rule![
             tf![Tf::tx(-0.5), Tf::sby(thin, height, 1.0)] => cube(),
             tf![Tf::tx(0.5), Tf::sby(thin, height, 1.0)] => cube(),
             tf![Tf::tz(-0.5), Tf::sby(1.0, height, thin)] => cube(),
@@ -1,15 +1,15 @@
 use immense::*;
-use rand::*;
+use rand::seq::SliceRandom;
 use std::fs::File;
 
 #[derive(Debug)]
 struct RandCube;
 
 impl ToRule for RandCube {
-    fn to_rule(&self) -> Rule {
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule {
         rule![
-            *thread_rng()
-                .choose(&[Tf::tx(0.1), Tf::tx(-0.1), Tf::tx(0.2), Tf::tx(-0.2)])
+            *[Tf::tx(0.1), Tf::tx(-0.1), Tf::tx(0.2), Tf::tx(-0.2)]
+                .choose(ctx)
                 .unwrap() =>
             cube()
         ]
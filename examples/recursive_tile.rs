@@ -6,7 +6,7 @@ struct RecursiveTile {
 }
 
 impl ToRule for RecursiveTile {
-    fn to_rule(&self) -> Rule {
+    fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
         let rule = rule![
             tf![Tf::t(0.25, 0.25, 0.0), Tf::s(0.4)] => icosphere(),
             tf![Tf::t(-0.25, -0.25, 0.0), Tf::s(0.4)] => icosphere(),
@@ -0,0 +1,362 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Renders a rule script to a mesh file.
+//!
+//! Scripts are EisenScript, or JSON rule descriptions if their file name ends in `.json`. The
+//! output format is chosen by the extension of the output file: `.obj`, `.stl`, `.gltf` or
+//! `.glb`. Run with `--help` for the list of options.
+
+use failure::{bail, format_err, Error};
+use immense::*;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::Path;
+use std::process;
+use std::str::FromStr;
+
+const USAGE: &str = "\
+usage: immense [OPTIONS] <SCRIPT> <OUTPUT>
+
+Expands the EisenScript rule in SCRIPT and writes the meshes to OUTPUT. If
+SCRIPT ends in .json, it is read as a JSON rule description instead. The output
+format is chosen by the extension of OUTPUT: .obj, .stl, .gltf or .glb.
+
+Expansion options override the script's own `set` directives:
+    --seed <N>          seed for random choices
+    --max-depth <N>     maximum depth of nested rule invocations [default: 1000]
+    --max-meshes <N>    maximum number of meshes to output [default: 1000000]
+    --min-size <F>      minimum size of an invocation before it is culled
+
+Export options:
+    --grouping <MODE>   `together` (default), `individual` or `color`
+    --mtl <PATH>        write colors to a material library (.obj only)
+    --buffer <PATH>     buffer file for .gltf output [default: OUTPUT with .bin]
+    --instanced         write each distinct mesh once (.gltf and .glb only)
+    --ascii             write ASCII instead of binary (.stl only)
+    -h, --help          print this message";
+
+/// The depth limit of scripts which set none, so that recursive rules terminate.
+const DEFAULT_MAX_DEPTH: usize = 1000;
+
+/// The mesh limit of scripts which set none, so that recursive rules terminate.
+const DEFAULT_MAX_MESHES: usize = 1_000_000;
+
+/// The output file formats, named by their file extensions.
+#[derive(Debug, PartialEq)]
+enum Format {
+    Obj,
+    Stl,
+    Gltf,
+    Glb,
+}
+
+struct Options {
+    script: String,
+    output: String,
+    seed: Option<u64>,
+    max_depth: Option<usize>,
+    max_meshes: Option<usize>,
+    min_size: Option<f32>,
+    grouping: MeshGrouping,
+    mtl: Option<String>,
+    buffer: Option<String>,
+    instanced: bool,
+    ascii: bool,
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+        println!("{}", USAGE);
+        return;
+    }
+    let options = match parse_args(args) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("immense: {}\n\n{}", e, USAGE);
+            process::exit(2);
+        }
+    };
+    if let Err(e) = run(options) {
+        eprintln!("immense: {}", e);
+        process::exit(1);
+    }
+}
+
+fn run(options: Options) -> Result<(), Error> {
+    let format = output_format(&options.output)?;
+    let source = fs::read_to_string(&options.script)
+        .map_err(|e| format_err!("could not read {}: {}", options.script, e))?;
+    let is_json = Path::new(&options.script)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let (rule, config) = if is_json {
+        let description: RuleDescription = serde_json::from_str(&source)
+            .map_err(|e| format_err!("invalid rule description {}: {}", options.script, e))?;
+        (description.compile()?, ExpansionConfig::default())
+    } else {
+        let script = EisenScript::parse(&source)?;
+        (script.to_rule(), script.expansion_config())
+    };
+
+    let meshes = rule.generate_with(expansion_config(&options, config));
+
+    let buffer_file = match format {
+        Format::Gltf => Some(options.buffer.clone().unwrap_or_else(|| {
+            Path::new(&options.output)
+                .with_extension("bin")
+                .to_string_lossy()
+                .into_owned()
+        })),
+        _ => None,
+    };
+    let sink = BufWriter::new(
+        File::create(&options.output)
+            .map_err(|e| format_err!("could not create {}: {}", options.output, e))?,
+    );
+    match format {
+        Format::Obj => write_meshes(
+            ExportConfig {
+                grouping: options.grouping,
+                export_colors: options.mtl,
+            },
+            meshes,
+            sink,
+        )?,
+        Format::Stl => write_stl(
+            if options.ascii {
+                StlFormat::Ascii
+            } else {
+                StlFormat::Binary
+            },
+            meshes,
+            sink,
+        )?,
+        Format::Gltf | Format::Glb => write_gltf(
+            GltfConfig {
+                grouping: options.grouping,
+                buffer_file,
+                instanced: options.instanced,
+            },
+            meshes,
+            sink,
+        )?,
+    }
+    Ok(())
+}
+
+/// Applies the command line's expansion options over the script's, and the default limits where
+/// neither sets one.
+fn expansion_config(options: &Options, script: ExpansionConfig) -> ExpansionConfig {
+    ExpansionConfig {
+        seed: options.seed.or(script.seed),
+        // Like `set maxdepth`, the flag counts the rule invocations below the root one.
+        max_depth: options
+            .max_depth
+            .map(|max_depth| max_depth + 1)
+            .or(script.max_depth)
+            .or(Some(DEFAULT_MAX_DEPTH + 1)),
+        max_meshes: options
+            .max_meshes
+            .or(script.max_meshes)
+            .or(Some(DEFAULT_MAX_MESHES)),
+        min_size: options.min_size.or(script.min_size),
+    }
+}
+
+fn output_format(output: &str) -> Result<Format, Error> {
+    let extension = Path::new(output)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_lowercase());
+    match extension.as_deref() {
+        Some("obj") => Ok(Format::Obj),
+        Some("stl") => Ok(Format::Stl),
+        Some("gltf") => Ok(Format::Gltf),
+        Some("glb") => Ok(Format::Glb),
+        _ => bail!(
+            "cannot tell the output format of {}; use .obj, .stl, .gltf or .glb",
+            output
+        ),
+    }
+}
+
+fn parse_args(args: Vec<String>) -> Result<Options, Error> {
+    let mut positional = vec![];
+    let mut options = Options {
+        script: String::new(),
+        output: String::new(),
+        seed: None,
+        max_depth: None,
+        max_meshes: None,
+        min_size: None,
+        grouping: MeshGrouping::default(),
+        mtl: None,
+        buffer: None,
+        instanced: false,
+        ascii: false,
+    };
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format_err!("{} requires a value", arg))
+        };
+        match arg.as_str() {
+            "--seed" => options.seed = Some(parse_value(&arg, &value()?)?),
+            "--max-depth" => options.max_depth = Some(parse_value(&arg, &value()?)?),
+            "--max-meshes" => options.max_meshes = Some(parse_value(&arg, &value()?)?),
+            "--min-size" => options.min_size = Some(parse_value(&arg, &value()?)?),
+            "--grouping" => {
+                options.grouping = match value()?.as_str() {
+                    "together" => MeshGrouping::AllTogether,
+                    "individual" => MeshGrouping::Individual,
+                    "color" => MeshGrouping::ByColor,
+                    mode => bail!("unknown grouping `{}`", mode),
+                }
+            }
+            "--mtl" => options.mtl = Some(value()?),
+            "--buffer" => options.buffer = Some(value()?),
+            "--instanced" => options.instanced = true,
+            "--ascii" => options.ascii = true,
+            _ if arg.starts_with('-') && arg.len() > 1 => bail!("unknown option `{}`", arg),
+            _ => positional.push(arg),
+        }
+    }
+    if positional.len() != 2 {
+        bail!("expected a script and an output file");
+    }
+    options.output = positional.pop().unwrap();
+    options.script = positional.pop().unwrap();
+    Ok(options)
+}
+
+fn parse_value<T: FromStr>(flag: &str, value: &str) -> Result<T, Error> {
+    value
+        .parse()
+        .map_err(|_| format_err!("invalid value `{}` for {}", value, flag))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &str) -> Vec<String> {
+        args.split_whitespace().map(String::from).collect()
+    }
+
+    fn parse_error(arguments: &str) -> String {
+        match parse_args(args(arguments)) {
+            Ok(_) => panic!("invalid arguments parsed"),
+            Err(error) => error.to_string(),
+        }
+    }
+
+    #[test]
+    fn arguments_parse() {
+        let options = parse_args(args(
+            "--seed 7 --max-depth 20 --max-meshes 500 --min-size 0.25 --grouping color \
+             --mtl tower.mtl --buffer tower.bin --instanced --ascii tower.es tower.obj",
+        ))
+        .expect("valid arguments");
+        assert_eq!(options.script, "tower.es");
+        assert_eq!(options.output, "tower.obj");
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.max_depth, Some(20));
+        assert_eq!(options.max_meshes, Some(500));
+        assert_eq!(options.min_size, Some(0.25));
+        assert!(matches!(options.grouping, MeshGrouping::ByColor));
+        assert_eq!(options.mtl.as_deref(), Some("tower.mtl"));
+        assert_eq!(options.buffer.as_deref(), Some("tower.bin"));
+        assert!(options.instanced);
+        assert!(options.ascii);
+
+        let options = parse_args(args("tower.es tower.stl")).expect("valid arguments");
+        assert_eq!(options.seed, None);
+        assert!(matches!(options.grouping, MeshGrouping::AllTogether));
+        assert!(!options.instanced && !options.ascii);
+    }
+
+    #[test]
+    fn invalid_arguments_are_errors() {
+        assert_eq!(
+            parse_error("tower.es"),
+            "expected a script and an output file"
+        );
+        assert_eq!(
+            parse_error("--seed seven tower.es tower.obj"),
+            "invalid value `seven` for --seed"
+        );
+        assert_eq!(
+            parse_error("tower.es tower.obj --max-depth"),
+            "--max-depth requires a value"
+        );
+        assert_eq!(
+            parse_error("--grouping all tower.es tower.obj"),
+            "unknown grouping `all`"
+        );
+        assert_eq!(
+            parse_error("--fast tower.es tower.obj"),
+            "unknown option `--fast`"
+        );
+    }
+
+    #[test]
+    fn formats_follow_extensions() {
+        assert_eq!(output_format("out/tower.obj").ok(), Some(Format::Obj));
+        assert_eq!(output_format("tower.STL").ok(), Some(Format::Stl));
+        assert_eq!(output_format("tower.gltf").ok(), Some(Format::Gltf));
+        assert_eq!(output_format("tower.glb").ok(), Some(Format::Glb));
+        assert!(output_format("tower.ply").is_err());
+        assert!(output_format("tower").is_err());
+    }
+
+    #[test]
+    fn limits_default_when_unset() {
+        let options = parse_args(args("--max-depth 5 tower.es tower.obj")).expect("valid");
+        let config = expansion_config(
+            &options,
+            ExpansionConfig {
+                seed: Some(3),
+                max_depth: Some(40),
+                ..ExpansionConfig::default()
+            },
+        );
+        assert_eq!(config.seed, Some(3));
+        assert_eq!(config.max_depth, Some(6));
+        assert_eq!(config.max_meshes, Some(DEFAULT_MAX_MESHES));
+        assert_eq!(config.min_size, None);
+
+        let options = parse_args(args("tower.es tower.obj")).expect("valid");
+        let config = expansion_config(&options, ExpansionConfig::default());
+        assert_eq!(config.max_depth, Some(DEFAULT_MAX_DEPTH + 1));
+    }
+
+    #[test]
+    fn max_depth_flag_matches_the_script_setting() {
+        let script = EisenScript::parse("set maxdepth 5 box").expect("valid script");
+        let options = parse_args(args("--max-depth 5 tower.es tower.obj")).expect("valid");
+        assert_eq!(
+            expansion_config(&options, ExpansionConfig::default()).max_depth,
+            script.expansion_config().max_depth
+        );
+        let options = parse_args(args("tower.es tower.obj")).expect("valid");
+        assert_eq!(
+            expansion_config(&options, script.expansion_config()).max_depth,
+            Some(6)
+        );
+    }
+}
@@ -0,0 +1,454 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! Rule trees described as plain data, which can be serialized.
+
+use crate::mesh::Mesh;
+use crate::rule::*;
+use failure_derive::Fail;
+use palette::Hsv;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Arc;
+
+#[derive(Fail, Debug)]
+pub enum DescriptionError {
+    #[fail(display = "No rule is named `{}`.", name)]
+    UnknownRule { name: String },
+}
+
+/// A rule tree described as plain data, which can be serialized with
+/// [serde](https://serde.rs/) to formats like JSON or RON and compiled into a
+/// [Rule][crate::rule::Rule].
+///
+/// The root rule is a list of invocations, and so is each named rule. Invocations may refer to
+/// named rules, including the rule they are in, so recursive descriptions should be expanded
+/// with limits like [max_depth][crate::rule::ExpansionConfig::max_depth].
+///
+/// ````
+/// # use immense::*;
+/// # fn main() -> Result<(), failure::Error> {
+/// let description: RuleDescription = serde_json::from_str(r#"{
+///     "root": [
+///         { "transforms": [{ "replicate": { "n": 4, "transforms": [{ "ry": 90 }] } }],
+///           "subrule": { "rule": "tower" } }
+///     ],
+///     "rules": {
+///         "tower": [
+///             { "transforms": [{ "tx": 2 }], "subrule": { "primitive": "cube" } },
+///             { "transforms": [{ "tx": 2 }, { "ty": 1.1 }, { "s": 0.8 }, { "hue": 20 }],
+///               "subrule": { "rule": "tower" } }
+///         ]
+///     }
+/// }"#)?;
+///
+/// let meshes = description.compile()?.generate_with(ExpansionConfig {
+///     max_depth: Some(10),
+///     ..ExpansionConfig::default()
+/// });
+/// # assert_eq!(meshes.count(), 36);
+/// # Ok(())
+/// # }
+/// ````
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct RuleDescription {
+    /// The invocations of the root rule.
+    pub root: Vec<InvocationDescription>,
+    /// The invocations of each named rule.
+    #[serde(default)]
+    pub rules: BTreeMap<String, Vec<InvocationDescription>>,
+}
+
+/// An invocation of a subrule under a list of transforms, like
+/// [Rule::push][crate::rule::Rule::push].
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct InvocationDescription {
+    /// Transforms applied in order, which branch on replications like those in a
+    /// [tf][crate::tf] list.
+    #[serde(default)]
+    pub transforms: Vec<TransformDescription>,
+    pub subrule: SubruleDescription,
+}
+
+/// A transform, named after the [Transform][crate::rule::Transform] constructor it stands for.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TransformDescription {
+    T([f32; 3]),
+    Tx(f32),
+    Ty(f32),
+    Tz(f32),
+    S(f32),
+    Sby([f32; 3]),
+    Rx(f32),
+    Ry(f32),
+    Rz(f32),
+    /// A color as a hue in degrees, a saturation and a value.
+    Color([f32; 3]),
+    Hue(f32),
+    Saturation(f32),
+    Value(f32),
+    /// A transform serialized directly.
+    Transform(Transform),
+    /// Transforms replicated like [Replicate::n][crate::rule::Replicate::n].
+    Replicate {
+        n: usize,
+        transforms: Vec<TransformDescription>,
+    },
+}
+
+/// What an invocation invokes.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SubruleDescription {
+    /// The named rule.
+    Rule(String),
+    Primitive(PrimitiveDescription),
+    /// One of the subrules chosen by weight like [Rule::choose][crate::rule::Rule::choose].
+    Choose(Vec<(f32, SubruleDescription)>),
+}
+
+/// A builtin mesh, named after the builtin function it stands for.
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PrimitiveDescription {
+    Cube,
+    Icosphere,
+    Sphere {
+        resolution: usize,
+    },
+    Cylinder {
+        segments: usize,
+    },
+    Cone {
+        segments: usize,
+    },
+    Torus {
+        major: f32,
+        minor: f32,
+        segments: usize,
+        rings: usize,
+    },
+    Capsule {
+        radius: f32,
+        segments: usize,
+    },
+    Quad,
+    Disc {
+        segments: usize,
+    },
+    Triangle,
+    Line,
+}
+
+impl RuleDescription {
+    /// Compiles the description into a rule, checking that every rule it names is defined.
+    pub fn compile(&self) -> Result<Rule, DescriptionError> {
+        let mut compiler = Compiler {
+            indices: self
+                .rules
+                .keys()
+                .enumerate()
+                .map(|(i, name)| (name.as_str(), i))
+                .collect(),
+            spheres: HashMap::new(),
+        };
+        let rules = self
+            .rules
+            .values()
+            .map(|invocations| compiler.invocations(invocations))
+            .collect::<Result<_, _>>()?;
+        let root = compiler.invocations(&self.root)?;
+        let program = Arc::new(Program { rules });
+        Ok(program.rule(&root))
+    }
+}
+
+struct Program {
+    rules: Vec<Vec<Invocation>>,
+}
+
+struct Invocation {
+    transforms: Vec<Transform>,
+    subrule: Subrule,
+}
+
+enum Subrule {
+    Named(usize),
+    Rule(Rule),
+    Choose(Vec<(f32, Subrule)>),
+}
+
+/// A reference to a named rule, which is only built when it is expanded so rules can recur.
+struct Named {
+    program: Arc<Program>,
+    index: usize,
+}
+
+impl ToRule for Named {
+    fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
+        self.program.rule(&self.program.rules[self.index])
+    }
+}
+
+impl Program {
+    fn rule(self: &Arc<Self>, invocations: &[Invocation]) -> Rule {
+        invocations.iter().fold(Rule::new(), |rule, invocation| {
+            let transforms = TransformArgument::Many(invocation.transforms.clone());
+            match invocation.subrule {
+                Subrule::Named(index) => rule.push(transforms, self.named(index)),
+                ref subrule => rule.push(transforms, self.subrule(subrule)),
+            }
+        })
+    }
+
+    fn named(self: &Arc<Self>, index: usize) -> Named {
+        Named {
+            program: self.clone(),
+            index,
+        }
+    }
+
+    fn subrule(self: &Arc<Self>, subrule: &Subrule) -> Rule {
+        match subrule {
+            Subrule::Named(index) => Rule::new().push(None, self.named(*index)),
+            Subrule::Rule(rule) => rule.clone(),
+            Subrule::Choose(choices) => Rule::choose(
+                choices
+                    .iter()
+                    .map(|(weight, subrule)| (*weight, self.subrule(subrule)))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+struct Compiler<'a> {
+    indices: HashMap<&'a str, usize>,
+    /// Spheres are allocated once for each resolution.
+    spheres: HashMap<usize, Arc<Mesh>>,
+}
+
+impl<'a> Compiler<'a> {
+    fn invocations(
+        &mut self,
+        invocations: &[InvocationDescription],
+    ) -> Result<Vec<Invocation>, DescriptionError> {
+        invocations
+            .iter()
+            .map(|invocation| {
+                Ok(Invocation {
+                    transforms: transforms(&invocation.transforms).into(),
+                    subrule: self.subrule(&invocation.subrule)?,
+                })
+            })
+            .collect()
+    }
+
+    fn subrule(&mut self, subrule: &SubruleDescription) -> Result<Subrule, DescriptionError> {
+        Ok(match subrule {
+            SubruleDescription::Rule(name) => match self.indices.get(name.as_str()) {
+                Some(index) => Subrule::Named(*index),
+                None => {
+                    return Err(DescriptionError::UnknownRule { name: name.clone() });
+                }
+            },
+            SubruleDescription::Primitive(primitive) => Subrule::Rule(self.primitive(*primitive)),
+            SubruleDescription::Choose(choices) => Subrule::Choose(
+                choices
+                    .iter()
+                    .map(|(weight, subrule)| Ok((*weight, self.subrule(subrule)?)))
+                    .collect::<Result<_, _>>()?,
+            ),
+        })
+    }
+
+    fn primitive(&mut self, primitive: PrimitiveDescription) -> Rule {
+        match primitive {
+            PrimitiveDescription::Cube => cube(),
+            PrimitiveDescription::Icosphere => icosphere(),
+            PrimitiveDescription::Sphere { resolution } => {
+                let mesh = self
+                    .spheres
+                    .entry(resolution)
+                    .or_insert_with(|| sphere(resolution));
+                Rule::new().push(None, mesh.clone())
+            }
+            PrimitiveDescription::Cylinder { segments } => cylinder(segments),
+            PrimitiveDescription::Cone { segments } => cone(segments),
+            PrimitiveDescription::Torus {
+                major,
+                minor,
+                segments,
+                rings,
+            } => torus(major, minor, segments, rings),
+            PrimitiveDescription::Capsule { radius, segments } => capsule(radius, segments),
+            PrimitiveDescription::Quad => quad(),
+            PrimitiveDescription::Disc { segments } => disc(segments),
+            PrimitiveDescription::Triangle => triangle(),
+            PrimitiveDescription::Line => line(),
+        }
+    }
+}
+
+/// Composes a list of transforms, branching on replications.
+fn transforms(descriptions: &[TransformDescription]) -> TransformArgument {
+    let arguments: Vec<TransformArgument> = descriptions.iter().map(transform_argument).collect();
+    arguments.into()
+}
+
+fn transform_argument(description: &TransformDescription) -> TransformArgument {
+    match *description {
+        TransformDescription::T([x, y, z]) => Tf::t(x, y, z).into(),
+        TransformDescription::Tx(x) => Tf::tx(x).into(),
+        TransformDescription::Ty(y) => Tf::ty(y).into(),
+        TransformDescription::Tz(z) => Tf::tz(z).into(),
+        TransformDescription::S(factor) => Tf::s(factor).into(),
+        TransformDescription::Sby([x, y, z]) => Tf::sby(x, y, z).into(),
+        TransformDescription::Rx(x) => Tf::rx(x).into(),
+        TransformDescription::Ry(y) => Tf::ry(y).into(),
+        TransformDescription::Rz(z) => Tf::rz(z).into(),
+        TransformDescription::Color([hue, saturation, value]) => {
+            Tf::color(Hsv::new(hue, saturation, value)).into()
+        }
+        TransformDescription::Hue(hue) => Tf::hue(hue).into(),
+        TransformDescription::Saturation(factor) => Tf::saturation(factor).into(),
+        TransformDescription::Value(factor) => Tf::value(factor).into(),
+        TransformDescription::Transform(transform) => transform.into(),
+        TransformDescription::Replicate { n, ref transforms } => {
+            Replicate::n(n, self::transforms(transforms)).into()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::OutputMesh;
+
+    fn description() -> RuleDescription {
+        let invocation = |transforms, subrule| InvocationDescription {
+            transforms,
+            subrule,
+        };
+        let mut rules = BTreeMap::new();
+        rules.insert(
+            String::from("row"),
+            vec![invocation(
+                vec![
+                    TransformDescription::Replicate {
+                        n: 3,
+                        transforms: vec![TransformDescription::Tx(1.1)],
+                    },
+                    TransformDescription::Hue(30.0),
+                ],
+                SubruleDescription::Primitive(PrimitiveDescription::Cube),
+            )],
+        );
+        RuleDescription {
+            root: vec![
+                invocation(
+                    vec![TransformDescription::Transform(
+                        Tf::ty(2.0).cons(Tf::s(0.5)),
+                    )],
+                    SubruleDescription::Rule(String::from("row")),
+                ),
+                invocation(
+                    vec![],
+                    SubruleDescription::Primitive(PrimitiveDescription::Sphere { resolution: 1 }),
+                ),
+            ],
+            rules,
+        }
+    }
+
+    fn expected() -> Rule {
+        let transforms: Vec<TransformArgument> =
+            vec![Replicate::n(3, Tf::tx(1.1)).into(), Tf::hue(30.0).into()];
+        let row = Rule::new().push(transforms, cube());
+        Rule::new()
+            .push(vec![Tf::ty(2.0), Tf::s(0.5)], row)
+            .push(None, sphere(1))
+    }
+
+    fn assert_same_meshes(actual: Rule, expected: Rule) {
+        let summary = |mesh: OutputMesh| {
+            let color = mesh.color();
+            let vertices: Vec<[f32; 3]> = mesh.vertices().map(|v| [v.x, v.y, v.z]).collect();
+            (vertices, [color.red, color.green, color.blue])
+        };
+        let actual: Vec<_> = actual.generate_with_seed(0).map(summary).collect();
+        let expected: Vec<_> = expected.generate_with_seed(0).map(summary).collect();
+        assert_eq!(actual.len(), 4);
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn json_round_trip_compiles_to_the_same_rule() {
+        let json = serde_json::to_string(&description()).expect("json output");
+        let description: RuleDescription = serde_json::from_str(&json).expect("json input");
+        assert_same_meshes(
+            description.compile().expect("valid description"),
+            expected(),
+        );
+    }
+
+    #[test]
+    fn ron_round_trip_compiles_to_the_same_rule() {
+        let text = ron::to_string(&description()).expect("ron output");
+        let description: RuleDescription = ron::from_str(&text).expect("ron input");
+        assert_same_meshes(
+            description.compile().expect("valid description"),
+            expected(),
+        );
+    }
+
+    #[test]
+    fn choices_and_recursion_expand() {
+        let description: RuleDescription = serde_json::from_str(
+            r#"{
+                "root": [{ "subrule": { "rule": "spine" } }],
+                "rules": {
+                    "spine": [
+                        { "subrule": { "choose": [[1, { "primitive": "cube" }],
+                                                  [1, { "primitive": "icosphere" }]] } },
+                        { "transforms": [{ "ty": 1 }], "subrule": { "rule": "spine" } }
+                    ]
+                }
+            }"#,
+        )
+        .expect("valid json");
+        let meshes = description.compile().expect("valid description");
+        let meshes = meshes.generate_with(ExpansionConfig {
+            max_depth: Some(20),
+            ..ExpansionConfig::default()
+        });
+        assert_eq!(meshes.count(), 18);
+    }
+
+    #[test]
+    fn unknown_rules_are_errors() {
+        let description = RuleDescription {
+            root: vec![InvocationDescription {
+                transforms: vec![],
+                subrule: SubruleDescription::Rule(String::from("missing")),
+            }],
+            rules: BTreeMap::new(),
+        };
+        match description.compile() {
+            Err(DescriptionError::UnknownRule { name }) => assert_eq!(name, "missing"),
+            _ => panic!("expected an unknown rule"),
+        }
+    }
+}
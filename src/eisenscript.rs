@@ -0,0 +1,1071 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! A parser for [Structure Synth](http://structuresynth.sourceforge.net/)'s EisenScript.
+
+use crate::error::Result;
+use crate::mesh::{primitives, vertex, Mesh, Vertex};
+use crate::rule::{
+    choose_weighted, cube, line, sphere, Context, ExpansionConfig, Rule, ToRule, Transform,
+    TransformArgument,
+};
+use failure_derive::Fail;
+use nalgebra::Matrix4;
+use palette::{encoding::srgb::Srgb, named, rgb::Rgb, Hsv};
+use std::collections::HashMap;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+const SPHERE_RESOLUTION: usize = 2;
+
+/// An error in an EisenScript source, at the given 1-based line and column.
+#[derive(Fail, Debug)]
+#[fail(display = "{}:{}: {}", line, column, message)]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+/// A rule tree described in EisenScript, the language of
+/// [Structure Synth](http://structuresynth.sourceforge.net/).
+///
+/// The script's top level actions form the root rule, which you can invoke like any other rule.
+/// Rule definitions may have a positive `weight` (or `w`) and a `maxdepth` (or `md`) with an
+/// optional `> retirement` rule, and all definitions of a name are chosen between by weight each
+/// time the name is invoked. Actions may be preceded by transform blocks such as `{ x 1 ry 10 }`
+/// and loops such as `3 * { y 1 }`, whose first copy is untransformed as in Structure Synth.
+///
+/// Transforms `x`, `y`, `z`, `rx`, `ry`, `rz`, `s` (uniform or with three factors), the `fx`, `fy`
+/// and `fz` reflections, `m` (a 3x3 matrix in row order), `hue` (or `h`), `sat`, `b` (or
+/// `brightness`), and `color` (a name or hex code) are supported, and so are the `box`, `sphere`,
+/// `line`, `grid` and `triangle[x,y,z;x,y,z;x,y,z]` primitives. The `set maxdepth`,
+/// `set maxobjects`, `set minsize`, and `set seed` settings are available in
+/// [expansion_config][EisenScript::expansion_config]; other settings are ignored.
+///
+/// ````
+/// # use failure::{Error};
+/// # let _ = || -> Result<(), Error> {
+/// use immense::*;
+///
+/// let script = EisenScript::parse(r#"
+///     set maxdepth 40
+///     10 * { ry 36 hue 36 } spiral
+///
+///     rule spiral w 10 {
+///         box
+///         { y 1.1 rx 5 s 0.95 } spiral
+///     }
+///
+///     rule spiral w 1 {
+///         { s 0.5 } sphere
+///     }
+/// "#)?;
+/// let meshes = script.to_rule().generate_with(script.expansion_config());
+/// # Ok(())
+/// # };
+/// ````
+#[derive(Clone)]
+pub struct EisenScript {
+    program: Arc<Program>,
+    config: ExpansionConfig,
+}
+
+impl EisenScript {
+    /// Parses an EisenScript source.
+    pub fn parse(source: &str) -> Result<EisenScript> {
+        let mut parser = Parser::new(Lexer::new(source).tokens()?);
+        let start = parser.parse()?;
+        let config = parser.config.clone();
+        let program = parser.finish(start)?;
+        Ok(EisenScript {
+            program: Arc::new(program),
+            config,
+        })
+    }
+
+    /// The expansion limits and seed set in the script.
+    pub fn expansion_config(&self) -> ExpansionConfig {
+        self.config.clone()
+    }
+}
+
+impl ToRule for EisenScript {
+    fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
+        let depths = Arc::new(vec![0; self.program.definition_count]);
+        self.program.actions(&self.program.start, &depths)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Word(String),
+    Number(f32),
+    OpenBrace,
+    CloseBrace,
+    Star,
+    Greater,
+    Symbol(char),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Word(word) => write!(f, "`{}`", word),
+            Token::Number(number) => write!(f, "`{}`", number),
+            Token::OpenBrace => write!(f, "`{{`"),
+            Token::CloseBrace => write!(f, "`}}`"),
+            Token::Star => write!(f, "`*`"),
+            Token::Greater => write!(f, "`>`"),
+            Token::Symbol(c) => write!(f, "`{}`", c),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Spanned {
+    token: Token,
+    line: usize,
+    column: usize,
+    /// How many `#define` replacements produced this token.
+    expansions: usize,
+}
+
+fn error_at(line: usize, column: usize, message: impl Into<String>) -> ParseError {
+    ParseError {
+        line,
+        column,
+        message: message.into(),
+    }
+}
+
+struct Lexer<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> Lexer<'a> {
+    fn new(source: &'a str) -> Self {
+        Self {
+            chars: source.chars().peekable(),
+            line: 1,
+            column: 1,
+        }
+    }
+
+    fn bump(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> String {
+        let mut taken = String::new();
+        while let Some(&c) = self.chars.peek() {
+            if !predicate(c) {
+                break;
+            }
+            taken.push(c);
+            self.bump();
+        }
+        taken
+    }
+
+    fn tokens(mut self) -> std::result::Result<Vec<Spanned>, ParseError> {
+        let mut tokens = vec![];
+        while let Some(&c) = self.chars.peek() {
+            let (line, column) = (self.line, self.column);
+            let token = match c {
+                _ if c.is_whitespace() => {
+                    self.bump();
+                    continue;
+                }
+                '/' => {
+                    self.bump();
+                    match self.bump() {
+                        Some('/') => {
+                            self.take_while(|c| c != '\n');
+                        }
+                        Some('*') => {
+                            let mut previous = None;
+                            loop {
+                                match self.bump() {
+                                    Some('/') if previous == Some('*') => break,
+                                    Some(c) => previous = Some(c),
+                                    None => {
+                                        return Err(error_at(line, column, "unterminated comment"))
+                                    }
+                                }
+                            }
+                        }
+                        _ => return Err(error_at(line, column, "unexpected `/`")),
+                    }
+                    continue;
+                }
+                '{' | '}' | '*' | '>' => {
+                    self.bump();
+                    match c {
+                        '{' => Token::OpenBrace,
+                        '}' => Token::CloseBrace,
+                        '*' => Token::Star,
+                        _ => Token::Greater,
+                    }
+                }
+                _ if c.is_ascii_digit() || c == '-' || c == '+' || c == '.' => {
+                    let number = self.take_while(|c| {
+                        c.is_ascii_digit() || c == '-' || c == '+' || c == '.' || c == 'e'
+                    });
+                    match f32::from_str(&number) {
+                        Ok(number) => Token::Number(number),
+                        Err(_) => {
+                            return Err(error_at(
+                                line,
+                                column,
+                                format!("invalid number `{}`", number),
+                            ))
+                        }
+                    }
+                }
+                _ if c.is_alphanumeric() || c == '_' || c == '#' => Token::Word(
+                    self.take_while(|c| c.is_alphanumeric() || c == '_' || c == '#' || c == ':'),
+                ),
+                _ => {
+                    self.bump();
+                    Token::Symbol(c)
+                }
+            };
+            tokens.push(Spanned {
+                token,
+                line,
+                column,
+                expansions: 0,
+            });
+        }
+        Ok(tokens)
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Primitive {
+    Box,
+    Sphere,
+    Line,
+    Grid,
+    /// A triangle, by its index in the program's triangles.
+    Triangle(usize),
+}
+
+#[derive(Clone, Copy)]
+enum Target {
+    Rule(usize),
+    Primitive(Primitive),
+}
+
+struct Action {
+    // The transforms of each copy made by each loop. A plain transform block is a loop with only
+    // one copy.
+    loops: Vec<Vec<Transform>>,
+    target: Target,
+}
+
+impl Action {
+    fn transforms(&self) -> TransformArgument {
+        let loops: Vec<TransformArgument> = self
+            .loops
+            .iter()
+            .map(|copies| TransformArgument::Many(copies.clone()))
+            .collect();
+        if loops.is_empty() {
+            TransformArgument::Many(vec![])
+        } else {
+            loops.into()
+        }
+    }
+}
+
+struct Definition {
+    id: usize,
+    max_depth: Option<usize>,
+    retirement: Option<usize>,
+    actions: Vec<Action>,
+}
+
+struct Program {
+    // The definitions of each name, with their weights.
+    definitions: Vec<Vec<(f32, Definition)>>,
+    definition_count: usize,
+    start: Vec<Action>,
+    sphere: Arc<Mesh>,
+    grid: Arc<Mesh>,
+    triangles: Vec<Arc<Mesh>>,
+}
+
+impl Program {
+    fn actions(self: &Arc<Self>, actions: &[Action], depths: &Arc<Vec<usize>>) -> Rule {
+        actions.iter().fold(Rule::new(), |rule, action| {
+            let transforms = action.transforms();
+            match action.target {
+                Target::Primitive(Primitive::Box) => rule.push(transforms, cube()),
+                Target::Primitive(Primitive::Sphere) => rule.push(transforms, self.sphere.clone()),
+                Target::Primitive(Primitive::Line) => rule.push(transforms, line()),
+                Target::Primitive(Primitive::Grid) => rule.push(transforms, self.grid.clone()),
+                Target::Primitive(Primitive::Triangle(index)) => {
+                    rule.push(transforms, self.triangles[index].clone())
+                }
+                Target::Rule(name) => rule.push(
+                    transforms,
+                    Invocation {
+                        program: self.clone(),
+                        name,
+                        depths: depths.clone(),
+                    },
+                ),
+            }
+        })
+    }
+}
+
+/// An invocation of a named rule, which tracks how many times each definition has recursed.
+struct Invocation {
+    program: Arc<Program>,
+    name: usize,
+    depths: Arc<Vec<usize>>,
+}
+
+impl ToRule for Invocation {
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule {
+        let definition = match choose_weighted(&self.program.definitions[self.name], ctx) {
+            Some(definition) => definition,
+            None => return Rule::new(),
+        };
+        match definition.max_depth {
+            Some(max_depth) if self.depths[definition.id] >= max_depth => {
+                match definition.retirement {
+                    Some(retirement) => Rule::new().push(
+                        None,
+                        Invocation {
+                            program: self.program.clone(),
+                            name: retirement,
+                            depths: self.depths.clone(),
+                        },
+                    ),
+                    None => Rule::new(),
+                }
+            }
+            _ => {
+                let mut depths = self.depths.as_ref().clone();
+                depths[definition.id] += 1;
+                self.program.actions(&definition.actions, &Arc::new(depths))
+            }
+        }
+    }
+}
+
+struct Parser {
+    tokens: Vec<Spanned>,
+    position: usize,
+    defines: HashMap<String, Vec<Spanned>>,
+    names: HashMap<String, usize>,
+    // The first reference to each name, to report if the name is never defined.
+    references: Vec<(usize, usize)>,
+    definitions: Vec<Vec<(f32, Definition)>>,
+    definition_count: usize,
+    triangles: Vec<Arc<Mesh>>,
+    config: ExpansionConfig,
+}
+
+impl Parser {
+    fn new(tokens: Vec<Spanned>) -> Self {
+        Self {
+            tokens,
+            position: 0,
+            defines: HashMap::new(),
+            names: HashMap::new(),
+            references: vec![],
+            definitions: vec![],
+            definition_count: 0,
+            triangles: vec![],
+            config: ExpansionConfig::default(),
+        }
+    }
+
+    fn peek(&self) -> Option<&Spanned> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> std::result::Result<Spanned, ParseError> {
+        match self.tokens.get(self.position).cloned() {
+            Some(token) => {
+                self.position += 1;
+                Ok(token)
+            }
+            None => {
+                let (line, column) = self
+                    .tokens
+                    .last()
+                    .map(|t| (t.line, t.column))
+                    .unwrap_or((1, 1));
+                Err(error_at(line, column, "unexpected end of script"))
+            }
+        }
+    }
+
+    fn expect(&mut self, expected: Token) -> std::result::Result<(), ParseError> {
+        let next = self.next()?;
+        if next.token == expected {
+            Ok(())
+        } else {
+            Err(error_at(
+                next.line,
+                next.column,
+                format!("expected {}, found {}", expected, next.token),
+            ))
+        }
+    }
+
+    fn number(&mut self) -> std::result::Result<f32, ParseError> {
+        let next = self.next()?;
+        match next.token {
+            Token::Number(number) => Ok(number),
+            token => Err(error_at(
+                next.line,
+                next.column,
+                format!("expected a number, found {}", token),
+            )),
+        }
+    }
+
+    fn count(&mut self) -> std::result::Result<usize, ParseError> {
+        let next = self.peek().cloned();
+        let number = self.number()?;
+        match next {
+            Some(next) if number < 0.0 || number.fract() != 0.0 => Err(error_at(
+                next.line,
+                next.column,
+                format!("expected a count, found {}", number),
+            )),
+            _ => Ok(number as usize),
+        }
+    }
+
+    fn weight(&mut self) -> std::result::Result<f32, ParseError> {
+        let next = self.peek().cloned();
+        let number = self.number()?;
+        match next {
+            Some(next) if number <= 0.0 => Err(error_at(
+                next.line,
+                next.column,
+                format!("expected a positive weight, found {}", number),
+            )),
+            _ => Ok(number),
+        }
+    }
+
+    fn word(&mut self) -> std::result::Result<(String, usize, usize), ParseError> {
+        let next = self.next()?;
+        match next.token {
+            Token::Word(word) => Ok((word, next.line, next.column)),
+            token => Err(error_at(
+                next.line,
+                next.column,
+                format!("expected a name, found {}", token),
+            )),
+        }
+    }
+
+    fn name(&mut self, name: &str, line: usize, column: usize) -> usize {
+        if let Some(index) = self.names.get(name) {
+            return *index;
+        }
+        self.names.insert(name.to_string(), self.definitions.len());
+        self.definitions.push(vec![]);
+        self.references.push((line, column));
+        self.definitions.len() - 1
+    }
+
+    /// Replaces the upcoming token with its definition if it names a `#define`.
+    ///
+    /// Definitions may refer to other definitions, but a chain of replacements longer than the
+    /// number of definitions must repeat one, so it is an error.
+    fn expand_defines(&mut self) -> std::result::Result<(), ParseError> {
+        while let Some(next) = self.peek().cloned() {
+            let replacement = match next.token {
+                Token::Word(ref word) => match self.defines.get(word) {
+                    Some(replacement) => replacement,
+                    None => return Ok(()),
+                },
+                _ => return Ok(()),
+            };
+            if next.expansions >= self.defines.len() {
+                return Err(error_at(
+                    next.line,
+                    next.column,
+                    format!("{} is defined recursively", next.token),
+                ));
+            }
+            let replacement: Vec<Spanned> = replacement
+                .iter()
+                .map(|token| Spanned {
+                    expansions: next.expansions + 1,
+                    ..token.clone()
+                })
+                .collect();
+            let at = self.position;
+            self.tokens.splice(at..at + 1, replacement);
+        }
+        Ok(())
+    }
+
+    /// Parses the script and returns its top level actions.
+    fn parse(&mut self) -> std::result::Result<Vec<Action>, ParseError> {
+        let mut start = vec![];
+        loop {
+            self.expand_defines()?;
+            let next = match self.peek() {
+                Some(next) => next.clone(),
+                None => break,
+            };
+            match next.token {
+                Token::Word(ref word) if word == "#define" => self.define()?,
+                Token::Word(ref word) if word == "set" => self.set()?,
+                Token::Word(ref word) if word == "rule" => self.rule()?,
+                _ => start.push(self.action()?),
+            }
+        }
+        Ok(start)
+    }
+
+    fn define(&mut self) -> std::result::Result<(), ParseError> {
+        let define = self.next()?;
+        let (name, _, _) = self.word()?;
+        let mut replacement = vec![];
+        while self.peek().map(|t| t.line) == Some(define.line) {
+            replacement.push(self.next()?);
+        }
+        self.defines.insert(name, replacement);
+        Ok(())
+    }
+
+    fn set(&mut self) -> std::result::Result<(), ParseError> {
+        let set = self.next()?;
+        let (key, _, _) = self.word()?;
+        match key.as_str() {
+            "maxdepth" => self.config.max_depth = Some(self.count()? + 1),
+            "maxobjects" => self.config.max_meshes = Some(self.count()?),
+            "minsize" => self.config.min_size = Some(self.number()?),
+            "seed" => match self.peek().map(|t| t.token.clone()) {
+                Some(Token::Number(_)) => self.config.seed = Some(self.count()? as u64),
+                _ => {
+                    self.next()?;
+                }
+            },
+            // Skip the arguments of settings that do not apply to immense.
+            _ => {
+                while self.peek().map(|t| t.line) == Some(set.line) {
+                    self.next()?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rule(&mut self) -> std::result::Result<(), ParseError> {
+        self.next()?;
+        let (name, line, column) = self.word()?;
+        let name = self.name(&name, line, column);
+        let mut weight = 1.0;
+        let mut definition = Definition {
+            id: self.definition_count,
+            max_depth: None,
+            retirement: None,
+            actions: vec![],
+        };
+        self.definition_count += 1;
+        loop {
+            let next = self.next()?;
+            match next.token {
+                Token::OpenBrace => break,
+                Token::Word(ref word) if word == "weight" || word == "w" => {
+                    weight = self.weight()?;
+                }
+                Token::Word(ref word) if word == "maxdepth" || word == "md" => {
+                    definition.max_depth = Some(self.count()?);
+                    if self.peek().map(|t| &t.token) == Some(&Token::Greater) {
+                        self.next()?;
+                        let (retirement, line, column) = self.word()?;
+                        definition.retirement = Some(self.name(&retirement, line, column));
+                    }
+                }
+                token => {
+                    return Err(error_at(
+                        next.line,
+                        next.column,
+                        format!("expected a rule modifier or `{{`, found {}", token),
+                    ))
+                }
+            }
+        }
+        loop {
+            self.expand_defines()?;
+            match self.peek().map(|t| t.token.clone()) {
+                Some(Token::CloseBrace) => {
+                    self.next()?;
+                    break;
+                }
+                _ => definition.actions.push(self.action()?),
+            }
+        }
+        self.definitions[name].push((weight, definition));
+        Ok(())
+    }
+
+    fn action(&mut self) -> std::result::Result<Action, ParseError> {
+        let mut loops = vec![];
+        loop {
+            self.expand_defines()?;
+            let next = self.next()?;
+            match next.token {
+                Token::Number(_) => {
+                    self.position -= 1;
+                    let count = self.count()?;
+                    self.expect(Token::Star)?;
+                    self.expect(Token::OpenBrace)?;
+                    let transform = self.transform()?;
+                    let mut copies = vec![Transform::default()];
+                    for i in 1..count {
+                        copies.push(copies[i - 1].cons(transform));
+                    }
+                    copies.truncate(count);
+                    loops.push(copies);
+                }
+                Token::OpenBrace => loops.push(vec![self.transform()?]),
+                Token::Word(word) => {
+                    let target = self.target(&word, next.line, next.column)?;
+                    return Ok(Action { loops, target });
+                }
+                token => {
+                    return Err(error_at(
+                        next.line,
+                        next.column,
+                        format!("expected a transform, loop, or rule, found {}", token),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn target(
+        &mut self,
+        word: &str,
+        line: usize,
+        column: usize,
+    ) -> std::result::Result<Target, ParseError> {
+        // Primitives may be suffixed with a class for the renderer, e.g. `box::shiny`.
+        let primitive = word.split("::").next().unwrap_or(word);
+        match primitive {
+            "box" => Ok(Target::Primitive(Primitive::Box)),
+            "sphere" => Ok(Target::Primitive(Primitive::Sphere)),
+            "line" => Ok(Target::Primitive(Primitive::Line)),
+            "grid" => Ok(Target::Primitive(Primitive::Grid)),
+            "triangle" => {
+                let triangle = self.triangle()?;
+                self.triangles.push(Arc::new(triangle));
+                Ok(Target::Primitive(Primitive::Triangle(
+                    self.triangles.len() - 1,
+                )))
+            }
+            "point" | "mesh" | "cylinder" | "tube" => Err(error_at(
+                line,
+                column,
+                format!("unsupported primitive `{}`", primitive),
+            )),
+            _ => Ok(Target::Rule(self.name(word, line, column))),
+        }
+    }
+
+    /// Parses the corners of a triangle, as in `[0,0,0;1,0,0;0,1,0]`.
+    ///
+    /// Structure Synth places them in the space of its box, which spans 0 to 1 on each axis rather
+    /// than being centered on the origin.
+    fn triangle(&mut self) -> std::result::Result<Mesh, ParseError> {
+        self.expect(Token::Symbol('['))?;
+        let mut corners = vec![];
+        for separator in &[';', ';', ']'] {
+            let x = self.number()?;
+            self.expect(Token::Symbol(','))?;
+            let y = self.number()?;
+            self.expect(Token::Symbol(','))?;
+            let z = self.number()?;
+            self.expect(Token::Symbol(*separator))?;
+            corners.push(vertex(x - 0.5, y - 0.5, z - 0.5));
+        }
+        Ok(Mesh::new(corners, None, vec![vec![1, 2, 3]]))
+    }
+
+    /// Parses the contents of a transform block after its opening brace.
+    fn transform(&mut self) -> std::result::Result<Transform, ParseError> {
+        let mut transform = Transform::default();
+        loop {
+            self.expand_defines()?;
+            let next = self.next()?;
+            let operator = match next.token {
+                Token::CloseBrace => return Ok(transform),
+                Token::Word(word) => word,
+                token => {
+                    return Err(error_at(
+                        next.line,
+                        next.column,
+                        format!("expected a transform, found {}", token),
+                    ))
+                }
+            };
+            let step = match operator.as_str() {
+                "x" => Transform::tx(self.number()?),
+                "y" => Transform::ty(self.number()?),
+                "z" => Transform::tz(self.number()?),
+                "rx" => Transform::r_axis(Vertex::x(), self.number()?),
+                "ry" => Transform::r_axis(Vertex::y(), self.number()?),
+                "rz" => Transform::r_axis(Vertex::z(), self.number()?),
+                "s" => {
+                    let x = self.number()?;
+                    let is_number = |t: Option<&Spanned>| {
+                        matches!(
+                            t,
+                            Some(Spanned {
+                                token: Token::Number(_),
+                                ..
+                            })
+                        )
+                    };
+                    if is_number(self.tokens.get(self.position))
+                        && is_number(self.tokens.get(self.position + 1))
+                    {
+                        Transform::sby(x, self.number()?, self.number()?)
+                    } else {
+                        Transform::s(x)
+                    }
+                }
+                "fx" => Transform::fx(),
+                "fy" => Transform::fy(),
+                "fz" => Transform::fz(),
+                "m" => {
+                    let mut matrix = Matrix4::identity();
+                    for i in 0..9 {
+                        matrix[(i / 3, i % 3)] = self.number()?;
+                    }
+                    Transform::matrix(matrix)
+                }
+                "hue" | "h" => Transform::hue(self.number()?),
+                "sat" => Transform::saturation(self.number()?),
+                "b" | "brightness" => Transform::value(self.number()?),
+                "color" | "c" => {
+                    let (color, line, column) = self.word()?;
+                    Transform::color(parse_color(&color).ok_or_else(|| {
+                        error_at(line, column, format!("unknown color `{}`", color))
+                    })?)
+                }
+                _ => {
+                    return Err(error_at(
+                        next.line,
+                        next.column,
+                        format!("unsupported transform `{}`", operator),
+                    ))
+                }
+            };
+            transform = transform.cons(step);
+        }
+    }
+
+    /// Resolves the parsed rules into a program, failing if any rule is invoked but not defined.
+    fn finish(self, start: Vec<Action>) -> std::result::Result<Program, ParseError> {
+        let mut names: Vec<(&String, &usize)> = self.names.iter().collect();
+        names.sort_by_key(|(_, index)| **index);
+        for (name, index) in names {
+            if self.definitions[*index].is_empty() {
+                let (line, column) = self.references[*index];
+                return Err(error_at(line, column, format!("undefined rule `{}`", name)));
+            }
+        }
+        Ok(Program {
+            definitions: self.definitions,
+            definition_count: self.definition_count,
+            start,
+            sphere: sphere(SPHERE_RESOLUTION),
+            grid: Arc::new(primitives::grid()),
+            triangles: self.triangles,
+        })
+    }
+}
+
+fn parse_color(color: &str) -> Option<Hsv> {
+    let rgb = if let Some(hex) = color.strip_prefix('#') {
+        let digits: Vec<u8> = hex
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as u8))
+            .collect::<Option<_>>()?;
+        match digits.len() {
+            3 => Rgb::new(digits[0] * 17, digits[1] * 17, digits[2] * 17),
+            6 => Rgb::new(
+                digits[0] * 16 + digits[1],
+                digits[2] * 16 + digits[3],
+                digits[4] * 16 + digits[5],
+            ),
+            _ => return None,
+        }
+    } else {
+        named::from_str(&color.to_lowercase())?
+    };
+    let rgb: Rgb<Srgb, f32> = rgb.into_format();
+    Some(Hsv::from(rgb))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(source: &str) -> Vec<(f32, f32, f32)> {
+        let script = EisenScript::parse(source).expect("valid script");
+        let mut positions: Vec<(f32, f32, f32)> = script
+            .to_rule()
+            .generate_with(script.expansion_config())
+            .map(|output| {
+                let count = output.vertices().count() as f32;
+                output.vertices().fold((0.0, 0.0, 0.0), |(x, y, z), v| {
+                    (x + v.x / count, y + v.y / count, z + v.z / count)
+                })
+            })
+            .map(|(x, y, z)| (x.round(), y.round(), z.round()))
+            .collect();
+        positions.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        positions
+    }
+
+    fn error(source: &str) -> (usize, usize, String) {
+        match EisenScript::parse(source) {
+            Err(crate::Error::Parse(error)) => (error.line, error.column, error.message),
+            Err(error) => panic!("unexpected error {}", error),
+            Ok(_) => panic!("invalid script parsed"),
+        }
+    }
+
+    #[test]
+    fn loops_start_untransformed_and_nest() {
+        assert_eq!(
+            positions("2 * { x 2 } 2 * { y 3 } box"),
+            vec![
+                (0.0, 0.0, 0.0),
+                (0.0, 3.0, 0.0),
+                (2.0, 0.0, 0.0),
+                (2.0, 3.0, 0.0)
+            ]
+        );
+        assert_eq!(positions("{ x 2 } { z 1 } box"), vec![(2.0, 0.0, 1.0)]);
+    }
+
+    #[test]
+    fn rotations_turn_primitives_about_their_centre() {
+        assert_eq!(
+            positions("{ rx 180 } box { ry 180 } sphere { rz 90 } box { x 2 rz 90 } sphere"),
+            vec![
+                (0.0, 0.0, 0.0),
+                (0.0, 0.0, 0.0),
+                (0.0, 0.0, 0.0),
+                (2.0, 0.0, 0.0)
+            ]
+        );
+    }
+
+    #[test]
+    fn rule_max_depth_retires() {
+        let script = "
+            r1
+            rule r1 maxdepth 2 > r2 {
+                box
+                { x 1 } r1
+            }
+            rule r2 {
+                { y 5 } sphere
+            }
+        ";
+        assert_eq!(
+            positions(script),
+            vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (2.0, 5.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn settings_configure_expansion() {
+        let script = EisenScript::parse(
+            "set maxdepth 10 // comment
+            set maxobjects 5
+            /* block
+               comment */ set minsize 0.5
+            set seed 4
+            set background #fff
+            box",
+        )
+        .expect("valid script");
+        let config = script.expansion_config();
+        assert_eq!(config.max_depth, Some(11));
+        assert_eq!(config.max_meshes, Some(5));
+        assert_eq!(config.min_size, Some(0.5));
+        assert_eq!(config.seed, Some(4));
+    }
+
+    fn mesh_count(source: &str) -> usize {
+        let script = EisenScript::parse(source).expect("valid script");
+        script
+            .to_rule()
+            .generate_with(script.expansion_config())
+            .count()
+    }
+
+    #[test]
+    fn max_depth_matches_structure_synth() {
+        // Structure Synth draws one box for each of the `maxdepth` generations of this chain.
+        let chain = |max_depth| {
+            format!(
+                "set maxdepth {}\nR1\nrule R1 {{ box {{ x 1 }} R1 }}",
+                max_depth
+            )
+        };
+        assert_eq!(mesh_count(&chain(1)), 1);
+        assert_eq!(mesh_count(&chain(20)), 20);
+    }
+
+    #[test]
+    fn settings_end_at_their_arguments() {
+        assert_eq!(
+            mesh_count("set maxdepth 20 R1 rule R1 { box { x 1 s 0.9 } R1 }"),
+            20
+        );
+        assert_eq!(
+            mesh_count("set maxobjects 3 set seed 4 10 * { x 1 } box"),
+            3
+        );
+    }
+
+    #[test]
+    fn defines_are_substituted() {
+        assert_eq!(
+            positions("#define steps 3\nsteps * { x 1 } box"),
+            vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0), (2.0, 0.0, 0.0)]
+        );
+    }
+
+    #[test]
+    fn reflections_and_matrices_transform() {
+        assert_eq!(positions("{ fx x 2 } box"), vec![(-2.0, 0.0, 0.0)]);
+        assert_eq!(positions("{ x 1 fz z 2 } box"), vec![(1.0, 0.0, -2.0)]);
+        assert_eq!(
+            positions("{ m 0 -1 0 1 0 0 0 0 2 } { x 3 z 1 } box"),
+            vec![(0.0, 3.0, 2.0)]
+        );
+        let script = EisenScript::parse("{ fz } box { fx fy } box").expect("valid script");
+        let mirrored: Vec<bool> = script.to_rule().generate().map(|m| m.mirrored()).collect();
+        assert_eq!(mirrored.iter().filter(|m| **m).count(), 1);
+    }
+
+    #[test]
+    fn colors_parse() {
+        let hsv = parse_color("#ff0000").expect("hex color");
+        assert_eq!(hsv.hue.to_positive_degrees(), 0.0);
+        assert!(parse_color("#0f0").is_some());
+        assert!(parse_color("White").is_some());
+        assert!(parse_color("#ff00").is_none());
+    }
+
+    #[test]
+    fn errors_have_positions() {
+        assert_eq!(
+            error("box\n  { x 1 blend } box"),
+            (2, 9, String::from("unsupported transform `blend`"))
+        );
+        assert_eq!(
+            error("{ x 1 } missing"),
+            (1, 9, String::from("undefined rule `missing`"))
+        );
+        assert_eq!(
+            error("rule a {\n box"),
+            (2, 2, String::from("unexpected end of script"))
+        );
+        assert_eq!(
+            error("3 * x 1 box"),
+            (1, 5, String::from("expected `{`, found `x`"))
+        );
+        assert_eq!(
+            error("a\nrule a w 0 { box }"),
+            (2, 10, String::from("expected a positive weight, found 0"))
+        );
+        assert_eq!(
+            error("a\nrule a weight -2 { box }"),
+            (2, 15, String::from("expected a positive weight, found -2"))
+        );
+    }
+
+    #[test]
+    fn triangles_and_grids_are_primitives() {
+        let script = EisenScript::parse("{ x 1 } triangle[0,0,0;1,0,0;0,1,-1] grid::wire")
+            .expect("valid script");
+        let meshes: Vec<crate::rule::OutputMesh> = script.to_rule().generate().collect();
+        assert_eq!(meshes.len(), 2);
+        let (triangle, grid) = match meshes[0].faces().count() {
+            1 => (&meshes[0], &meshes[1]),
+            _ => (&meshes[1], &meshes[0]),
+        };
+        let corners: Vec<(f32, f32, f32)> = triangle.vertices().map(|v| (v.x, v.y, v.z)).collect();
+        assert_eq!(
+            corners,
+            vec![(0.5, -0.5, -0.5), (1.5, -0.5, -0.5), (0.5, 0.5, -1.5)]
+        );
+        assert_eq!(grid.faces().count(), 0);
+        assert_eq!(grid.lines().count(), 12);
+
+        assert_eq!(
+            error("triangle box"),
+            (1, 10, String::from("expected `[`, found `box`"))
+        );
+        assert_eq!(
+            error("triangle[0,0,0;1,0,0]"),
+            (1, 21, String::from("expected `;`, found `]`"))
+        );
+    }
+
+    #[test]
+    fn recursive_defines_are_errors() {
+        assert_eq!(
+            error("#define a a\na box"),
+            (1, 11, String::from("`a` is defined recursively"))
+        );
+        assert_eq!(
+            error("#define a b\n#define b a\na box"),
+            (2, 11, String::from("`a` is defined recursively"))
+        );
+        assert_eq!(
+            error("#define a box a\na"),
+            (1, 15, String::from("`a` is defined recursively"))
+        );
+        assert_eq!(
+            positions("#define a b\n#define b 2\na * { x 1 } box"),
+            vec![(0.0, 0.0, 0.0), (1.0, 0.0, 0.0)]
+        );
+    }
+}
@@ -12,8 +12,11 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use auto_from::auto_from;
+use crate::description::DescriptionError;
+use crate::eisenscript::ParseError;
 use crate::export::ExportError;
+use crate::import::ImportError;
+use auto_from::auto_from;
 use failure_derive::Fail;
 use std;
 
@@ -25,4 +28,10 @@ pub type Result<T> = std::result::Result<T, Error>;
 pub enum Error {
     #[fail(display = "Error exporting mesh.")]
     Export(ExportError),
+    #[fail(display = "Error parsing script: {}", _0)]
+    Parse(ParseError),
+    #[fail(display = "Error importing mesh: {}", _0)]
+    Import(ImportError),
+    #[fail(display = "Error compiling rule description: {}", _0)]
+    Description(DescriptionError),
 }
@@ -12,11 +12,14 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+use crate::mesh::Vertex;
 use crate::rule::OutputMesh;
 use failure_derive::Fail;
+use palette::{encoding::srgb::Srgb, rgb::Rgb};
 use std::fs::File;
 use std::io;
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Fail, Debug)]
 pub enum ExportError {
     #[fail(display = "Failed to write to obj file.")]
@@ -29,6 +32,21 @@ pub enum ExportError {
         #[cause]
         write_error: io::Error,
     },
+    #[fail(display = "Failed to write to gltf file.")]
+    GltfWriteError {
+        #[cause]
+        write_error: io::Error,
+    },
+    #[fail(display = "Failed to write to gltf buffer file.")]
+    BufferWriteError {
+        #[cause]
+        write_error: io::Error,
+    },
+    #[fail(display = "Failed to write to stl file.")]
+    StlWriteError {
+        #[cause]
+        write_error: io::Error,
+    },
 }
 
 macro_rules! try_write_obj {
@@ -55,6 +73,48 @@ macro_rules! try_write_mtl {
     };
 }
 
+macro_rules! try_write_stl {
+    ($expr:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return Err(ExportError::StlWriteError { write_error: err }),
+        }
+    };
+    ($expr:expr,) => {
+        try!($expr)
+    };
+}
+
+macro_rules! try_write_gltf {
+    ($expr:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return Err(ExportError::GltfWriteError { write_error: err }),
+        }
+    };
+    ($expr:expr,) => {
+        try!($expr)
+    };
+}
+
+macro_rules! try_write_buffer {
+    ($expr:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return Err(ExportError::BufferWriteError { write_error: err }),
+        }
+    };
+    ($expr:expr,) => {
+        try!($expr)
+    };
+}
+
+mod gltf;
+mod stl;
+
+pub use self::gltf::{write_gltf, GltfConfig};
+pub use self::stl::{write_stl, StlFormat};
+
 /// A policy for grouping meshes in the object file.
 ///
 /// Use this to specify how you want to work with your meshes later. E.g. if you want to use Blender
@@ -103,37 +163,38 @@ pub fn write_meshes(
     } else {
         None
     };
-    let mut vertex_offset = 0;
-    let mut normal_offset = 0;
+    let mut offsets = ObjOffsets::default();
     for mesh in meshes {
         let vertex_count = mesh.mesh().vertices().len();
         let normal_count = mesh.mesh().normals().map(|ns| ns.len()).unwrap_or(0);
-        render_obj(
-            &config,
-            mesh,
-            vertex_offset,
-            normal_offset,
-            &mut sink,
-            mtl_file.as_mut(),
-        )?;
-        normal_offset += normal_count;
-        vertex_offset += vertex_count;
+        let uv_count = mesh.uvs().map(|uvs| uvs.len()).unwrap_or(0);
+        render_obj(&config, mesh, offsets, &mut sink, mtl_file.as_mut())?;
+        offsets.vertex += vertex_count;
+        offsets.normal += normal_count;
+        offsets.uv += uv_count;
     }
     Ok(())
 }
 
+/// The number of each kind of element written to the object file before a mesh.
+#[derive(Copy, Clone, Default)]
+struct ObjOffsets {
+    vertex: usize,
+    normal: usize,
+    uv: usize,
+}
+
 fn render_obj(
     config: &ExportConfig,
     output_mesh: OutputMesh,
-    vertex_offset: usize,
-    normal_offset: usize,
+    offsets: ObjOffsets,
     mut sink: impl io::Write,
     material_sink: Option<impl io::Write>,
 ) -> Result<(), ExportError> {
     let color = output_mesh.color();
-    let color_hex = format!("#{:x}", color.into_format::<u8>());
+    let color_hex = color_hex(color);
     match config.grouping {
-        MeshGrouping::Individual => try_write_obj!(write!(&mut sink, "g g{}\n", vertex_offset)),
+        MeshGrouping::Individual => try_write_obj!(write!(&mut sink, "g g{}\n", offsets.vertex)),
         MeshGrouping::ByColor => try_write_obj!(write!(&mut sink, "g {}\n", color_hex)),
         _ => (),
     };
@@ -163,26 +224,122 @@ fn render_obj(
         }
     }
 
-    let write_face_vertex = |sink: &mut io::Write, vertex_index| -> Result<(), ExportError> {
-        if let Some(_) = output_mesh.normals() {
-            try_write_mtl!(write!(
-                sink,
-                " {}//{}",
-                vertex_index + vertex_offset,
-                vertex_index + normal_offset
-            ));
-        } else {
-            try_write_mtl!(write!(sink, " {}", vertex_index + vertex_offset));
+    if let Some(uvs) = output_mesh.uvs() {
+        for uv in uvs {
+            try_write_obj!(write!(&mut sink, "vt {} {}\n", uv[0], uv[1]));
+        }
+    }
+
+    let has_normals = output_mesh.normals().is_some();
+    let has_uvs = output_mesh.uvs().is_some();
+    let write_face_vertex = |sink: &mut dyn io::Write, vertex_index| -> Result<(), ExportError> {
+        let vertex = vertex_index + offsets.vertex;
+        let normal = vertex_index + offsets.normal;
+        let uv = vertex_index + offsets.uv;
+        match (has_uvs, has_normals) {
+            (true, true) => try_write_obj!(write!(sink, " {}/{}/{}", vertex, uv, normal)),
+            (true, false) => try_write_obj!(write!(sink, " {}/{}", vertex, uv)),
+            (false, true) => try_write_obj!(write!(sink, " {}//{}", vertex, normal)),
+            (false, false) => try_write_obj!(write!(sink, " {}", vertex)),
         };
         Ok(())
     };
 
+    let mirrored = output_mesh.mirrored();
     for face in output_mesh.faces() {
         try_write_obj!(write!(&mut sink, "f "));
-        for vertex_index in face {
+        for vertex_index in wind(face, mirrored) {
             write_face_vertex(&mut sink, vertex_index)?;
         }
         try_write_obj!(write!(&mut sink, "\n"));
     }
+
+    for line in output_mesh.lines() {
+        try_write_obj!(write!(&mut sink, "l"));
+        for vertex_index in line {
+            try_write_obj!(write!(&mut sink, " {}", vertex_index + offsets.vertex));
+        }
+        try_write_obj!(write!(&mut sink, "\n"));
+    }
     Ok(())
 }
+
+/// Returns the name for a color used in materials and groups.
+pub(crate) fn color_hex(color: Rgb<Srgb, f32>) -> String {
+    format!("#{:x}", color.into_format::<u8>())
+}
+
+/// Iterates over the vertices of a face, in reverse if the face belongs to a mirrored mesh so
+/// that it still faces out.
+pub(crate) fn wind<'a>(face: &'a [usize], mirrored: bool) -> impl Iterator<Item = usize> + 'a {
+    (0..face.len()).map(move |i| {
+        if mirrored {
+            face[face.len() - 1 - i]
+        } else {
+            face[i]
+        }
+    })
+}
+
+/// Splits a face into a fan of triangles sharing its first vertex, reversing their winding if the
+/// face belongs to a mirrored mesh.
+pub(crate) fn triangulate<'a>(
+    face: &'a [usize],
+    mirrored: bool,
+) -> impl Iterator<Item = [usize; 3]> + 'a {
+    (1..face.len().saturating_sub(1)).map(move |i| {
+        if mirrored {
+            [face[0], face[i + 1], face[i]]
+        } else {
+            [face[0], face[i], face[i + 1]]
+        }
+    })
+}
+
+/// Returns the unit normal of the triangle wound counterclockwise through `a`, `b`, and `c`, or a
+/// zero vector if the triangle is degenerate.
+pub(crate) fn facet_normal(a: Vertex, b: Vertex, c: Vertex) -> [f32; 3] {
+    let (u, v) = (b - a, c - a);
+    let normal = [
+        u.y * v.z - u.z * v.y,
+        u.z * v.x - u.x * v.z,
+        u.x * v.y - u.y * v.x,
+    ];
+    let length = normal.iter().map(|n| n * n).sum::<f32>().sqrt();
+    if length > 0.0 {
+        [normal[0] / length, normal[1] / length, normal[2] / length]
+    } else {
+        [0.0, 0.0, 0.0]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::vertex;
+    use crate::rule::{line, polyline, triangle, Rule, Tf};
+
+    #[test]
+    fn lines_are_written_after_earlier_vertices() {
+        let rule = Rule::new()
+            .push(Tf::tx(2.0), line())
+            .push(None, triangle())
+            .push(
+                None,
+                polyline(vec![
+                    vertex(0.0, 0.0, 0.0),
+                    vertex(1.0, 0.0, 0.0),
+                    vertex(1.0, 1.0, 0.0),
+                ]),
+            );
+        let mut obj = vec![];
+        write_meshes(ExportConfig::default(), rule.generate(), &mut obj).expect("writing to a vec");
+        let obj = String::from_utf8(obj).expect("obj is utf8");
+        let records: Vec<&str> = obj
+            .lines()
+            .filter(|record| !record.starts_with('v'))
+            .collect();
+        assert_eq!(records, vec!["l 1 2 3", "f  4//1 5//2 6//3", "l 7 8"]);
+        assert!(obj.ends_with("v 1.5 0 0\nv 2.5 0 0\nl 7 8\n"));
+    }
+}
@@ -0,0 +1,853 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::export::{color_hex, triangulate, ExportError, MeshGrouping};
+use crate::mesh::{PrimitiveMesh, Vertex};
+use crate::rule::{OutputMesh, OutputMeshSource, Transform};
+use palette::{encoding::srgb::Srgb, rgb::Rgb};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+const FLOAT: u32 = 5126;
+const UNSIGNED_INT: u32 = 5125;
+const ARRAY_BUFFER: u32 = 34962;
+const ELEMENT_ARRAY_BUFFER: u32 = 34963;
+const TRIANGLES: u32 = 4;
+
+const INSTANCING_EXTENSION: &str = "EXT_mesh_gpu_instancing";
+
+const GLB_MAGIC: &[u8; 4] = b"glTF";
+const GLB_JSON_CHUNK: u32 = 0x4E4F_534A;
+const GLB_BIN_CHUNK: u32 = 0x004E_4942;
+
+/// Configuration for glTF 2.0 output.
+#[derive(Clone, Debug, Default)]
+pub struct GltfConfig {
+    /// Mesh grouping policy. Each group becomes a glTF node with its own mesh.
+    pub grouping: MeshGrouping,
+    /// Binary buffer file to write vertex data to.
+    ///
+    /// If set, the sink receives a glTF JSON document which references the buffer file by its file
+    /// name, so the buffer file should be kept in the same directory as the document. If not, the
+    /// sink receives a single self-contained binary glTF (.glb) file.
+    pub buffer_file: Option<String>,
+    /// Write the geometry of each builtin primitive and each custom [Mesh][crate::mesh::Mesh] only
+    /// once, and place every invocation of it with the `EXT_mesh_gpu_instancing` extension.
+    ///
+    /// Each instance's transform is stored as a translation, rotation, and scale. Meshes whose
+    /// transforms shear are written out in world space on their own, like meshes under a
+    /// [Deformer][crate::rule::Deformer]. The grouping policy does not apply to instanced output;
+    /// there is one node for each mesh and color.
+    pub instanced: bool,
+}
+
+/// Writes out meshes as a glTF 2.0 asset to the given [Write][io::Write] sink.
+///
+/// Each distinct color becomes a PBR material. Faces are triangulated.
+pub fn write_gltf(
+    config: GltfConfig,
+    meshes: impl Iterator<Item = OutputMesh>,
+    mut sink: impl io::Write,
+) -> Result<(), ExportError> {
+    // Readers resolve the buffer's URI relative to the document, which sits beside the buffer.
+    let buffer_uri =
+        config
+            .buffer_file
+            .as_ref()
+            .map(|buffer_file| match Path::new(buffer_file).file_name() {
+                Some(file_name) => file_name.to_string_lossy().into_owned(),
+                None => buffer_file.clone(),
+            });
+    let buffer_uri = buffer_uri.as_deref();
+    let (document, buffer) = if config.instanced {
+        let mut scene = InstancedScene::default();
+        for mesh in meshes {
+            scene.add(mesh);
+        }
+        scene.encode(buffer_uri)
+    } else {
+        let mut scene = Scene::default();
+        for (i, mesh) in meshes.enumerate() {
+            scene.add(config.grouping, i, mesh);
+        }
+        scene.encode(buffer_uri)
+    };
+    if let Some(ref buffer_filename) = config.buffer_file {
+        let mut buffer_file = try_write_buffer!(File::create(buffer_filename));
+        try_write_buffer!(buffer_file.write_all(&buffer));
+        try_write_gltf!(sink.write_all(document.to_string().as_bytes()));
+    } else {
+        write_glb(&document, &buffer, sink)?;
+    }
+    Ok(())
+}
+
+/// Writes a binary glTF container holding the JSON document and its single buffer.
+fn write_glb(document: &Value, buffer: &[u8], mut sink: impl io::Write) -> Result<(), ExportError> {
+    let mut json = document.to_string().into_bytes();
+    pad(&mut json, b' ');
+    let mut chunks = vec![(GLB_JSON_CHUNK, json)];
+    if !buffer.is_empty() {
+        let mut bin = buffer.to_vec();
+        pad(&mut bin, 0);
+        chunks.push((GLB_BIN_CHUNK, bin));
+    }
+    let length = 12
+        + chunks
+            .iter()
+            .map(|(_, chunk)| 8 + chunk.len())
+            .sum::<usize>();
+
+    try_write_gltf!(sink.write_all(GLB_MAGIC));
+    try_write_gltf!(sink.write_all(&2u32.to_le_bytes()));
+    try_write_gltf!(sink.write_all(&(length as u32).to_le_bytes()));
+    for (chunk_type, chunk) in &chunks {
+        try_write_gltf!(sink.write_all(&(chunk.len() as u32).to_le_bytes()));
+        try_write_gltf!(sink.write_all(&chunk_type.to_le_bytes()));
+        try_write_gltf!(sink.write_all(chunk));
+    }
+    Ok(())
+}
+
+fn pad(bytes: &mut Vec<u8>, filler: u8) {
+    while !bytes.len().is_multiple_of(4) {
+        bytes.push(filler);
+    }
+}
+
+/// Returns the glTF material for a color.
+fn material(color: Rgb<Srgb, f32>) -> Value {
+    let linear = color.into_linear();
+    json!({
+        "name": color_hex(color),
+        "pbrMetallicRoughness": {
+            "baseColorFactor": [linear.red, linear.green, linear.blue, 1.0],
+            "metallicFactor": 0.0,
+            "roughnessFactor": 1.0,
+        },
+    })
+}
+
+/// Accumulates the binary buffer and the views and accessors into it.
+#[derive(Default)]
+struct BufferBuilder {
+    bytes: Vec<u8>,
+    buffer_views: Vec<Value>,
+    accessors: Vec<Value>,
+}
+
+impl BufferBuilder {
+    /// Appends float vectors to the buffer and returns the index of their accessor.
+    fn push_vectors(&mut self, vectors: &[[f32; 3]], target: Option<u32>) -> usize {
+        self.push_floats(vectors, "VEC3", target)
+    }
+
+    /// Appends float vectors of any width to the buffer and returns the index of their accessor.
+    fn push_floats<V: AsRef<[f32]>>(
+        &mut self,
+        vectors: &[V],
+        kind: &str,
+        target: Option<u32>,
+    ) -> usize {
+        let width = vectors.first().map(|v| v.as_ref().len()).unwrap_or(0);
+        let mut min = vec![f32::INFINITY; width];
+        let mut max = vec![f32::NEG_INFINITY; width];
+        let view = self.begin_view();
+        for vector in vectors {
+            for (i, component) in vector.as_ref().iter().enumerate() {
+                min[i] = min[i].min(*component);
+                max[i] = max[i].max(*component);
+                self.bytes.extend_from_slice(&component.to_le_bytes());
+            }
+        }
+        self.end_view(view, target);
+        self.accessors.push(json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": FLOAT,
+            "count": vectors.len(),
+            "type": kind,
+            "min": min,
+            "max": max,
+        }));
+        self.accessors.len() - 1
+    }
+
+    /// Appends triangle indices to the buffer and returns the index of their accessor.
+    fn push_indices(&mut self, indices: &[u32]) -> usize {
+        let view = self.begin_view();
+        for index in indices {
+            self.bytes.extend_from_slice(&index.to_le_bytes());
+        }
+        self.end_view(view, Some(ELEMENT_ARRAY_BUFFER));
+        self.accessors.push(json!({
+            "bufferView": self.buffer_views.len() - 1,
+            "componentType": UNSIGNED_INT,
+            "count": indices.len(),
+            "type": "SCALAR",
+        }));
+        self.accessors.len() - 1
+    }
+
+    fn begin_view(&mut self) -> usize {
+        pad(&mut self.bytes, 0);
+        self.bytes.len()
+    }
+
+    fn end_view(&mut self, offset: usize, target: Option<u32>) {
+        let mut view = json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": self.bytes.len() - offset,
+        });
+        if let Some(target) = target {
+            view["target"] = json!(target);
+        }
+        self.buffer_views.push(view);
+    }
+
+    /// Returns the document's buffers, which hold only this one if it is not empty.
+    fn buffers(&self, uri: Option<&str>) -> Value {
+        if self.bytes.is_empty() {
+            return json!([]);
+        }
+        let mut buffer = json!({ "byteLength": self.bytes.len() });
+        if let Some(uri) = uri {
+            buffer["uri"] = json!(uri);
+        }
+        json!([buffer])
+    }
+}
+
+/// Triangles of one material, with normals either on all vertices or on none.
+struct Primitive {
+    material: usize,
+    positions: Vec<[f32; 3]>,
+    normals: Option<Vec<[f32; 3]>>,
+    indices: Vec<u32>,
+}
+
+impl Primitive {
+    fn add(&mut self, mesh: &OutputMesh) {
+        let base = self.positions.len() as u32;
+        self.positions
+            .extend(mesh.vertices().map(|v| [v.x, v.y, v.z]));
+        if let (Some(normals), Some(mesh_normals)) = (self.normals.as_mut(), mesh.normals()) {
+            normals.extend(mesh_normals.map(|n| [n.x, n.y, n.z]));
+        }
+        let mirrored = mesh.mirrored();
+        for face in mesh.faces() {
+            for triangle in triangulate(face, mirrored) {
+                self.indices
+                    .extend(triangle.iter().map(|i| base + *i as u32 - 1));
+            }
+        }
+    }
+}
+
+/// A glTF node and its mesh.
+struct Group {
+    name: String,
+    primitives: Vec<Primitive>,
+}
+
+/// The materials of the document, one for each distinct color.
+#[derive(Default)]
+struct Materials {
+    materials: Vec<Value>,
+    indices: HashMap<String, usize>,
+}
+
+impl Materials {
+    fn index(&mut self, color: Rgb<Srgb, f32>) -> usize {
+        let color_name = color_hex(color);
+        match self.indices.get(&color_name) {
+            Some(index) => *index,
+            None => {
+                self.materials.push(material(color));
+                self.indices.insert(color_name, self.materials.len() - 1);
+                self.materials.len() - 1
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Scene {
+    materials: Materials,
+    groups: Vec<Group>,
+    group_indices: HashMap<String, usize>,
+}
+
+impl Scene {
+    fn add(&mut self, grouping: MeshGrouping, index: usize, mesh: OutputMesh) {
+        let color = mesh.color();
+        let color_name = color_hex(color);
+        let material = self.materials.index(color);
+        let group_name = match grouping {
+            MeshGrouping::AllTogether => String::from("immense"),
+            MeshGrouping::Individual => format!("g{}", index),
+            MeshGrouping::ByColor => color_name,
+        };
+        let group = match self.group_indices.get(&group_name) {
+            Some(group) => *group,
+            None => {
+                self.groups.push(Group {
+                    name: group_name.clone(),
+                    primitives: vec![],
+                });
+                self.group_indices.insert(group_name, self.groups.len() - 1);
+                self.groups.len() - 1
+            }
+        };
+        let has_normals = mesh.normals().is_some();
+        let primitives = &mut self.groups[group].primitives;
+        let primitive = match primitives
+            .iter()
+            .position(|p| p.material == material && p.normals.is_some() == has_normals)
+        {
+            Some(primitive) => primitive,
+            None => {
+                primitives.push(Primitive {
+                    material,
+                    positions: vec![],
+                    normals: if has_normals { Some(vec![]) } else { None },
+                    indices: vec![],
+                });
+                primitives.len() - 1
+            }
+        };
+        primitives[primitive].add(&mesh);
+    }
+
+    fn encode(self, buffer_uri: Option<&str>) -> (Value, Vec<u8>) {
+        let mut buffer = BufferBuilder::default();
+        let mut meshes = vec![];
+        let mut nodes = vec![];
+        for group in self.groups {
+            let mut primitives = vec![];
+            for primitive in group.primitives {
+                if primitive.indices.is_empty() {
+                    continue;
+                }
+                let mut attributes = json!({
+                    "POSITION": buffer.push_vectors(&primitive.positions, Some(ARRAY_BUFFER)),
+                });
+                if let Some(ref normals) = primitive.normals {
+                    attributes["NORMAL"] = json!(buffer.push_vectors(normals, Some(ARRAY_BUFFER)));
+                }
+                primitives.push(json!({
+                    "attributes": attributes,
+                    "indices": buffer.push_indices(&primitive.indices),
+                    "material": primitive.material,
+                    "mode": TRIANGLES,
+                }));
+            }
+            if primitives.is_empty() {
+                continue;
+            }
+            meshes.push(json!({ "name": group.name, "primitives": primitives }));
+            nodes.push(json!({ "name": group.name, "mesh": meshes.len() - 1 }));
+        }
+        document(nodes, meshes, self.materials, buffer, buffer_uri)
+    }
+}
+
+/// The geometry of a mesh source, written once and shared by all its instances.
+struct Geometry {
+    attributes: Value,
+    indices: usize,
+}
+
+/// All the instances of one mesh source in one color.
+struct Batch {
+    source: usize,
+    material: usize,
+    /// Whether the instances are mirrored, and so need the source's faces reversed.
+    mirrored: bool,
+    instances: Vec<Transform>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+enum SourceKey {
+    Primitive(PrimitiveMesh),
+    Dynamic(usize),
+    /// A mesh written out in world space, which is never shared, by its index in the sources.
+    Baked(usize),
+}
+
+impl SourceKey {
+    fn of(source: &OutputMeshSource) -> Self {
+        match source {
+            OutputMeshSource::Primitive(primitive) => SourceKey::Primitive(primitive.clone()),
+            OutputMeshSource::Dynamic(mesh) => SourceKey::Dynamic(Arc::as_ptr(mesh) as usize),
+        }
+    }
+}
+
+#[derive(Default)]
+struct InstancedScene {
+    materials: Materials,
+    // The first output of each source is kept to read its geometry and, for dynamic meshes, to
+    // keep the pointer its key is derived from alive. Baked sources are flagged to be read in
+    // world space.
+    sources: Vec<(OutputMesh, bool)>,
+    source_indices: HashMap<SourceKey, usize>,
+    batches: Vec<Batch>,
+    batch_indices: HashMap<(usize, usize, bool), usize>,
+}
+
+impl InstancedScene {
+    fn add(&mut self, mesh: OutputMesh) {
+        let material = self.materials.index(mesh.color());
+        let mirrored = mesh.mirrored();
+        // Meshes which cannot be placed by a translation, rotation, and scale are written out in
+        // world space as their own source, with one untransformed instance.
+        let baked = mesh.deformed() || !mesh.transform().decomposes();
+        let (transform, key) = if baked {
+            (Transform::default(), SourceKey::Baked(self.sources.len()))
+        } else {
+            (mesh.transform(), SourceKey::of(mesh.source()))
+        };
+        let source = match self.source_indices.get(&key) {
+            Some(source) => *source,
+            None => {
+                self.sources.push((mesh, baked));
+                self.source_indices.insert(key, self.sources.len() - 1);
+                self.sources.len() - 1
+            }
+        };
+        let batch = match self.batch_indices.get(&(source, material, mirrored)) {
+            Some(batch) => *batch,
+            None => {
+                self.batches.push(Batch {
+                    source,
+                    material,
+                    mirrored,
+                    instances: vec![],
+                });
+                self.batch_indices
+                    .insert((source, material, mirrored), self.batches.len() - 1);
+                self.batches.len() - 1
+            }
+        };
+        self.batches[batch].instances.push(transform);
+    }
+
+    fn encode(self, buffer_uri: Option<&str>) -> (Value, Vec<u8>) {
+        let mut buffer = BufferBuilder::default();
+        // Geometry is encoded once for each source and winding that instances use.
+        let mut geometries: HashMap<(usize, bool), Option<Geometry>> = HashMap::new();
+        let mut meshes = vec![];
+        let mut nodes = vec![];
+        for batch in self.batches {
+            let (source, baked) = &self.sources[batch.source];
+            let geometry = geometries
+                .entry((batch.source, batch.mirrored))
+                .or_insert_with(|| {
+                    Self::encode_geometry(source, *baked, batch.mirrored, &mut buffer)
+                });
+            let geometry = match geometry {
+                Some(ref geometry) => geometry,
+                None => continue,
+            };
+            meshes.push(json!({
+                "primitives": [{
+                    "attributes": geometry.attributes,
+                    "indices": geometry.indices,
+                    "material": batch.material,
+                    "mode": TRIANGLES,
+                }],
+            }));
+            let (mut translations, mut rotations, mut scales) = (vec![], vec![], vec![]);
+            for instance in batch.instances {
+                let (translation, rotation, scale) = instance.decompose();
+                translations.push([translation.x, translation.y, translation.z]);
+                let rotation = rotation.quaternion().coords;
+                rotations.push([rotation.x, rotation.y, rotation.z, rotation.w]);
+                scales.push([scale.x, scale.y, scale.z]);
+            }
+            nodes.push(json!({
+                "mesh": meshes.len() - 1,
+                "extensions": {
+                    INSTANCING_EXTENSION: {
+                        "attributes": {
+                            "TRANSLATION": buffer.push_vectors(&translations, None),
+                            "ROTATION": buffer.push_floats(&rotations, "VEC4", None),
+                            "SCALE": buffer.push_vectors(&scales, None),
+                        },
+                    },
+                },
+            }));
+        }
+        let (mut document, bytes) = document(nodes, meshes, self.materials, buffer, buffer_uri);
+        document["extensionsUsed"] = json!([INSTANCING_EXTENSION]);
+        document["extensionsRequired"] = json!([INSTANCING_EXTENSION]);
+        (document, bytes)
+    }
+
+    fn encode_geometry(
+        source: &OutputMesh,
+        baked: bool,
+        mirrored: bool,
+        buffer: &mut BufferBuilder,
+    ) -> Option<Geometry> {
+        let indices: Vec<u32> = source
+            .faces()
+            .flat_map(|face| triangulate(face, mirrored))
+            .flat_map(|triangle| triangle.to_vec())
+            .map(|i| i as u32 - 1)
+            .collect();
+        if indices.is_empty() {
+            return None;
+        }
+        let to_array = |v: &Vertex| [v.x, v.y, v.z];
+        let (positions, normals): (Vec<[f32; 3]>, Option<Vec<[f32; 3]>>) = if baked {
+            (
+                source.vertices().map(|v| to_array(&v)).collect(),
+                source
+                    .normals()
+                    .map(|normals| normals.map(|n| to_array(&n)).collect()),
+            )
+        } else {
+            let mesh = source.mesh();
+            (
+                mesh.vertices().iter().map(to_array).collect(),
+                mesh.normals()
+                    .map(|normals| normals.iter().map(to_array).collect()),
+            )
+        };
+        let mut attributes = json!({
+            "POSITION": buffer.push_vectors(&positions, Some(ARRAY_BUFFER)),
+        });
+        if let Some(normals) = normals {
+            attributes["NORMAL"] = json!(buffer.push_vectors(&normals, Some(ARRAY_BUFFER)));
+        }
+        Some(Geometry {
+            attributes,
+            indices: buffer.push_indices(&indices),
+        })
+    }
+}
+
+/// Assembles the document with a scene of all the given nodes.
+fn document(
+    nodes: Vec<Value>,
+    meshes: Vec<Value>,
+    materials: Materials,
+    buffer: BufferBuilder,
+    buffer_uri: Option<&str>,
+) -> (Value, Vec<u8>) {
+    let buffers = buffer.buffers(buffer_uri);
+    let document = json!({
+        "asset": { "version": "2.0", "generator": "immense" },
+        "scene": 0,
+        "scenes": [{ "nodes": (0..nodes.len()).collect::<Vec<_>>() }],
+        "nodes": nodes,
+        "meshes": meshes,
+        "materials": materials.materials,
+        "accessors": buffer.accessors,
+        "bufferViews": buffer.buffer_views,
+        "buffers": buffers,
+    });
+    (document, buffer.bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{cube, cylinder, disc, torus, Replicate, Rule, Tf};
+    use nalgebra::{Matrix4, Quaternion, UnitQuaternion, Vector3};
+
+    fn colored_cubes() -> impl Iterator<Item = OutputMesh> {
+        Rule::new()
+            .push(Replicate::n(3, Tf::ty(1.1)), cube())
+            .push(Tf::hue(120.0), cube())
+            .generate()
+    }
+
+    /// Splits a binary glTF file into its JSON document and binary chunk.
+    fn read_glb(glb: &[u8]) -> (Value, Vec<u8>) {
+        let word = |offset: usize| {
+            let mut bytes = [0u8; 4];
+            bytes.copy_from_slice(&glb[offset..offset + 4]);
+            u32::from_le_bytes(bytes) as usize
+        };
+        assert_eq!(&glb[..4], GLB_MAGIC);
+        assert_eq!(word(4), 2);
+        assert_eq!(word(8), glb.len());
+
+        let json_length = word(12);
+        assert_eq!(word(16), GLB_JSON_CHUNK as usize);
+        assert_eq!(json_length % 4, 0);
+        let document = serde_json::from_slice(&glb[20..20 + json_length]).expect("valid json");
+
+        let bin = 20 + json_length;
+        let bin_length = word(bin);
+        assert_eq!(word(bin + 4), GLB_BIN_CHUNK as usize);
+        assert_eq!(bin_length % 4, 0);
+        assert_eq!(bin + 8 + bin_length, glb.len());
+        (document, glb[bin + 8..].to_vec())
+    }
+
+    /// Checks that every accessor fits the buffer view it reads from.
+    fn assert_accessors_fit(document: &Value, buffer_length: usize) {
+        let views = document["bufferViews"].as_array().expect("buffer views");
+        for accessor in document["accessors"].as_array().expect("accessors") {
+            let width = match accessor["type"].as_str() {
+                Some("SCALAR") => 1,
+                Some("VEC3") => 3,
+                Some("VEC4") => 4,
+                kind => panic!("unexpected accessor type {:?}", kind),
+            };
+            let count = accessor["count"].as_u64().expect("count") as usize;
+            let view = &views[accessor["bufferView"].as_u64().expect("view") as usize];
+            assert_eq!(
+                view["byteLength"].as_u64(),
+                Some((count * width * 4) as u64)
+            );
+            let offset = view["byteOffset"].as_u64().expect("offset") as usize;
+            assert_eq!(offset % 4, 0);
+            assert!(offset + count * width * 4 <= buffer_length);
+        }
+        assert_eq!(
+            document["buffers"][0]["byteLength"].as_u64(),
+            Some(buffer_length as u64)
+        );
+    }
+
+    #[test]
+    fn glb_chunks_hold_the_document_and_buffer() {
+        let mut glb = vec![];
+        let config = GltfConfig {
+            grouping: MeshGrouping::ByColor,
+            ..GltfConfig::default()
+        };
+        write_gltf(config, colored_cubes(), &mut glb).expect("writing to a vec");
+        let (document, bin) = read_glb(&glb);
+
+        assert_eq!(document["asset"]["version"], "2.0");
+        assert!(document["buffers"][0].get("uri").is_none());
+        assert_eq!(document["materials"].as_array().map(Vec::len), Some(2));
+        assert_eq!(document["nodes"].as_array().map(Vec::len), Some(2));
+        assert_accessors_fit(&document, bin.len());
+
+        let cube_mesh = cube().generate().next().expect("a cube");
+        let cube_vertices = cube_mesh.vertices().count();
+        let cube_indices = 3 * cube_mesh.faces().map(|face| face.len() - 2).sum::<usize>();
+        let counts = |group: usize| {
+            let primitive = &document["meshes"][group]["primitives"][0];
+            let count = |accessor: &Value| {
+                document["accessors"][accessor.as_u64().expect("accessor") as usize]["count"]
+                    .as_u64()
+                    .expect("count") as usize
+            };
+            (
+                count(&primitive["attributes"]["POSITION"]),
+                count(&primitive["indices"]),
+            )
+        };
+        let mut group_counts = vec![counts(0), counts(1)];
+        group_counts.sort();
+        assert_eq!(
+            group_counts,
+            vec![
+                (cube_vertices, cube_indices),
+                (3 * cube_vertices, 3 * cube_indices)
+            ]
+        );
+    }
+
+    /// Reads the floats an accessor refers to.
+    fn floats(document: &Value, bin: &[u8], accessor: &Value) -> Vec<f32> {
+        let accessor = &document["accessors"][accessor.as_u64().expect("accessor") as usize];
+        let view =
+            &document["bufferViews"][accessor["bufferView"].as_u64().expect("view") as usize];
+        let offset = view["byteOffset"].as_u64().expect("offset") as usize;
+        let length = view["byteLength"].as_u64().expect("length") as usize;
+        bin[offset..offset + length]
+            .chunks(4)
+            .map(|bytes| {
+                let mut float = [0u8; 4];
+                float.copy_from_slice(bytes);
+                f32::from_le_bytes(float)
+            })
+            .collect()
+    }
+
+    /// Returns the world space position of every vertex of every instance in the document.
+    fn instanced_positions(document: &Value, bin: &[u8]) -> Vec<Vector3<f32>> {
+        let mut positions = vec![];
+        for node in document["nodes"].as_array().expect("nodes") {
+            let mesh = &document["meshes"][node["mesh"].as_u64().expect("mesh") as usize];
+            let attributes = &mesh["primitives"][0]["attributes"];
+            let vertices = floats(document, bin, &attributes["POSITION"]);
+            let instances = &node["extensions"][INSTANCING_EXTENSION]["attributes"];
+            let translations = floats(document, bin, &instances["TRANSLATION"]);
+            let rotations = floats(document, bin, &instances["ROTATION"]);
+            let scales = floats(document, bin, &instances["SCALE"]);
+            for i in 0..translations.len() / 3 {
+                let translation = Vector3::from_column_slice(&translations[i * 3..i * 3 + 3]);
+                let rotation = UnitQuaternion::from_quaternion(Quaternion::new(
+                    rotations[i * 4 + 3],
+                    rotations[i * 4],
+                    rotations[i * 4 + 1],
+                    rotations[i * 4 + 2],
+                ));
+                let scale = Vector3::from_column_slice(&scales[i * 3..i * 3 + 3]);
+                for vertex in vertices.chunks(3) {
+                    let vertex = Vector3::from_column_slice(vertex);
+                    positions.push(rotation * vertex.component_mul(&scale) + translation);
+                }
+            }
+        }
+        positions
+    }
+
+    /// Checks that the instanced document places every vertex where the rule's meshes put it.
+    fn assert_instanced_positions(rule: Rule) {
+        let mut glb = vec![];
+        let config = GltfConfig {
+            instanced: true,
+            ..GltfConfig::default()
+        };
+        write_gltf(config, rule.clone().generate(), &mut glb).expect("writing to a vec");
+        let (document, bin) = read_glb(&glb);
+        assert_accessors_fit(&document, bin.len());
+
+        let actual = instanced_positions(&document, &bin);
+        let expected: Vec<Vertex> = rule
+            .generate()
+            .flat_map(|mesh| mesh.vertices().collect::<Vec<_>>())
+            .collect();
+        assert_eq!(actual.len(), expected.len());
+        for vertex in expected {
+            assert!(
+                actual
+                    .iter()
+                    .any(|position| (position - vertex.xyz()).norm() < 0.0001),
+                "no instance has a vertex at {:?}",
+                vertex
+            );
+        }
+    }
+
+    #[test]
+    fn instanced_meshes_are_written_once() {
+        let meshes = Rule::new()
+            .push(Replicate::n(3, Tf::ty(1.1)), cube())
+            .push(Tf::hue(120.0), cube())
+            .generate();
+        let mut glb = vec![];
+        let config = GltfConfig {
+            instanced: true,
+            ..GltfConfig::default()
+        };
+        write_gltf(config, meshes, &mut glb).expect("writing to a vec");
+        let (document, bin) = read_glb(&glb);
+
+        assert_eq!(document["extensionsRequired"][0], INSTANCING_EXTENSION);
+        let nodes = document["nodes"].as_array().expect("nodes");
+        assert_eq!(nodes.len(), 2);
+        let geometries: Vec<&Value> = document["meshes"]
+            .as_array()
+            .expect("meshes")
+            .iter()
+            .map(|mesh| &mesh["primitives"][0]["attributes"]["POSITION"])
+            .collect();
+        assert_eq!(geometries[0], geometries[1]);
+        let mut instances: Vec<usize> = nodes
+            .iter()
+            .map(|node| {
+                floats(
+                    &document,
+                    &bin,
+                    &node["extensions"][INSTANCING_EXTENSION]["attributes"]["TRANSLATION"],
+                )
+                .len()
+                    / 3
+            })
+            .collect();
+        instances.sort();
+        assert_eq!(instances, vec![1, 3]);
+    }
+
+    #[test]
+    fn parameterized_primitives_are_written_once() {
+        let meshes = Rule::new()
+            .push(Tf::tx(2.0), torus(0.35, 0.15, 12, 8))
+            .push(Tf::tx(-2.0), torus(0.35, 0.15, 12, 8))
+            .push(Tf::ty(2.0), cylinder(8))
+            .push(Tf::ty(-2.0), cylinder(8))
+            .push(None, cylinder(9))
+            .push(Tf::tz(2.0), disc(6))
+            .push(Tf::tz(-2.0), disc(6))
+            .generate();
+        let mut glb = vec![];
+        let config = GltfConfig {
+            instanced: true,
+            ..GltfConfig::default()
+        };
+        write_gltf(config, meshes, &mut glb).expect("writing to a vec");
+        let (document, _) = read_glb(&glb);
+        assert_eq!(document["meshes"].as_array().expect("meshes").len(), 4);
+    }
+
+    #[test]
+    fn instances_are_placed_by_their_transforms() {
+        let rule = Rule::new()
+            .push(Tf::t(1.0, -2.0, 3.0), cube())
+            .push(Tf::rx(30.0).cons(Tf::sby(2.0, 0.5, 1.0)), cube())
+            .push(Tf::fx().cons(Tf::ry(45.0)).cons(Tf::s(3.0)), cube());
+        assert_instanced_positions(rule);
+    }
+
+    #[test]
+    fn sheared_instances_are_baked() {
+        let matrix = Matrix4::new(
+            1.0, 0.5, 0.0, 2.0, //
+            0.0, 1.0, 0.0, 0.0, //
+            0.3, 0.0, 2.0, -1.0, //
+            0.0, 0.0, 0.0, 1.0,
+        );
+        let rule = Rule::new()
+            .push(Tf::shx(0.5, -1.0), cube())
+            .push(Tf::tz(3.0).cons(Tf::shy(2.0, 0.0)), cube())
+            .push(Tf::matrix(matrix), cube())
+            .push(Tf::tx(-3.0), cube());
+        assert_instanced_positions(rule);
+    }
+
+    #[test]
+    fn buffer_uri_is_relative_to_the_document() {
+        let directory = std::env::temp_dir().join("immense_gltf_buffer_uri");
+        std::fs::create_dir_all(&directory).expect("creating a temporary directory");
+        let buffer_file = directory.join("scene.bin");
+        let mut document = vec![];
+        let config = GltfConfig {
+            buffer_file: Some(buffer_file.to_string_lossy().into_owned()),
+            ..GltfConfig::default()
+        };
+        write_gltf(config, colored_cubes(), &mut document).expect("writing the buffer");
+
+        let document: Value = serde_json::from_slice(&document).expect("valid json");
+        let buffer = std::fs::read(&buffer_file).expect("reading the buffer");
+        assert_eq!(document["buffers"][0]["uri"], "scene.bin");
+        assert_accessors_fit(&document, buffer.len());
+        std::fs::remove_dir_all(&directory).expect("removing the temporary directory");
+    }
+}
@@ -0,0 +1,192 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::export::{facet_normal, triangulate, ExportError};
+use crate::mesh::Vertex;
+use crate::rule::OutputMesh;
+use std::io;
+
+/// The encoding of an STL file.
+///
+/// Most slicers accept either, but binary files are a fraction of the size.
+#[derive(Copy, Clone, Debug)]
+pub enum StlFormat {
+    /// The compact binary encoding.
+    Binary,
+    /// The human readable text encoding.
+    Ascii,
+}
+
+/// The default is [StlFormat::Binary][StlFormat::Binary].
+impl Default for StlFormat {
+    fn default() -> StlFormat {
+        StlFormat::Binary
+    }
+}
+
+/// A triangle of the output, as a facet normal followed by its three corners.
+type Facet = [[f32; 3]; 4];
+
+/// Writes out meshes as an STL file to the given [Write][io::Write] sink.
+///
+/// STL has no notion of color or grouping, so all meshes are written out as one solid.
+pub fn write_stl(
+    format: StlFormat,
+    meshes: impl Iterator<Item = OutputMesh>,
+    mut sink: impl io::Write,
+) -> Result<(), ExportError> {
+    match format {
+        StlFormat::Ascii => {
+            try_write_stl!(write!(&mut sink, "solid immense\n"));
+            for mesh in meshes {
+                for facet in facets(&mesh) {
+                    render_ascii_facet(facet, &mut sink)?;
+                }
+            }
+            try_write_stl!(write!(&mut sink, "endsolid immense\n"));
+        }
+        StlFormat::Binary => {
+            // The triangle count precedes the triangles, so they must all be known up front.
+            let facets: Vec<Facet> = meshes.flat_map(|mesh| facets(&mesh)).collect();
+            let mut header = [0u8; 80];
+            header[..7].copy_from_slice(b"immense");
+            try_write_stl!(sink.write_all(&header));
+            try_write_stl!(sink.write_all(&(facets.len() as u32).to_le_bytes()));
+            for facet in facets {
+                for component in facet.iter().flat_map(|v| v.iter()) {
+                    try_write_stl!(sink.write_all(&component.to_le_bytes()));
+                }
+                // Attribute byte count, which is unused.
+                try_write_stl!(sink.write_all(&[0, 0]));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn facets(output_mesh: &OutputMesh) -> Vec<Facet> {
+    let vertices: Vec<Vertex> = output_mesh.vertices().collect();
+    let mirrored = output_mesh.mirrored();
+    let mut facets = vec![];
+    for face in output_mesh.faces() {
+        for [a, b, c] in triangulate(face, mirrored) {
+            let (a, b, c) = (vertices[a - 1], vertices[b - 1], vertices[c - 1]);
+            facets.push([
+                facet_normal(a, b, c),
+                [a.x, a.y, a.z],
+                [b.x, b.y, b.z],
+                [c.x, c.y, c.z],
+            ]);
+        }
+    }
+    facets
+}
+
+fn render_ascii_facet(facet: Facet, mut sink: impl io::Write) -> Result<(), ExportError> {
+    let [normal, a, b, c] = facet;
+    try_write_stl!(write!(
+        &mut sink,
+        "facet normal {} {} {}\nouter loop\n",
+        normal[0], normal[1], normal[2]
+    ));
+    for corner in &[a, b, c] {
+        try_write_stl!(write!(
+            &mut sink,
+            "vertex {} {} {}\n",
+            corner[0], corner[1], corner[2]
+        ));
+    }
+    try_write_stl!(write!(&mut sink, "endloop\nendfacet\n"));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{cube, Replicate, Rule, Tf};
+
+    fn three_cubes() -> impl Iterator<Item = OutputMesh> {
+        Rule::new()
+            .push(Replicate::n(3, Tf::ty(1.1)), cube())
+            .generate()
+    }
+
+    #[test]
+    fn binary_has_header_and_triangle_count() {
+        let mut stl = vec![];
+        write_stl(StlFormat::Binary, three_cubes(), &mut stl).expect("writing to a vec");
+
+        assert_eq!(&stl[..7], b"immense");
+        assert!(stl[7..80].iter().all(|byte| *byte == 0));
+        let mut count = [0u8; 4];
+        count.copy_from_slice(&stl[80..84]);
+        assert_eq!(u32::from_le_bytes(count), 36);
+        assert_eq!(stl.len(), 84 + 36 * 50);
+        for triangle in stl[84..].chunks(50) {
+            assert_eq!(&triangle[48..], &[0, 0]);
+        }
+    }
+
+    #[test]
+    fn binary_without_meshes_is_an_empty_solid() {
+        let mut stl = vec![];
+        write_stl(StlFormat::Binary, std::iter::empty(), &mut stl).expect("writing to a vec");
+        assert_eq!(stl.len(), 84);
+        assert_eq!(&stl[80..], &[0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn ascii_has_facet_structure() {
+        let mut stl = vec![];
+        write_stl(StlFormat::Ascii, three_cubes(), &mut stl).expect("writing to a vec");
+        let stl = String::from_utf8(stl).expect("ascii stl is utf8");
+        let lines: Vec<&str> = stl.lines().collect();
+
+        assert_eq!(lines.first(), Some(&"solid immense"));
+        assert_eq!(lines.last(), Some(&"endsolid immense"));
+        let facets = &lines[1..lines.len() - 1];
+        assert_eq!(facets.len(), 36 * 7);
+        for facet in facets.chunks(7) {
+            assert!(facet[0].starts_with("facet normal "));
+            assert_eq!(facet[0].split_whitespace().count(), 5);
+            assert_eq!(facet[1], "outer loop");
+            for corner in &facet[2..5] {
+                assert!(corner.starts_with("vertex "));
+                assert_eq!(corner.split_whitespace().count(), 4);
+            }
+            assert_eq!(facet[5], "endloop");
+            assert_eq!(facet[6], "endfacet");
+        }
+    }
+
+    #[test]
+    fn mirrored_facets_face_out() {
+        let cubes = Rule::new()
+            .push(Tf::fx(), cube())
+            .push(Tf::fy().cons(Tf::fz()), cube())
+            .push(Tf::sby(1.0, 1.0, -2.0), cube());
+        let rule = Rule::new().push(Replicate::n(2, Tf::tx(3.0)), cubes);
+        let meshes: Vec<OutputMesh> = rule.generate().collect();
+        assert_eq!(meshes.iter().filter(|mesh| mesh.mirrored()).count(), 4);
+        for mesh in meshes {
+            let center = mesh.vertices().fold(Vertex::zeros(), |sum, v| sum + v) / 8.0;
+            for [normal, a, b, c] in facets(&mesh) {
+                let outward = (0..3)
+                    .map(|i| normal[i] * ((a[i] + b[i] + c[i]) / 3.0 - center[i]))
+                    .sum::<f32>();
+                assert!(outward > 0.0);
+            }
+        }
+    }
+}
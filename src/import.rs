@@ -0,0 +1,270 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::mesh::{vertex, Mesh, Vertex};
+use failure_derive::Fail;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader};
+use std::sync::Arc;
+
+#[derive(Fail, Debug)]
+pub enum ImportError {
+    #[fail(display = "Failed to read mesh file.")]
+    ReadError {
+        #[cause]
+        read_error: io::Error,
+    },
+    #[fail(display = "Invalid mesh file at line {}: {}", line, message)]
+    InvalidLine { line: usize, message: String },
+    #[fail(display = "Invalid mesh file: {}", message)]
+    Invalid { message: String },
+}
+
+macro_rules! try_read {
+    ($expr:expr) => {
+        match $expr {
+            Ok(val) => val,
+            Err(err) => return Err(ImportError::ReadError { read_error: err }),
+        }
+    };
+}
+
+mod ply;
+mod stl;
+
+pub use self::ply::read_ply;
+pub use self::stl::read_stl;
+
+/// A corner of a face, as indices into the file's vertex, texture coordinate and normal lists.
+type Corner = (usize, Option<usize>, Option<usize>);
+
+/// Reads a mesh from Wavefront object file text.
+///
+/// Positions (`v`), texture coordinates (`vt`), normals (`vn`) and faces (`f`) are read, and
+/// everything else is ignored. All groups and objects in the file are combined into one mesh.
+///
+/// An object file indexes positions, texture coordinates, and normals separately, but a
+/// [Mesh][crate::mesh::Mesh] has one list of vertices with a normal for each, so a vertex is
+/// created for each distinct combination the faces use. Normals and texture coordinates are only
+/// kept if every face corner has them.
+pub fn read_obj(source: impl io::Read) -> Result<Arc<Mesh>, ImportError> {
+    let mut positions: Vec<Vertex> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+    let mut normals: Vec<Vertex> = vec![];
+    let mut faces: Vec<Vec<Corner>> = vec![];
+
+    for (i, line) in BufReader::new(source).lines().enumerate() {
+        let line = try_read!(line);
+        let number = i + 1;
+        let invalid = |message: String| ImportError::InvalidLine {
+            line: number,
+            message,
+        };
+        let line = line.split('#').next().unwrap_or("");
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("v") => {
+                let [x, y, z] = read_floats(&mut fields).map_err(invalid)?;
+                positions.push(vertex(x, y, z));
+            }
+            Some("vn") => {
+                let [x, y, z] = read_floats(&mut fields).map_err(invalid)?;
+                normals.push(Vertex::new(x, y, z, 0.0).normalize());
+            }
+            Some("vt") => {
+                let [u, v] = read_floats(&mut fields).map_err(invalid)?;
+                uvs.push([u, v]);
+            }
+            Some("f") => {
+                let face = fields
+                    .map(|corner| read_corner(corner, [positions.len(), uvs.len(), normals.len()]))
+                    .collect::<Result<Vec<Corner>, String>>()
+                    .map_err(invalid)?;
+                if face.len() < 3 {
+                    return Err(invalid(format!(
+                        "a face needs at least 3 vertices, found {}",
+                        face.len()
+                    )));
+                }
+                faces.push(face);
+            }
+            _ => (),
+        }
+    }
+
+    let has_uvs = faces.iter().flatten().all(|(_, uv, _)| uv.is_some());
+    let has_normals = faces
+        .iter()
+        .flatten()
+        .all(|(_, _, normal)| normal.is_some());
+    let mut indices: HashMap<Corner, usize> = HashMap::new();
+    let mut mesh_vertices = vec![];
+    let mut mesh_normals = vec![];
+    let mut mesh_uvs = vec![];
+    let mesh_faces = faces
+        .into_iter()
+        .map(|face| {
+            face.into_iter()
+                .map(|(position, uv, normal)| {
+                    let uv = uv.filter(|_| has_uvs);
+                    let normal = normal.filter(|_| has_normals);
+                    *indices.entry((position, uv, normal)).or_insert_with(|| {
+                        mesh_vertices.push(positions[position]);
+                        if let Some(normal) = normal {
+                            mesh_normals.push(normals[normal]);
+                        }
+                        if let Some(uv) = uv {
+                            mesh_uvs.push(uvs[uv]);
+                        }
+                        mesh_vertices.len()
+                    })
+                })
+                .collect()
+        })
+        .collect();
+
+    let mesh = Mesh::new(
+        mesh_vertices,
+        if has_normals {
+            Some(mesh_normals)
+        } else {
+            None
+        },
+        mesh_faces,
+    );
+    Ok(Arc::new(if has_uvs {
+        mesh.with_uvs(mesh_uvs)
+    } else {
+        mesh
+    }))
+}
+
+/// Reads the leading numbers of a record, ignoring any optional ones that follow.
+fn read_floats<'a, A: Default + AsMut<[f32]>>(
+    fields: &mut impl Iterator<Item = &'a str>,
+) -> Result<A, String> {
+    let mut values = A::default();
+    for value in values.as_mut() {
+        let field = fields
+            .next()
+            .ok_or_else(|| String::from("too few coordinates"))?;
+        *value = field
+            .parse()
+            .map_err(|_| format!("invalid number `{}`", field))?;
+    }
+    Ok(values)
+}
+
+/// Reads a `v`, `v/vt`, `v//vn` or `v/vt/vn` face corner, resolving its 1-based or negative
+/// relative indices against the number of each element read so far.
+fn read_corner(corner: &str, counts: [usize; 3]) -> Result<Corner, String> {
+    let mut parts = corner.split('/');
+    let mut index = |count: usize, required: bool| -> Result<Option<usize>, String> {
+        let part = match parts.next() {
+            Some(part) if !part.is_empty() => part,
+            _ if required => return Err(format!("invalid face vertex `{}`", corner)),
+            _ => return Ok(None),
+        };
+        let index: isize = part
+            .parse()
+            .map_err(|_| format!("invalid index `{}`", part))?;
+        let resolved = if index < 0 {
+            count as isize + index
+        } else {
+            index - 1
+        };
+        if index == 0 || resolved < 0 || resolved >= count as isize {
+            return Err(format!("index `{}` is out of range", part));
+        }
+        Ok(Some(resolved as usize))
+    };
+    let position = index(counts[0], true)?.expect("required index");
+    let uv = index(counts[1], false)?;
+    let normal = index(counts[2], false)?;
+    Ok((position, uv, normal))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(source: &str) -> Result<Arc<Mesh>, ImportError> {
+        read_obj(source.as_bytes())
+    }
+
+    #[test]
+    fn shared_corners_become_one_vertex() {
+        let mesh = read(
+            "# a unit square in two groups
+            v 0 0 0
+            v 1 0 0
+            v 1 1 0
+            v 0 1 0
+            vt 0 0
+            vt 1 0
+            vt 1 1
+            vt 0 1
+            vn 0 0 2
+            g first
+            f 1/1/1 2/2/1 3/3/1
+            g second
+            f -4/-4/-1 -2/-2/-1 -1/-1/-1",
+        )
+        .expect("valid obj");
+        assert_eq!(mesh.vertices().len(), 4);
+        assert_eq!(
+            mesh.faces().collect::<Vec<_>>(),
+            vec![&[1, 2, 3][..], &[1, 3, 4][..]]
+        );
+        assert_eq!(mesh.uvs().expect("uvs")[3], [0.0, 1.0]);
+        let normals = mesh.normals().expect("normals");
+        assert_eq!(normals.len(), 4);
+        assert!(normals
+            .iter()
+            .all(|n| *n == Vertex::new(0.0, 0.0, 1.0, 0.0)));
+    }
+
+    #[test]
+    fn distinct_normals_split_vertices() {
+        let mesh = read(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\nvn 0 0 -1\nvn 0 -1 0\n\
+             f 1//1 3//1 2//1\nf 1//2 2//2 4//2",
+        )
+        .expect("valid obj");
+        assert_eq!(mesh.vertices().len(), 6);
+        assert!(mesh.uvs().is_none());
+    }
+
+    #[test]
+    fn partial_attributes_are_dropped() {
+        let mesh =
+            read("v 0 0 0\nv 1 0 0\nv 0 1 0\nvn 0 0 1\nf 1//1 2//1 3//1\nf 1 3 2").expect("obj");
+        assert!(mesh.normals().is_none());
+        assert_eq!(mesh.vertices().len(), 3);
+    }
+
+    #[test]
+    fn invalid_lines_are_reported() {
+        let line = |source: &str| match read(source) {
+            Err(ImportError::InvalidLine { line, .. }) => line,
+            _ => panic!("expected an invalid line in {:?}", source),
+        };
+        assert_eq!(line("v 0 0 0\nv 1 0 0\nf 1 2"), 3);
+        assert_eq!(line("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 4"), 4);
+        assert_eq!(line("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 1 2 -4"), 4);
+        assert_eq!(line("v 0 0 0\nv 1 0 0\nv 1 1 0\nf 0 1 2"), 4);
+        assert_eq!(line("v 0 0\n"), 1);
+        assert_eq!(line("v 0 0 zero\n"), 1);
+    }
+}
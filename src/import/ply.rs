@@ -0,0 +1,437 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::import::ImportError;
+use crate::mesh::{vertex, vertex_normals, Mesh, Vertex};
+use std::convert::TryInto;
+use std::io;
+use std::sync::Arc;
+
+#[derive(Copy, Clone, PartialEq)]
+enum Encoding {
+    Ascii,
+    BinaryLittleEndian,
+    BinaryBigEndian,
+}
+
+#[derive(Copy, Clone)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+enum Property {
+    Scalar {
+        name: String,
+        scalar: Scalar,
+    },
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+/// Reads a mesh from an ASCII or binary PLY file.
+///
+/// Vertex positions, normals (`nx`, `ny`, `nz`) and texture coordinates (`u`, `v` or `s`, `t`) are
+/// read along with the faces. Other properties and elements are skipped. If the vertices have no
+/// normals, smooth normals are computed from the faces.
+pub fn read_ply(mut source: impl io::Read) -> Result<Arc<Mesh>, ImportError> {
+    let mut bytes = vec![];
+    try_read!(source.read_to_end(&mut bytes));
+    let (encoding, elements, body_start, header_lines) = read_header(&bytes)?;
+    let mut body = Body {
+        bytes: &bytes[body_start..],
+        position: 0,
+        encoding,
+        line: header_lines + 1,
+    };
+
+    let mut vertices: Vec<Vertex> = vec![];
+    let mut normals: Vec<Vertex> = vec![];
+    let mut uvs: Vec<[f32; 2]> = vec![];
+    let mut faces: Vec<Vec<usize>> = vec![];
+    for element in &elements {
+        let index_of = |names: &[&str]| {
+            element
+                .properties
+                .iter()
+                .position(|property| match property {
+                    Property::Scalar { name, .. } => names.contains(&name.as_str()),
+                    Property::List { .. } => false,
+                })
+        };
+        let position = [index_of(&["x"]), index_of(&["y"]), index_of(&["z"])];
+        let normal = [index_of(&["nx"]), index_of(&["ny"]), index_of(&["nz"])];
+        let uv = [
+            index_of(&["u", "s", "texture_u", "texture_s"]),
+            index_of(&["v", "t", "texture_v", "texture_t"]),
+        ];
+        for _ in 0..element.count {
+            let mut values = vec![];
+            let mut lists = vec![];
+            for property in &element.properties {
+                match property {
+                    Property::Scalar { scalar, .. } => {
+                        values.push(body.read(*scalar)?);
+                        lists.push(None);
+                    }
+                    Property::List { name, count, item } => {
+                        let count = body.read_index(*count)?;
+                        let items = (0..count)
+                            .map(|_| body.read_index(*item))
+                            .collect::<Result<Vec<usize>, ImportError>>()?;
+                        values.push(0.0);
+                        lists.push(Some((name, items)));
+                    }
+                }
+            }
+            let value = |index: Option<usize>| index.map(|index| values[index] as f32);
+            match element.name.as_str() {
+                "vertex" => {
+                    let [x, y, z] = position;
+                    match (value(x), value(y), value(z)) {
+                        (Some(x), Some(y), Some(z)) => vertices.push(vertex(x, y, z)),
+                        _ => {
+                            return Err(ImportError::Invalid {
+                                message: String::from("vertices need x, y and z properties"),
+                            })
+                        }
+                    }
+                    let [x, y, z] = normal;
+                    if let (Some(x), Some(y), Some(z)) = (value(x), value(y), value(z)) {
+                        normals.push(Vertex::new(x, y, z, 0.0).normalize());
+                    }
+                    if let (Some(u), Some(v)) = (value(uv[0]), value(uv[1])) {
+                        uvs.push([u, v]);
+                    }
+                }
+                "face" => {
+                    let indices = lists
+                        .into_iter()
+                        .flatten()
+                        .find(|(name, _)| *name == "vertex_indices" || *name == "vertex_index")
+                        .map(|(_, indices)| indices)
+                        .ok_or_else(|| ImportError::Invalid {
+                            message: String::from("faces need a vertex_indices property"),
+                        })?;
+                    faces.push(indices);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    for face in &mut faces {
+        if face.len() < 3 || face.iter().any(|index| *index >= vertices.len()) {
+            return Err(ImportError::Invalid {
+                message: format!("invalid face {:?}", face),
+            });
+        }
+        for index in face.iter_mut() {
+            *index += 1;
+        }
+    }
+    let normals = if normals.len() == vertices.len() {
+        normals
+    } else {
+        vertex_normals(&vertices, &faces)
+    };
+    let uv_count = uvs.len();
+    let mesh = Mesh::new(vertices, Some(normals), faces);
+    Ok(Arc::new(if uv_count == mesh.vertices().len() {
+        mesh.with_uvs(uvs)
+    } else {
+        mesh
+    }))
+}
+
+/// Reads the header, returning the body's encoding and elements, and where and on which line
+/// the body starts.
+fn read_header(bytes: &[u8]) -> Result<(Encoding, Vec<Element>, usize, usize), ImportError> {
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut position = 0;
+    let mut line_number = 0;
+    loop {
+        let end = match bytes[position..].iter().position(|b| *b == b'\n') {
+            Some(end) => position + end,
+            None => {
+                return Err(ImportError::Invalid {
+                    message: String::from("the header is not terminated by end_header"),
+                })
+            }
+        };
+        let line = String::from_utf8_lossy(&bytes[position..end]);
+        position = end + 1;
+        line_number += 1;
+        let invalid = |message: &str| ImportError::InvalidLine {
+            line: line_number,
+            message: format!("{} `{}`", message, line.trim()),
+        };
+
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if line_number == 1 {
+            if fields != ["ply"] {
+                return Err(ImportError::Invalid {
+                    message: String::from("not a PLY file"),
+                });
+            }
+            continue;
+        }
+        match fields.as_slice() {
+            ["format", format, _] => {
+                encoding = Some(match *format {
+                    "ascii" => Encoding::Ascii,
+                    "binary_little_endian" => Encoding::BinaryLittleEndian,
+                    "binary_big_endian" => Encoding::BinaryBigEndian,
+                    _ => return Err(invalid("unknown format")),
+                })
+            }
+            ["element", name, count] => elements.push(Element {
+                name: name.to_string(),
+                count: count
+                    .parse()
+                    .map_err(|_| invalid("invalid element count"))?,
+                properties: vec![],
+            }),
+            ["property", "list", count, item, name] => {
+                let property = Property::List {
+                    name: name.to_string(),
+                    count: scalar(count).ok_or_else(|| invalid("unknown type"))?,
+                    item: scalar(item).ok_or_else(|| invalid("unknown type"))?,
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("property outside an element"))?
+                    .properties
+                    .push(property);
+            }
+            ["property", scalar_type, name] => {
+                let property = Property::Scalar {
+                    name: name.to_string(),
+                    scalar: scalar(scalar_type).ok_or_else(|| invalid("unknown type"))?,
+                };
+                elements
+                    .last_mut()
+                    .ok_or_else(|| invalid("property outside an element"))?
+                    .properties
+                    .push(property);
+            }
+            ["end_header"] => break,
+            ["comment", ..] | ["obj_info", ..] | [] => (),
+            _ => return Err(invalid("invalid header line")),
+        }
+    }
+    let encoding = encoding.ok_or_else(|| ImportError::Invalid {
+        message: String::from("the header has no format"),
+    })?;
+    Ok((encoding, elements, position, line_number))
+}
+
+fn scalar(name: &str) -> Option<Scalar> {
+    Some(match name {
+        "char" | "int8" => Scalar::I8,
+        "uchar" | "uint8" => Scalar::U8,
+        "short" | "int16" => Scalar::I16,
+        "ushort" | "uint16" => Scalar::U16,
+        "int" | "int32" => Scalar::I32,
+        "uint" | "uint32" => Scalar::U32,
+        "float" | "float32" => Scalar::F32,
+        "double" | "float64" => Scalar::F64,
+        _ => return None,
+    })
+}
+
+/// The element data following the header.
+struct Body<'a> {
+    bytes: &'a [u8],
+    position: usize,
+    encoding: Encoding,
+    /// The current line, for errors in ASCII files.
+    line: usize,
+}
+
+impl<'a> Body<'a> {
+    fn read(&mut self, scalar: Scalar) -> Result<f64, ImportError> {
+        if self.encoding == Encoding::Ascii {
+            return self.read_ascii();
+        }
+        let size = match scalar {
+            Scalar::I8 | Scalar::U8 => 1,
+            Scalar::I16 | Scalar::U16 => 2,
+            Scalar::I32 | Scalar::U32 | Scalar::F32 => 4,
+            Scalar::F64 => 8,
+        };
+        if self.position + size > self.bytes.len() {
+            return Err(ImportError::Invalid {
+                message: String::from("the file ends before all elements are read"),
+            });
+        }
+        let mut field = self.bytes[self.position..self.position + size].to_vec();
+        self.position += size;
+        if self.encoding == Encoding::BinaryBigEndian {
+            field.reverse();
+        }
+        Ok(match scalar {
+            Scalar::I8 => f64::from(field[0] as i8),
+            Scalar::U8 => f64::from(field[0]),
+            Scalar::I16 => f64::from(i16::from_le_bytes(field[..].try_into().expect("2 bytes"))),
+            Scalar::U16 => f64::from(u16::from_le_bytes(field[..].try_into().expect("2 bytes"))),
+            Scalar::I32 => f64::from(i32::from_le_bytes(field[..].try_into().expect("4 bytes"))),
+            Scalar::U32 => f64::from(u32::from_le_bytes(field[..].try_into().expect("4 bytes"))),
+            Scalar::F32 => f64::from(f32::from_le_bytes(field[..].try_into().expect("4 bytes"))),
+            Scalar::F64 => f64::from_le_bytes(field[..].try_into().expect("8 bytes")),
+        })
+    }
+
+    fn read_ascii(&mut self) -> Result<f64, ImportError> {
+        while let Some(byte) = self.bytes.get(self.position) {
+            if !byte.is_ascii_whitespace() {
+                break;
+            }
+            if *byte == b'\n' {
+                self.line += 1;
+            }
+            self.position += 1;
+        }
+        let start = self.position;
+        while let Some(byte) = self.bytes.get(self.position) {
+            if byte.is_ascii_whitespace() {
+                break;
+            }
+            self.position += 1;
+        }
+        let field = String::from_utf8_lossy(&self.bytes[start..self.position]);
+        if field.is_empty() {
+            return Err(ImportError::Invalid {
+                message: String::from("the file ends before all elements are read"),
+            });
+        }
+        field.parse().map_err(|_| ImportError::InvalidLine {
+            line: self.line,
+            message: format!("invalid number `{}`", field),
+        })
+    }
+
+    fn read_index(&mut self, scalar: Scalar) -> Result<usize, ImportError> {
+        let value = self.read(scalar)?;
+        if value < 0.0 || value.fract() != 0.0 {
+            return Err(ImportError::Invalid {
+                message: format!("invalid index {}", value),
+            });
+        }
+        Ok(value as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ASCII_TETRAHEDRON: &str = "ply
+format ascii 1.0
+comment a tetrahedron with an extra element
+element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+element face 4
+property list uchar int vertex_indices
+element edge 1
+property int vertex1
+property int vertex2
+end_header
+0 0 0 255
+1 0 0 0
+0 1 0 0
+0 0 1 0
+3 0 2 1
+3 0 1 3
+3 0 3 2
+3 1 2 3
+0 1
+";
+
+    #[test]
+    fn ascii_faces_and_computed_normals() {
+        let mesh = read_ply(ASCII_TETRAHEDRON.as_bytes()).expect("valid ply");
+        assert_eq!(mesh.vertices().len(), 4);
+        assert_eq!(
+            mesh.faces().collect::<Vec<_>>()[0],
+            &[1, 3, 2][..],
+            "indices start at 1"
+        );
+        let normals = mesh.normals().expect("computed normals");
+        let expected = -Vertex::new(1.0, 1.0, 1.0, 0.0).normalize();
+        assert!((normals[0] - expected).norm() < 0.0001);
+    }
+
+    #[test]
+    fn binary_matches_ascii() {
+        let header = "ply\nformat binary_little_endian 1.0\nelement vertex 3\nproperty float x\n\
+                      property float y\nproperty float z\nproperty float nx\nproperty float ny\n\
+                      property float nz\nproperty double u\nproperty double v\n\
+                      element face 1\nproperty list uchar ushort vertex_index\nend_header\n";
+        let mut bytes = header.as_bytes().to_vec();
+        for (x, y) in &[(0.0f32, 0.0f32), (1.0, 0.0), (0.0, 1.0)] {
+            for value in &[*x, *y, 0.0, 0.0, 0.0, 1.0] {
+                bytes.extend(&value.to_le_bytes());
+            }
+            bytes.extend(&f64::from(*x).to_le_bytes());
+            bytes.extend(&f64::from(*y).to_le_bytes());
+        }
+        bytes.push(3);
+        for index in &[0u16, 1, 2] {
+            bytes.extend(&index.to_le_bytes());
+        }
+
+        let mesh = read_ply(bytes.as_slice()).expect("valid ply");
+        assert_eq!(mesh.vertices()[1], vertex(1.0, 0.0, 0.0));
+        assert_eq!(
+            mesh.normals().expect("normals")[2],
+            Vertex::new(0.0, 0.0, 1.0, 0.0)
+        );
+        assert_eq!(mesh.uvs().expect("uvs")[2], [0.0, 1.0]);
+        assert_eq!(mesh.faces().collect::<Vec<_>>(), vec![&[1, 2, 3][..]]);
+
+        bytes.pop();
+        assert!(read_ply(bytes.as_slice()).is_err());
+    }
+
+    #[test]
+    fn invalid_faces_are_rejected() {
+        let source = ASCII_TETRAHEDRON.replace("3 1 2 3", "3 1 2 4");
+        assert!(read_ply(source.as_bytes()).is_err());
+        let source = ASCII_TETRAHEDRON.replace("1 0 0 0", "1 0 zero 0");
+        match read_ply(source.as_bytes()) {
+            Err(ImportError::InvalidLine { line, .. }) => assert_eq!(line, 16),
+            _ => panic!("expected an invalid number"),
+        }
+    }
+}
@@ -0,0 +1,173 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::import::ImportError;
+use crate::mesh::{vertex, vertex_normals, Mesh, Vertex};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::io;
+use std::sync::Arc;
+
+/// The size of a binary STL header, including the triangle count.
+const HEADER_SIZE: usize = 84;
+/// The size of each triangle in a binary STL file.
+const TRIANGLE_SIZE: usize = 50;
+
+/// Reads a mesh from a binary or ASCII STL file.
+///
+/// STL files list the corners of each triangle separately, so corners at the same position are
+/// welded into one vertex, and triangles which collapse when welded are dropped. The facet normals
+/// in the file are ignored in favor of smooth vertex normals computed from the triangles.
+pub fn read_stl(mut source: impl io::Read) -> Result<Arc<Mesh>, ImportError> {
+    let mut bytes = vec![];
+    try_read!(source.read_to_end(&mut bytes));
+    let triangles = if is_binary(&bytes) {
+        read_binary(&bytes)
+    } else if bytes.starts_with(b"solid") {
+        read_ascii(&String::from_utf8_lossy(&bytes))?
+    } else {
+        return Err(ImportError::Invalid {
+            message: String::from("not an STL file"),
+        });
+    };
+
+    let mut indices: HashMap<[u32; 3], usize> = HashMap::new();
+    let mut vertices: Vec<Vertex> = vec![];
+    let mut faces: Vec<Vec<usize>> = vec![];
+    for triangle in triangles {
+        let face: Vec<usize> = triangle
+            .iter()
+            .map(|corner| {
+                // Adding zero turns -0.0 into 0.0 so the two are welded together.
+                let key = [
+                    (corner[0] + 0.0).to_bits(),
+                    (corner[1] + 0.0).to_bits(),
+                    (corner[2] + 0.0).to_bits(),
+                ];
+                *indices.entry(key).or_insert_with(|| {
+                    vertices.push(vertex(corner[0], corner[1], corner[2]));
+                    vertices.len()
+                })
+            })
+            .collect();
+        if face[0] != face[1] && face[1] != face[2] && face[2] != face[0] {
+            faces.push(face);
+        }
+    }
+
+    let normals = vertex_normals(&vertices, &faces);
+    Ok(Arc::new(Mesh::new(vertices, Some(normals), faces)))
+}
+
+/// ASCII files begin with "solid" too, but a binary file's size always matches its triangle count.
+fn is_binary(bytes: &[u8]) -> bool {
+    if bytes.len() < HEADER_SIZE {
+        return false;
+    }
+    let count = u32::from_le_bytes(bytes[80..84].try_into().expect("4 bytes")) as usize;
+    bytes.len() == HEADER_SIZE + count * TRIANGLE_SIZE
+}
+
+fn read_binary(bytes: &[u8]) -> Vec<[[f32; 3]; 3]> {
+    let float =
+        |offset: usize| f32::from_le_bytes(bytes[offset..offset + 4].try_into().expect("4 bytes"));
+    bytes[HEADER_SIZE..]
+        .chunks(TRIANGLE_SIZE)
+        .enumerate()
+        .map(|(i, _)| {
+            // Each triangle is a facet normal, three corners, and an attribute byte count.
+            let corners = HEADER_SIZE + i * TRIANGLE_SIZE + 12;
+            let corner = |j: usize| {
+                let offset = corners + j * 12;
+                [float(offset), float(offset + 4), float(offset + 8)]
+            };
+            [corner(0), corner(1), corner(2)]
+        })
+        .collect()
+}
+
+fn read_ascii(text: &str) -> Result<Vec<[[f32; 3]; 3]>, ImportError> {
+    let mut triangles = vec![];
+    let mut corners: Vec<[f32; 3]> = vec![];
+    for (i, line) in text.lines().enumerate() {
+        let invalid = |message: String| ImportError::InvalidLine {
+            line: i + 1,
+            message,
+        };
+        let mut fields = line.split_whitespace();
+        match fields.next() {
+            Some("vertex") => {
+                let mut corner = [0.0; 3];
+                for coordinate in corner.iter_mut() {
+                    let field = fields
+                        .next()
+                        .ok_or_else(|| invalid(String::from("too few coordinates")))?;
+                    *coordinate = field
+                        .parse()
+                        .map_err(|_| invalid(format!("invalid number `{}`", field)))?;
+                }
+                corners.push(corner);
+            }
+            Some("endfacet") => {
+                if corners.len() != 3 {
+                    return Err(invalid(format!(
+                        "a facet needs 3 vertices, found {}",
+                        corners.len()
+                    )));
+                }
+                triangles.push([corners[0], corners[1], corners[2]]);
+                corners.clear();
+            }
+            _ => (),
+        }
+    }
+    Ok(triangles)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::{write_stl, StlFormat};
+    use crate::rule::cube;
+
+    fn round_trip(format: StlFormat) -> Arc<Mesh> {
+        let mut bytes = vec![];
+        write_stl(format, cube().generate(), &mut bytes).expect("stl output");
+        read_stl(bytes.as_slice()).expect("stl input")
+    }
+
+    #[test]
+    fn cube_corners_are_welded() {
+        for format in &[StlFormat::Binary, StlFormat::Ascii] {
+            let mesh = round_trip(*format);
+            assert_eq!(mesh.vertices().len(), 8);
+            assert_eq!(mesh.faces().count(), 12);
+            for (vertex, normal) in mesh.vertices().iter().zip(mesh.normals().expect("normals")) {
+                // Each corner's normal points diagonally away from the center.
+                let expected = vertex.xyz().normalize();
+                assert!((normal.xyz() - expected).norm() < 0.0001);
+            }
+        }
+    }
+
+    #[test]
+    fn ascii_errors_have_lines() {
+        let source = "solid s\nfacet normal 0 0 1\nouter loop\nvertex 0 0 0\nvertex 1 0 0\n\
+                      endloop\nendfacet\nendsolid s\n";
+        match read_stl(source.as_bytes()) {
+            Err(ImportError::InvalidLine { line, .. }) => assert_eq!(line, 7),
+            _ => panic!("expected an invalid facet"),
+        }
+    }
+}
@@ -37,9 +37,13 @@
 //! 2. [Composing Rules](#composing_rules)
 //!     1. [Recursion](#recursion)
 //!     2. [Randomness](#randomness)
+//!     3. [Deformers](#deformers)
 //! 3. [Color](#color)
 //! 4. [Ergonomics Macros](#ergonomics-macros)
 //! 5. [Custom Meshes](#custom-meshes)
+//! 6. [EisenScript](#eisenscript)
+//! 7. [L-systems](#l-systems)
+//! 8. [Rule Descriptions](#rule-descriptions)
 //!
 //! # Intro
 //!
@@ -50,7 +54,8 @@
 //!
 //! After you've built your [Rule][self::rule::Rule], you can export the meshes it expands to as a
 //! Wavefront object file for the next part of your workflow, whether that is rendering it in Blender,
-//! printing it in your 3D printer, or importing it into your game!
+//! printing it in your 3D printer, or importing it into your game! If your printer wants STL, use
+//! [write_stl][self::write_stl] instead.
 //!
 //! # Composing Rules
 //!
@@ -107,6 +112,7 @@
 //! You can generate rules recursively with the api we've covered so far, but doing so would put
 //! your entire rule tree in memory at one time, which can become a problem. immense provides a
 //! trait, [ToRule][rule::ToRule], so you can give it types that can instantiate rules when needed.
+//! The [Context][rule::Context] each rule is built with is for randomness, which we'll get to below.
 //!
 //! ````
 //! # use immense::*;
@@ -115,7 +121,7 @@
 //! }
 //!
 //! impl ToRule for RecursiveTile {
-//!     fn to_rule(&self) -> Rule {
+//!     fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
 //!         let rule = Rule::new()
 //!             .push(vec![Tf::t(0.25, 0.25, 0.0), Tf::s(0.4)], cube())
 //!             .push(vec![Tf::t(-0.25, -0.25, 0.0), Tf::s(0.4)], cube())
@@ -140,24 +146,34 @@
 //!
 //! ![](https://i.imgur.com/huqVLHE.png)
 //!
+//! Instead of carrying a depth budget in each rule, you can also limit the whole expansion by depth,
+//! mesh count, or the size meshes are scaled down to with
+//! [Rule::generate_with][rule::Rule::generate_with].
+//!
+//! Types implementing [ToRule][rule::ToRule] must be `Send` and `Sync`, so that large expansions
+//! can be spread across threads with [Rule::generate_parallel][rule::Rule::generate_parallel].
+//!
 //! ## Randomness
 //!
 //! Using [ToRule][rule::ToRule] to delay rule construction, we can sample some random values
-//! each time our type builds a rule.
+//! each time our type builds a rule. Implement [to_rule_with][rule::ToRule::to_rule_with] and draw
+//! your random values from the [Context][rule::Context] you're given, which is a random number
+//! generator.
 //!
 //! ````
 //! # use immense::*;
 //! # use rand::*;
+//! # use rand::seq::SliceRandom;
 //! struct RandCube;
 //!
 //! impl ToRule for RandCube {
-//!     fn to_rule(&self) -> Rule {
+//!     fn to_rule_with(&self, ctx: &mut Context) -> Rule {
 //!         Rule::new().push(
-//!             *thread_rng()
-//!                 .choose(&[Tf::tx(0.1),
-//!                           Tf::tx(-0.1),
-//!                           Tf::tx(0.2),
-//!                           Tf::tx(-0.2)])
+//!             *[Tf::tx(0.1),
+//!               Tf::tx(-0.1),
+//!               Tf::tx(0.2),
+//!               Tf::tx(-0.2)]
+//!                 .choose(ctx)
 //!                 .unwrap(),
 //!             cube(),
 //!         )
@@ -170,6 +186,42 @@
 //!
 //! ![](https://i.imgur.com/bSNc6jw.png)
 //!
+//! Picking one of a few subrules is common enough that immense provides it as
+//! [Rule::choose][rule::Rule::choose], which makes a weighted choice each time it is expanded.
+//!
+//! ````
+//! # use immense::*;
+//! let rule = Rule::new().push(Replicate::n(4, Tf::ty(1.0)),
+//!                             Rule::choose(vec![(3.0, cube()), (1.0, icosphere())]));
+//! ````
+//!
+//! Each expansion is seeded, and every subrule is given a context seeded from its parent's seed and
+//! its position in the parent. If you like a structure, expand it again with
+//! [generate_with_seed][rule::Rule::generate_with_seed] and you will get the same meshes.
+//!
+//! ````
+//! # use immense::*;
+//! # struct RandCube;
+//! # impl ToRule for RandCube {
+//! #     fn to_rule_with(&self, _ctx: &mut Context) -> Rule { cube() }
+//! # }
+//! let meshes = Rule::new().push(Replicate::n(4, Tf::ty(1.0)), RandCube {})
+//!                         .generate_with_seed(42);
+//! ````
+//!
+//! ## Deformers
+//!
+//! Transforms move meshes rigidly, up to scale and shear. To bend, twist, taper or roughen the
+//! meshes under a rule, wrap it with [Rule::deform][rule::Rule::deform] and a
+//! [Deformer][rule::Deformer], which moves each vertex as a function of its position.
+//!
+//! ````
+//! # use immense::*;
+//! let tower = Rule::new().push(Replicate::n(24, Tf::ty(1.0)), cube());
+//! let rule = Rule::deform(Deformer::noise(0.05, 3.0, 3, 7),
+//!                         Rule::deform(Deformer::twist(5.0), tower));
+//! ````
+//!
 //! # Color
 //!
 //! immense can export some colors alongside your mesh, by linking the object file output to an
@@ -215,24 +267,87 @@
 //!
 //! ````
 //! # use immense::*;
-//! # use std::rc::Rc;
-//! let sphere: Rc<Mesh> = sphere(/*resolution=*/4);
+//! # use std::sync::Arc;
+//! let sphere: Arc<Mesh> = sphere(/*resolution=*/4);
 //! let rule = Rule::new().push(Tf::s(2.0), sphere);
 //! ````
+//!
+//! Meshes modeled in other tools can be loaded with [read_obj][self::read_obj],
+//! [read_stl][self::read_stl] or [read_ply][self::read_ply], and used the same way. Surfaces
+//! described by a function of two parameters can be sampled into a mesh with
+//! [ParametricSurface][self::mesh::ParametricSurface], and shapes described by a signed distance
+//! function with [DistanceField][self::mesh::DistanceField]. Prisms and surfaces of revolution
+//! can be made from 2D profiles with [Mesh::extrude][self::mesh::Mesh::extrude] and
+//! [Mesh::lathe][self::mesh::Mesh::lathe], and a cross-section can be swept through a sequence of
+//! transforms into one continuous tube with [Sweep][self::mesh::Sweep].
+//!
+//! # EisenScript
+//!
+//! Scripts written for [Structure Synth](http://structuresynth.sourceforge.net/) can be loaded
+//! with [EisenScript::parse][self::EisenScript::parse]. The result is a rule like any other, and
+//! its `set` directives are available as an
+//! [ExpansionConfig][self::rule::ExpansionConfig] for
+//! [generate_with][self::rule::Rule::generate_with]:
+//!
+//! ````
+//! # use immense::*;
+//! # let _ = || -> Result<(), Error> {
+//! let script = EisenScript::parse("set maxdepth 20 R1 rule R1 { box { x 1 s 0.9 } R1 }")?;
+//! let meshes = script.to_rule().generate_with(script.expansion_config());
+//! # Ok(())
+//! # };
+//! ````
+//!
+//! # L-systems
+//!
+//! Branching plants and fractal curves are often easiest to describe as
+//! [L-systems](https://en.wikipedia.org/wiki/L-system). An [LSystem][self::LSystem] rewrites its
+//! axiom by its productions and draws the result with a turtle, invoking a segment rule for each
+//! `F`:
+//!
+//! ````
+//! # use immense::*;
+//! let bush = LSystem::new("F")
+//!     .rule('F', "FF-[-F+F+F]+[+F-F-F]")
+//!     .angle(22.5)
+//!     .generations(3)
+//!     .segment(rule![Tf::ty(0.5) => cylinder(8)]);
+//! let meshes = bush.to_rule().generate();
+//! ````
+//!
+//! # Rule Descriptions
+//!
+//! Rules built in Rust can't be saved, since they are made of [ToRule][self::rule::ToRule]
+//! implementations. To cache or share a rule tree, describe it as plain data with a
+//! [RuleDescription][self::RuleDescription], which can be serialized with
+//! [serde](https://serde.rs/) and [compiled][self::RuleDescription::compile] into a rule.
 
+mod description;
+mod eisenscript;
 mod error;
 mod export;
+mod import;
+mod lsystem;
 mod mesh;
 mod rule;
 
+pub use crate::description::{
+    DescriptionError, InvocationDescription, PrimitiveDescription, RuleDescription,
+    SubruleDescription, TransformDescription,
+};
+pub use crate::eisenscript::{EisenScript, ParseError};
 pub use crate::error::Error;
-pub use crate::export::{ExportConfig, MeshGrouping};
-pub use crate::mesh::{vertex, Mesh, Vertex};
+pub use crate::export::{ExportConfig, GltfConfig, MeshGrouping, StlFormat};
+pub use crate::import::ImportError;
+pub use crate::lsystem::{LSystem, Module};
+pub use crate::mesh::{vertex, DistanceField, Mesh, ParametricSurface, SurfaceEdge, Sweep, Vertex};
 pub use crate::rule::*;
+pub use nalgebra::Matrix4;
 pub use palette::{Hsv, RgbHue};
 
 use crate::error::Result;
 use std::io;
+use std::sync::Arc;
 
 /// Writes out meshes as a Wavefront object file to the given [Write][io::Write] sink.
 pub fn write_meshes(
@@ -243,3 +358,105 @@ pub fn write_meshes(
     export::write_meshes(config, meshes, sink)?;
     Ok(())
 }
+
+/// Writes out meshes as an STL file to the given [Write][io::Write] sink.
+///
+/// Faces are triangulated and each triangle is given a facet normal computed from its transformed
+/// vertices.
+///
+/// ````
+/// # use failure::{Error};
+/// # fn main() -> Result<(), Error> {
+/// use immense::*;
+///
+/// let meshes = Rule::new().push(Replicate::n(3, Tf::ty(1.1)), cube()).generate();
+/// let mut stl = vec![];
+/// write_stl(StlFormat::Binary, meshes, &mut stl)?;
+/// assert_eq!(stl.len(), 84 + 3 * 12 * 50);
+/// # Ok(())
+/// # }
+/// ````
+pub fn write_stl(
+    format: StlFormat,
+    meshes: impl Iterator<Item = OutputMesh>,
+    sink: impl io::Write,
+) -> Result<()> {
+    export::write_stl(format, meshes, sink)?;
+    Ok(())
+}
+
+/// Writes out meshes as a glTF 2.0 asset to the given [Write][io::Write] sink.
+///
+/// Each distinct color becomes a PBR material, and each group of meshes under the
+/// [MeshGrouping][crate::export::MeshGrouping] policy becomes a node in the scene. Depending on
+/// [buffer_file][crate::export::GltfConfig::buffer_file], the sink receives either a JSON document
+/// and the vertex data goes to a separate buffer file, or everything is written to the sink as a
+/// single binary .glb file.
+///
+/// If your rule invokes the same meshes many times, set
+/// [instanced][crate::export::GltfConfig::instanced] to write each mesh only once.
+///
+/// ````
+/// # use failure::{Error};
+/// # let _ = || -> Result<(), Error> {
+/// use immense::*;
+/// use std::fs::File;
+///
+/// let meshes = Rule::new().push(Replicate::n(3, Tf::ty(1.1)), cube()).generate();
+/// let mut output_file = File::create("my_mesh.gltf")?;
+/// write_gltf(
+///     GltfConfig {
+///         grouping: MeshGrouping::ByColor,
+///         buffer_file: Some(String::from("my_mesh.bin")),
+///         instanced: false,
+///     },
+///     meshes,
+///     &mut output_file,
+/// )?;
+/// # Ok(())
+/// # };
+/// ````
+pub fn write_gltf(
+    config: GltfConfig,
+    meshes: impl Iterator<Item = OutputMesh>,
+    sink: impl io::Write,
+) -> Result<()> {
+    export::write_gltf(config, meshes, sink)?;
+    Ok(())
+}
+
+/// Reads a mesh from Wavefront object file text, to use as a rule.
+///
+/// Positions, texture coordinates, normals, and faces are read from all groups in the file and
+/// combined into one mesh. Like any custom mesh, read it once and share the [Arc][Arc].
+///
+/// ````
+/// # use failure::{Error};
+/// # let _ = || -> Result<(), Error> {
+/// use immense::*;
+/// use std::fs::File;
+///
+/// let chair = read_obj(File::open("chair.obj")?)?;
+/// let meshes = Rule::new().push(Replicate::n(8, Tf::tx(2.0)), chair).generate();
+/// # Ok(())
+/// # };
+/// ````
+pub fn read_obj(source: impl io::Read) -> Result<Arc<Mesh>> {
+    Ok(import::read_obj(source)?)
+}
+
+/// Reads a mesh from a binary or ASCII STL file, to use as a rule.
+///
+/// Corners at the same position are welded into shared vertices, and smooth vertex normals are
+/// computed from the triangles.
+pub fn read_stl(source: impl io::Read) -> Result<Arc<Mesh>> {
+    Ok(import::read_stl(source)?)
+}
+
+/// Reads a mesh from an ASCII or binary PLY file, to use as a rule.
+///
+/// Vertex positions, normals, and texture coordinates are read along with the faces. If the file
+/// has no normals, smooth vertex normals are computed from the faces.
+pub fn read_ply(source: impl io::Read) -> Result<Arc<Mesh>> {
+    Ok(import::read_ply(source)?)
+}
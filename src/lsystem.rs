@@ -0,0 +1,391 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+//! [L-systems](https://en.wikipedia.org/wiki/L-system) interpreted with turtle graphics.
+
+use crate::mesh::Vertex;
+use crate::rule::{choose_weighted, cube, Context, Rule, ToRule, Transform};
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// A symbol of an L-system string, with the parameters of parametric L-systems.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Module {
+    pub symbol: char,
+    pub params: Vec<f32>,
+}
+
+impl Module {
+    /// A module with the given parameters.
+    pub fn new(symbol: char, params: &[f32]) -> Self {
+        Self {
+            symbol,
+            params: params.to_vec(),
+        }
+    }
+
+    /// The modules of a string of symbols without parameters. Whitespace is ignored.
+    pub fn parse(symbols: &str) -> Vec<Module> {
+        symbols
+            .chars()
+            .filter(|c| !c.is_whitespace())
+            .map(|symbol| Module::new(symbol, &[]))
+            .collect()
+    }
+}
+
+type ParametricSuccessor = dyn Fn(&[f32]) -> Option<Vec<Module>> + Send + Sync;
+
+#[derive(Clone)]
+enum Successor {
+    Fixed(Vec<Module>),
+    Parametric(Arc<ParametricSuccessor>),
+}
+
+#[derive(Clone)]
+struct Production {
+    weight: f32,
+    successor: Successor,
+}
+
+/// An L-system, which rewrites an axiom by its productions for a number of generations and then
+/// draws the result with a turtle.
+///
+/// Each generation replaces every module of the string with the successor of a production for
+/// its symbol, or leaves it alone if there is none:
+///
+/// * A symbol with one [rule][LSystem::rule] is always replaced by its successor.
+/// * A symbol with several rules, or [weighted rules][LSystem::weighted_rule], is replaced by one
+///   of them chosen by weight, drawn from the expansion's [Context][crate::rule::Context] so
+///   the result is reproducible when seeded.
+/// * A [parametric rule][LSystem::parametric_rule] is a function of the module's parameters
+///   which may return a successor computed from them, or `None` if its condition does not hold.
+///
+/// The turtle starts at the origin heading up the y axis, and interprets these symbols:
+///
+/// | Symbol | Action |
+/// |--------|--------|
+/// | `F`    | Invoke the [segment][LSystem::segment] rule and move forward 1 |
+/// | `f`    | Move forward 1 |
+/// | `+` `-` | Turn left or right about the z axis |
+/// | `&` `^` | Pitch down or up about the x axis |
+/// | `\` `/` | Roll left or right about the heading |
+/// | <code>&#124;</code> | Turn around |
+/// | `[` `]` | Save and restore the turtle's state |
+///
+/// All rotations are relative to the turtle. They turn by the system's [angle][LSystem::angle],
+/// or by the module's first parameter in degrees if it has one. `F(l)` and `f(l)` move `l`
+/// instead of 1, and `F(l)` stretches its segment to match. Other symbols are not drawn.
+///
+/// ````
+/// # use immense::*;
+/// let plant = LSystem::new("X")
+///     .rule('X', "F+[[X]-X]-F[-FX]+X")
+///     .rule('F', "FF")
+///     .angle(25.0)
+///     .generations(4);
+///
+/// let meshes = plant.to_rule().generate();
+/// ````
+///
+/// A parametric system whose branches shrink until they are too short to grow:
+///
+/// ````
+/// # use immense::*;
+/// let tree = LSystem::with_axiom(vec![Module::new('A', &[1.0])])
+///     .parametric_rule('A', |params| {
+///         let length = params[0];
+///         if length < 0.2 {
+///             return None;
+///         }
+///         let branch = vec![Module::new('A', &[length * 0.6])];
+///         let mut successor = vec![Module::new('F', &[length]), Module::new('[', &[])];
+///         successor.push(Module::new('+', &[30.0]));
+///         successor.extend(branch.clone());
+///         successor.extend(Module::parse("]["));
+///         successor.push(Module::new('-', &[30.0]));
+///         successor.extend(branch);
+///         successor.push(Module::new(']', &[]));
+///         Some(successor)
+///     })
+///     .generations(8)
+///     .segment(rule![Tf::s(0.2) => icosphere()]);
+///
+/// # assert_eq!(tree.to_rule().generate().count(), 15);
+/// ````
+#[derive(Clone)]
+pub struct LSystem {
+    axiom: Vec<Module>,
+    productions: HashMap<char, Vec<Production>>,
+    generations: usize,
+    angle: f32,
+    segment: Rule,
+}
+
+impl LSystem {
+    /// Starts an L-system from an axiom of symbols without parameters.
+    pub fn new(axiom: &str) -> Self {
+        Self::with_axiom(Module::parse(axiom))
+    }
+
+    /// Starts an L-system from an axiom of modules, which may have parameters.
+    pub fn with_axiom(axiom: Vec<Module>) -> Self {
+        Self {
+            axiom,
+            productions: HashMap::new(),
+            generations: 1,
+            angle: 25.0,
+            segment: Rule::new().push(
+                vec![Transform::ty(0.5), Transform::sby(0.1, 1.0, 0.1)],
+                cube(),
+            ),
+        }
+    }
+
+    /// Adds a production replacing `symbol` with a string of symbols.
+    pub fn rule(self, symbol: char, successor: &str) -> Self {
+        self.weighted_rule(symbol, 1.0, successor)
+    }
+
+    /// Adds a production replacing `symbol` with a string of symbols, chosen by weight among the
+    /// symbol's other productions.
+    pub fn weighted_rule(mut self, symbol: char, weight: f32, successor: &str) -> Self {
+        self.productions
+            .entry(symbol)
+            .or_default()
+            .push(Production {
+                weight,
+                successor: Successor::Fixed(Module::parse(successor)),
+            });
+        self
+    }
+
+    /// Adds a production replacing modules of `symbol` with the modules `successor` returns for
+    /// their parameters. If it returns `None` the production does not apply to that module.
+    pub fn parametric_rule(
+        mut self,
+        symbol: char,
+        successor: impl Fn(&[f32]) -> Option<Vec<Module>> + Send + Sync + 'static,
+    ) -> Self {
+        self.productions
+            .entry(symbol)
+            .or_default()
+            .push(Production {
+                weight: 1.0,
+                successor: Successor::Parametric(Arc::new(successor)),
+            });
+        self
+    }
+
+    /// Sets the number of times the axiom is rewritten. The default is 1.
+    pub fn generations(mut self, generations: usize) -> Self {
+        self.generations = generations;
+        self
+    }
+
+    /// Sets the angle in degrees the turtle turns by. The default is 25.
+    pub fn angle(mut self, angle: f32) -> Self {
+        self.angle = angle;
+        self
+    }
+
+    /// Sets the rule invoked for each `F`, which should span from the origin to 1 up the y axis.
+    /// The default is a thin beam made of a [cube][crate::rule::cube].
+    pub fn segment(mut self, segment: impl ToRule) -> Self {
+        self.segment = Rule::new().push(None, segment);
+        self
+    }
+
+    /// Rewrites the axiom for each generation.
+    fn expand(&self, ctx: &mut Context) -> Vec<Module> {
+        let mut string = self.axiom.clone();
+        for _ in 0..self.generations {
+            let mut next = Vec::with_capacity(string.len());
+            for module in string {
+                match self.rewrite(&module, ctx) {
+                    Some(successor) => next.extend(successor),
+                    None => next.push(module),
+                }
+            }
+            string = next;
+        }
+        string
+    }
+
+    fn rewrite(&self, module: &Module, ctx: &mut Context) -> Option<Vec<Module>> {
+        let productions = self.productions.get(&module.symbol)?;
+        let candidates: Vec<(f32, Vec<Module>)> = productions
+            .iter()
+            .filter_map(|production| match production.successor {
+                Successor::Fixed(ref successor) => Some((production.weight, successor.clone())),
+                Successor::Parametric(ref successor) => {
+                    successor(&module.params).map(|successor| (production.weight, successor))
+                }
+            })
+            .collect();
+        choose_weighted(&candidates, ctx).cloned()
+    }
+
+    /// Draws a string with the turtle.
+    fn interpret(&self, string: &[Module]) -> Rule {
+        let mut rule = Rule::new();
+        let mut turtle = Transform::default();
+        let mut stack = vec![];
+        for module in string {
+            let angle = module.params.first().cloned().unwrap_or(self.angle);
+            let length = module.params.first().cloned().unwrap_or(1.0);
+            let turn = |axis, angle| turtle.cons(Transform::r_axis(axis, angle));
+            turtle = match module.symbol {
+                'F' => {
+                    rule = rule.push(
+                        turtle.cons(Transform::sby(1.0, length, 1.0)),
+                        self.segment.clone(),
+                    );
+                    turtle.cons(Transform::ty(length))
+                }
+                'f' => turtle.cons(Transform::ty(length)),
+                '+' => turn(Vertex::z(), angle),
+                '-' => turn(Vertex::z(), -angle),
+                '&' => turn(Vertex::x(), angle),
+                '^' => turn(Vertex::x(), -angle),
+                '\\' => turn(Vertex::y(), angle),
+                '/' => turn(Vertex::y(), -angle),
+                '|' => turn(Vertex::z(), 180.0),
+                '[' => {
+                    stack.push(turtle);
+                    turtle
+                }
+                ']' => stack.pop().unwrap_or(turtle),
+                _ => turtle,
+            };
+        }
+        rule
+    }
+}
+
+impl ToRule for LSystem {
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule {
+        self.interpret(&self.expand(ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::Tf;
+
+    /// The start of each segment drawn, in order, with the segment replaced by a point-like cube.
+    fn starts(system: LSystem, seed: u64) -> Vec<[f32; 3]> {
+        let system = system.segment(Rule::new().push(Tf::s(0.0), cube()));
+        let mut starts: Vec<[f32; 3]> = system
+            .to_rule_with(&mut Context::new(seed))
+            .generate_with_seed(seed)
+            .map(|mesh| {
+                let v = mesh.vertices().next().expect("a vertex");
+                [v.x, v.y, v.z]
+            })
+            .collect();
+        // Expansion yields the segments last first.
+        starts.reverse();
+        starts
+    }
+
+    fn assert_near(actual: &[[f32; 3]], expected: &[[f32; 3]]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            for i in 0..3 {
+                assert!(
+                    (a[i] - e[i]).abs() < 0.0001,
+                    "{:?} != {:?}",
+                    actual,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_rules_rewrite_every_generation() {
+        let koch = LSystem::new("F").rule('F', "F+F-F-F+F").generations(2);
+        assert_eq!(koch.to_rule().generate().count(), 25);
+        assert_eq!(koch.generations(0).to_rule().generate().count(), 1);
+    }
+
+    #[test]
+    fn turtle_branches_and_turns() {
+        let system = LSystem::new("F[+F]F[&F]").angle(90.0).generations(0);
+        assert_near(
+            &starts(system, 0),
+            &[
+                [0.0, 0.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 1.0, 0.0],
+                [0.0, 2.0, 0.0],
+            ],
+        );
+
+        // Turning left moves the turtle along -x, and pitching down moves it along +z.
+        let system = LSystem::new("+FF").angle(90.0).generations(0);
+        assert_near(&starts(system, 0), &[[0.0, 0.0, 0.0], [-1.0, 0.0, 0.0]]);
+        let mut axiom = Module::parse("&f");
+        axiom.push(Module::new('f', &[2.0]));
+        axiom.push(Module::new('F', &[]));
+        let system = LSystem::with_axiom(axiom).angle(90.0).generations(0);
+        assert_near(&starts(system, 0), &[[0.0, 0.0, 3.0]]);
+    }
+
+    #[test]
+    fn segments_stretch_with_their_length() {
+        let system = LSystem::with_axiom(vec![Module::new('F', &[2.0])]);
+        let mesh = system.to_rule().generate().next().expect("a segment");
+        let top = mesh.vertices().map(|v| v.y).fold(0.0, f32::max);
+        assert_eq!(top, 2.0);
+        assert!(mesh.vertices().all(|v| v.x.abs() <= 0.05));
+    }
+
+    #[test]
+    fn stochastic_rules_are_seeded() {
+        let system = LSystem::new("A")
+            .weighted_rule('A', 1.0, "FA")
+            .weighted_rule('A', 1.0, "fA")
+            .generations(32);
+        let counts: Vec<usize> = (0..4)
+            .map(|seed| starts(system.clone(), seed).len())
+            .collect();
+        assert_eq!(
+            counts,
+            (0..4)
+                .map(|seed| starts(system.clone(), seed).len())
+                .collect::<Vec<_>>()
+        );
+        assert!(counts.iter().all(|&count| count > 0 && count < 32));
+    }
+
+    #[test]
+    fn parametric_rules_apply_while_their_condition_holds() {
+        let system = LSystem::with_axiom(vec![Module::new('A', &[5.0])])
+            .parametric_rule('A', |params| {
+                if params[0] > 0.0 {
+                    Some(vec![
+                        Module::new('F', &[]),
+                        Module::new('A', &[params[0] - 1.0]),
+                    ])
+                } else {
+                    None
+                }
+            })
+            .generations(10);
+        assert_eq!(system.to_rule().generate().count(), 5);
+    }
+}
@@ -12,11 +12,21 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
+mod parametric;
+pub(crate) mod primitives;
+mod profile;
+mod sdf;
+
+pub use self::parametric::{ParametricSurface, SurfaceEdge};
+pub use self::profile::Sweep;
+pub use self::sdf::DistanceField;
+
 use crate::Tf;
 use genmesh::generators::{IcoSphere, IndexedPolygon, SharedVertex};
 use lazy_static::lazy_static;
 use nalgebra::base::dimension::{U1, U4};
-use std::rc::Rc;
+use nalgebra::Vector3;
+use std::sync::Arc;
 
 /// A type for custom mesh vertices. Initialize with [vertex][self::vertex].
 pub type Vertex = nalgebra::Matrix<f32, U4, U1, nalgebra::MatrixArray<f32, U4, U1>>;
@@ -45,6 +55,35 @@ pub(crate) fn sphere_of_resolution(resolution: usize) -> Mesh {
     )
 }
 
+/// Computes a normal for each vertex by averaging the normals of the faces around it, weighted by
+/// the angle each face makes at the vertex so the result does not depend on how faces are split.
+/// Faces are wound counterclockwise and their vertex indices start at 1.
+pub(crate) fn vertex_normals(vertices: &[Vertex], faces: &[Vec<usize>]) -> Vec<Vertex> {
+    let mut normals = vec![Vector3::zeros(); vertices.len()];
+    for face in faces {
+        let corner = |i: usize| vertices[face[i % face.len()] - 1].xyz();
+        let face_normal = (1..face.len().saturating_sub(1))
+            .map(|i| (corner(i) - corner(0)).cross(&(corner(i + 1) - corner(0))))
+            .sum::<Vector3<f32>>();
+        let face_normal = match face_normal.try_normalize(0.0) {
+            Some(face_normal) => face_normal,
+            None => continue,
+        };
+        for i in 0..face.len() {
+            let previous = corner(i + face.len() - 1) - corner(i);
+            let next = corner(i + 1) - corner(i);
+            normals[face[i] - 1] += face_normal * previous.angle(&next);
+        }
+    }
+    normals
+        .into_iter()
+        .map(|normal| {
+            let normal = normal.try_normalize(0.0).unwrap_or(normal);
+            Vertex::new(normal.x, normal.y, normal.z, 0.0)
+        })
+        .collect()
+}
+
 lazy_static! {
     static ref CUBE_MESH: Mesh = Mesh::new(
         vec![
@@ -68,6 +107,10 @@ lazy_static! {
         ]
     );
     static ref ICO_SPHERE: Mesh = sphere_of_resolution(0);
+    static ref QUAD_MESH: Mesh = primitives::quad();
+    static ref TRIANGLE_MESH: Mesh = primitives::triangle();
+    static ref LINE_MESH: Mesh =
+        primitives::polyline(vec![vertex(-0.5, 0.0, 0.0), vertex(0.5, 0.0, 0.0)]);
 }
 
 /// A custom mesh definition described by a set of vertices, normals, and faces.
@@ -81,7 +124,9 @@ lazy_static! {
 pub struct Mesh {
     vertices: Vec<Vertex>,
     normals: Option<Vec<Vertex>>,
+    uvs: Option<Vec<[f32; 2]>>,
     faces: Vec<Vec<usize>>,
+    lines: Vec<Vec<usize>>,
 }
 
 impl Mesh {
@@ -90,8 +135,8 @@ impl Mesh {
         vertices: Vec<Vertex>,
         normals: Option<Vec<Vertex>>,
         faces: Vec<Vec<usize>>,
-    ) -> Rc<Self> {
-        Rc::new(Self::new(vertices, normals, faces))
+    ) -> Arc<Self> {
+        Arc::new(Self::new(vertices, normals, faces))
     }
 
     pub(crate) fn new(
@@ -102,7 +147,22 @@ impl Mesh {
         Self {
             vertices,
             normals,
+            uvs: None,
             faces: faces,
+            lines: vec![],
+        }
+    }
+
+    /// Sets polylines through the mesh's vertices, as lists of vertex indices starting at 1.
+    pub(crate) fn with_lines(self, lines: Vec<Vec<usize>>) -> Self {
+        Self { lines, ..self }
+    }
+
+    /// Sets texture coordinates for the mesh. There must be one for each vertex.
+    pub(crate) fn with_uvs(self, uvs: Vec<[f32; 2]>) -> Self {
+        Self {
+            uvs: Some(uvs),
+            ..self
         }
     }
 
@@ -114,22 +174,36 @@ impl Mesh {
         self.normals.as_ref().map(|ns| ns.as_slice())
     }
 
+    pub(crate) fn uvs(&self) -> Option<&[[f32; 2]]> {
+        self.uvs.as_deref()
+    }
+
     pub(crate) fn faces<'a>(&'a self) -> impl Iterator<Item = &'a [usize]> {
         self.faces.iter().map(|f| f.as_slice())
     }
+
+    pub(crate) fn lines(&self) -> impl Iterator<Item = &[usize]> {
+        self.lines.iter().map(|l| l.as_slice())
+    }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
 pub enum PrimitiveMesh {
     Cube,
     IcoSphere,
+    Quad,
+    Triangle,
+    Line,
 }
 
 impl PrimitiveMesh {
     pub(crate) fn mesh(&self) -> &'static Mesh {
         match *self {
-            PrimitiveMesh::Cube => &*CUBE_MESH,
-            PrimitiveMesh::IcoSphere => &*ICO_SPHERE,
+            PrimitiveMesh::Cube => &CUBE_MESH,
+            PrimitiveMesh::IcoSphere => &ICO_SPHERE,
+            PrimitiveMesh::Quad => &QUAD_MESH,
+            PrimitiveMesh::Triangle => &TRIANGLE_MESH,
+            PrimitiveMesh::Line => &LINE_MESH,
         }
     }
 }
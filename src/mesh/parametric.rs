@@ -0,0 +1,331 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::mesh::{vertex_normals, Mesh, Vertex};
+use nalgebra::Vector3;
+use std::sync::Arc;
+
+/// How a parametric surface joins up at the ends of one of its parameters' ranges.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum SurfaceEdge {
+    /// The surface has a boundary at the ends of the range.
+    Open,
+    /// The end of the range meets the start, like the ring of a torus.
+    Closed,
+    /// The end of the range meets the start with the other parameter reversed, like a Möbius
+    /// strip.
+    Twisted,
+}
+
+/// The default is [SurfaceEdge::Open][SurfaceEdge::Open].
+impl Default for SurfaceEdge {
+    fn default() -> SurfaceEdge {
+        SurfaceEdge::Open
+    }
+}
+
+/// A builder for meshes sampled from a parametric surface `f(u, v) -> vertex`.
+///
+/// The surface is sampled on a grid over the `u` and `v` ranges, which are both `0..1` unless set
+/// otherwise. Normals are computed from the surface's partial derivatives by finite differences,
+/// unless an exact normal function is given with [normals][ParametricSurface::normals], and point
+/// along `∂f/∂u × ∂f/∂v`. Each vertex also gets texture coordinates from its position on the
+/// grid, from 0 to 1 along each parameter.
+///
+/// Closed and twisted edges share their vertices so the mesh is watertight, which means texture
+/// coordinates wrap back to 0 across the last row of faces along that parameter.
+///
+/// ````
+/// # use immense::*;
+/// use std::f32::consts::PI;
+///
+/// let mobius = ParametricSurface::new(|u, v| {
+///     let (angle, offset) = (u * 2.0 * PI, v - 0.5);
+///     let radius = 1.0 + offset * (angle / 2.0).cos();
+///     vertex(radius * angle.cos(), offset * (angle / 2.0).sin(), radius * angle.sin())
+/// })
+/// .segments(64, 4)
+/// .u_edge(SurfaceEdge::Twisted)
+/// .build();
+///
+/// let rule = Rule::new().push(Tf::s(0.5), mobius);
+/// ````
+pub struct ParametricSurface<F> {
+    surface: F,
+    normal: Option<Box<dyn Fn(f32, f32) -> Vertex>>,
+    u: Axis,
+    v: Axis,
+}
+
+#[derive(Copy, Clone)]
+struct Axis {
+    start: f32,
+    end: f32,
+    segments: usize,
+    edge: SurfaceEdge,
+}
+
+impl Axis {
+    /// The number of distinct samples along the axis.
+    fn samples(&self) -> usize {
+        match self.edge {
+            SurfaceEdge::Open => self.segments + 1,
+            SurfaceEdge::Closed | SurfaceEdge::Twisted => self.segments,
+        }
+    }
+
+    fn value(&self, i: usize) -> f32 {
+        self.start + (self.end - self.start) * i as f32 / self.segments as f32
+    }
+
+    /// The sample at the same place along the axis, counting from the other end.
+    fn mirror(&self, i: usize) -> usize {
+        match self.edge {
+            SurfaceEdge::Open => self.segments - i,
+            SurfaceEdge::Closed | SurfaceEdge::Twisted => (self.segments - i) % self.segments,
+        }
+    }
+
+    /// Moves `t` a small step toward the middle of the range.
+    fn nudge(&self, t: f32) -> f32 {
+        let middle = (self.start + self.end) / 2.0;
+        let step = (self.end - self.start).abs() / self.segments as f32 * 0.01;
+        if t < middle {
+            t + step
+        } else {
+            t - step
+        }
+    }
+
+    /// Differentiates `f` at `t`, stepping only inside the range at open edges.
+    fn derivative(&self, t: f32, f: impl Fn(f32) -> Vertex) -> Vector3<f32> {
+        let step = (self.end - self.start) / self.segments as f32 * 0.01;
+        let (low, high) = match self.edge {
+            SurfaceEdge::Open => {
+                let (min, max) = (self.start.min(self.end), self.start.max(self.end));
+                let clamp = |t: f32| t.max(min).min(max);
+                (clamp(t - step), clamp(t + step))
+            }
+            SurfaceEdge::Closed | SurfaceEdge::Twisted => (t - step, t + step),
+        };
+        (f(high) - f(low)).xyz() / (high - low)
+    }
+}
+
+impl<F: Fn(f32, f32) -> Vertex> ParametricSurface<F> {
+    /// Starts a surface from a function of `u` and `v` returning a [vertex][crate::mesh::vertex].
+    pub fn new(surface: F) -> Self {
+        let axis = Axis {
+            start: 0.0,
+            end: 1.0,
+            segments: 32,
+            edge: SurfaceEdge::Open,
+        };
+        Self {
+            surface,
+            normal: None,
+            u: axis,
+            v: axis,
+        }
+    }
+
+    /// Sets the range `u` is sampled over.
+    pub fn u_range(mut self, start: f32, end: f32) -> Self {
+        self.u.start = start;
+        self.u.end = end;
+        self
+    }
+
+    /// Sets the range `v` is sampled over.
+    pub fn v_range(mut self, start: f32, end: f32) -> Self {
+        self.v.start = start;
+        self.v.end = end;
+        self
+    }
+
+    /// Sets the number of faces along `u` and `v`. The default is 32 each.
+    pub fn segments(mut self, u: usize, v: usize) -> Self {
+        self.u.segments = u.max(1);
+        self.v.segments = v.max(1);
+        self
+    }
+
+    /// Sets how the surface joins up at the ends of the `u` range.
+    pub fn u_edge(mut self, edge: SurfaceEdge) -> Self {
+        self.u.edge = edge;
+        self
+    }
+
+    /// Sets how the surface joins up at the ends of the `v` range.
+    pub fn v_edge(mut self, edge: SurfaceEdge) -> Self {
+        self.v.edge = edge;
+        self
+    }
+
+    /// Sets a function returning the exact normal of the surface at `u` and `v`, to use instead
+    /// of finite differences. Normals are normalized, and their w is ignored.
+    pub fn normals(mut self, normal: impl Fn(f32, f32) -> Vertex + 'static) -> Self {
+        self.normal = Some(Box::new(normal));
+        self
+    }
+
+    /// Samples the surface into a mesh.
+    pub fn build(self) -> Arc<Mesh> {
+        let (u, v) = (self.u, self.v);
+        let mut vertices = vec![];
+        let mut uvs = vec![];
+        for i in 0..u.samples() {
+            for j in 0..v.samples() {
+                vertices.push((self.surface)(u.value(i), v.value(j)));
+                uvs.push([i as f32 / u.segments as f32, j as f32 / v.segments as f32]);
+            }
+        }
+
+        let index = |i: usize, j: usize| {
+            let (mut i, mut j) = (i, j);
+            if i == u.samples() {
+                i = 0;
+                if u.edge == SurfaceEdge::Twisted {
+                    j = v.mirror(j);
+                }
+            }
+            if j == v.samples() {
+                j = 0;
+                if v.edge == SurfaceEdge::Twisted {
+                    i = u.mirror(i);
+                }
+            }
+            1 + i * v.samples() + j
+        };
+        let faces: Vec<Vec<usize>> = (0..u.segments)
+            .flat_map(|i| (0..v.segments).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                vec![
+                    index(i, j),
+                    index(i + 1, j),
+                    index(i + 1, j + 1),
+                    index(i, j + 1),
+                ]
+            })
+            .collect();
+
+        // Where the surface pinches to a point, like the poles of a sphere, one derivative
+        // vanishes, so the normal is taken from just inside the grid instead.
+        let normal_at = |s: f32, t: f32| match self.normal {
+            Some(ref normal) => normal(s, t).xyz().try_normalize(0.0),
+            None => {
+                let du = u.derivative(s, |s| (self.surface)(s, t));
+                let dv = v.derivative(t, |t| (self.surface)(s, t));
+                let scale = (du.norm() + dv.norm()).powi(2);
+                Some(du.cross(&dv)).filter(|normal| normal.norm() > scale * 1.0e-5)
+            }
+        };
+        let mut fallback = None;
+        let mut normals = Vec::with_capacity(vertices.len());
+        for i in 0..u.samples() {
+            for j in 0..v.samples() {
+                let (s, t) = (u.value(i), v.value(j));
+                let normal = normal_at(s, t)
+                    .or_else(|| normal_at(u.nudge(s), v.nudge(t)))
+                    .and_then(|normal| normal.try_normalize(0.0));
+                normals.push(match normal {
+                    Some(normal) => Vertex::new(normal.x, normal.y, normal.z, 0.0),
+                    None => fallback.get_or_insert_with(|| vertex_normals(&vertices, &faces))
+                        [normals.len()],
+                });
+            }
+        }
+
+        Arc::new(Mesh::new(vertices, Some(normals), faces).with_uvs(uvs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::vertex;
+    use std::f32::consts::PI;
+
+    fn sphere(u: f32, v: f32) -> Vertex {
+        let (theta, phi) = (u * 2.0 * PI, (v - 0.5) * PI);
+        vertex(phi.cos() * theta.cos(), phi.sin(), -phi.cos() * theta.sin())
+    }
+
+    fn assert_normals_point_out(mesh: &Mesh) {
+        for (v, n) in mesh.vertices().iter().zip(mesh.normals().expect("normals")) {
+            assert!((n.norm() - 1.0).abs() < 0.0001);
+            assert!(
+                (n.xyz() - v.xyz().normalize()).norm() < 0.01,
+                "normal {:?} at {:?}",
+                n,
+                v
+            );
+        }
+    }
+
+    #[test]
+    fn finite_difference_normals_match_the_sphere() {
+        let mesh = ParametricSurface::new(sphere)
+            .segments(16, 8)
+            .u_edge(SurfaceEdge::Closed)
+            .build();
+        assert_eq!(mesh.vertices().len(), 16 * 9);
+        assert_eq!(mesh.faces().count(), 16 * 8);
+        assert_normals_point_out(&mesh);
+    }
+
+    #[test]
+    fn analytic_normals_are_used() {
+        let mesh = ParametricSurface::new(sphere)
+            .segments(16, 8)
+            .normals(|u, v| sphere(u, v) - vertex(0.0, 0.0, 0.0))
+            .build();
+        assert_eq!(mesh.vertices().len(), 17 * 9);
+        assert_normals_point_out(&mesh);
+    }
+
+    #[test]
+    fn faces_follow_normals() {
+        let mesh = ParametricSurface::new(sphere)
+            .segments(8, 8)
+            .u_edge(SurfaceEdge::Closed)
+            .build();
+        let (vertices, normals) = (mesh.vertices(), mesh.normals().unwrap());
+        for face in mesh.faces() {
+            let (a, b, d) = (face[0] - 1, face[1] - 1, face[3] - 1);
+            let facet = (vertices[b] - vertices[a])
+                .xyz()
+                .cross(&(vertices[d] - vertices[a]).xyz());
+            // Faces at the poles have corners on top of each other.
+            if facet.norm() < 1.0e-6 {
+                continue;
+            }
+            assert!(facet.dot(&normals[a].xyz()) > 0.0);
+        }
+    }
+
+    #[test]
+    fn twisted_edges_join_reversed() {
+        let mesh = ParametricSurface::new(|u, v| vertex(u, v, 0.0))
+            .segments(4, 2)
+            .u_edge(SurfaceEdge::Twisted)
+            .build();
+        assert_eq!(mesh.vertices().len(), 4 * 3);
+        let last_column: Vec<&[usize]> = mesh.faces().skip(3 * 2).collect();
+        // The last faces join v = 0 at u = 0.75 to v = 1 at u = 0.
+        assert_eq!(last_column[0], &[10, 3, 2, 11][..]);
+        assert_eq!(last_column[1], &[11, 2, 1, 12][..]);
+        assert_eq!(mesh.uvs().expect("uvs")[11], [0.75, 1.0]);
+    }
+}
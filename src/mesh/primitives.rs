@@ -0,0 +1,363 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::mesh::{vertex, Mesh, Vertex};
+use std::f32::consts::{FRAC_PI_2, PI};
+
+/// A point on the profile of a surface of revolution, as its distance from the y axis, its height,
+/// and the surface normal in those two dimensions.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct ProfilePoint {
+    pub radius: f32,
+    pub height: f32,
+    pub normal: [f32; 2],
+}
+
+impl ProfilePoint {
+    pub fn new(radius: f32, height: f32, normal: [f32; 2]) -> Self {
+        Self {
+            radius,
+            height,
+            normal,
+        }
+    }
+}
+
+/// Revolves profile strips around the y axis.
+///
+/// Each strip is a smooth run of the profile, so strips meeting at a sharp edge get separate
+/// vertices with their own normals. Strips should run from the bottom of the surface to the top
+/// along its outside for the faces to wind counterclockwise when seen from outside. Points on the
+/// axis close the surface with triangles.
+pub(crate) fn revolve(strips: &[Vec<ProfilePoint>], segments: usize) -> Mesh {
+    let mut vertices = vec![];
+    let mut normals = vec![];
+    let mut faces = vec![];
+    for strip in strips {
+        let first = vertices.len() + 1;
+        for point in strip {
+            // Points on the axis are shared by the triangles around them, so their normals face
+            // the middle of each triangle.
+            let offset = if point.radius == 0.0 { 0.5 } else { 0.0 };
+            for i in 0..segments {
+                let angle = 2.0 * PI * (i as f32 + offset) / segments as f32;
+                let (sin, cos) = angle.sin_cos();
+                vertices.push(vertex(point.radius * cos, point.height, point.radius * sin));
+                normals.push(
+                    Vertex::new(
+                        point.normal[0] * cos,
+                        point.normal[1],
+                        point.normal[0] * sin,
+                        0.0,
+                    )
+                    .normalize(),
+                );
+            }
+        }
+        let index = |j: usize, i: usize| first + j * segments + i % segments;
+        for (j, pair) in strip.windows(2).enumerate() {
+            for i in 0..segments {
+                faces.push(match (pair[0].radius == 0.0, pair[1].radius == 0.0) {
+                    (true, true) => continue,
+                    (true, false) => vec![index(j, i), index(j + 1, i), index(j + 1, i + 1)],
+                    (false, true) => vec![index(j, i), index(j + 1, i), index(j, i + 1)],
+                    (false, false) => vec![
+                        index(j, i),
+                        index(j + 1, i),
+                        index(j + 1, i + 1),
+                        index(j, i + 1),
+                    ],
+                });
+            }
+        }
+    }
+    Mesh::new(vertices, Some(normals), faces)
+}
+
+/// A cylinder of diameter 1 and height 1 along the y axis.
+pub(crate) fn cylinder(segments: usize) -> Mesh {
+    revolve(
+        &[
+            vec![
+                ProfilePoint::new(0.0, -0.5, [0.0, -1.0]),
+                ProfilePoint::new(0.5, -0.5, [0.0, -1.0]),
+            ],
+            vec![
+                ProfilePoint::new(0.5, -0.5, [1.0, 0.0]),
+                ProfilePoint::new(0.5, 0.5, [1.0, 0.0]),
+            ],
+            vec![
+                ProfilePoint::new(0.5, 0.5, [0.0, 1.0]),
+                ProfilePoint::new(0.0, 0.5, [0.0, 1.0]),
+            ],
+        ],
+        segments,
+    )
+}
+
+/// A cone with a base of diameter 1 and a height of 1 along the y axis, pointing up.
+pub(crate) fn cone(segments: usize) -> Mesh {
+    // The side rises 1 for every 0.5 it narrows.
+    let side = [1.0, 0.5];
+    revolve(
+        &[
+            vec![
+                ProfilePoint::new(0.0, -0.5, [0.0, -1.0]),
+                ProfilePoint::new(0.5, -0.5, [0.0, -1.0]),
+            ],
+            vec![
+                ProfilePoint::new(0.5, -0.5, side),
+                ProfilePoint::new(0.0, 0.5, side),
+            ],
+        ],
+        segments,
+    )
+}
+
+/// A torus around the y axis whose outer diameter is 1, with a tube `minor / major` times as
+/// thick as its ring is wide.
+pub(crate) fn torus(major: f32, minor: f32, segments: usize, rings: usize) -> Mesh {
+    let scale = 0.5 / (major + minor);
+    let (major, minor) = (major * scale, minor * scale);
+    // Starting on the inside of the tube, the profile runs under, around the outside and over it.
+    let tube = (0..=rings)
+        .map(|j| {
+            let angle = PI + 2.0 * PI * j as f32 / rings as f32;
+            let (sin, cos) = angle.sin_cos();
+            ProfilePoint::new(major + minor * cos, minor * sin, [cos, sin])
+        })
+        .collect();
+    revolve(&[tube], segments)
+}
+
+/// A capsule of height 1 along the y axis, made of a cylinder of the given radius capped by
+/// hemispheres.
+pub(crate) fn capsule(radius: f32, segments: usize) -> Mesh {
+    let rings = (segments / 4).max(2);
+    let half_length = 0.5 - radius;
+    let cap = |from: usize, center: f32| {
+        (from..from + rings + 1).map(move |k| {
+            let angle = -FRAC_PI_2 + FRAC_PI_2 * k as f32 / rings as f32;
+            let (sin, cos) = angle.sin_cos();
+            // Snap the poles onto the axis.
+            let cos = if k % (2 * rings) == 0 { 0.0 } else { cos };
+            ProfilePoint::new(radius * cos, center + radius * sin, [cos, sin])
+        })
+    };
+    let profile = cap(0, -half_length)
+        .chain(cap(rings, half_length))
+        .collect();
+    revolve(&[profile], segments)
+}
+
+/// A square of size 1 in the xz plane, facing up the y axis.
+pub(crate) fn quad() -> Mesh {
+    flat(vec![
+        vertex(0.5, 0.0, -0.5),
+        vertex(-0.5, 0.0, -0.5),
+        vertex(-0.5, 0.0, 0.5),
+        vertex(0.5, 0.0, 0.5),
+    ])
+}
+
+/// A triangle spanning the square of size 1 in the xz plane, facing up the y axis.
+pub(crate) fn triangle() -> Mesh {
+    flat(vec![
+        vertex(0.0, 0.0, -0.5),
+        vertex(-0.5, 0.0, 0.5),
+        vertex(0.5, 0.0, 0.5),
+    ])
+}
+
+/// A disc of diameter 1 in the xz plane, facing up the y axis.
+pub(crate) fn disc(segments: usize) -> Mesh {
+    revolve(
+        &[vec![
+            ProfilePoint::new(0.5, 0.0, [0.0, 1.0]),
+            ProfilePoint::new(0.0, 0.0, [0.0, 1.0]),
+        ]],
+        segments,
+    )
+}
+
+/// A polyline through the given points.
+pub(crate) fn polyline(points: Vec<Vertex>) -> Mesh {
+    let line = (1..=points.len()).collect();
+    Mesh::new(points, None, vec![]).with_lines(vec![line])
+}
+
+/// The twelve edges of a cube of size 1, as lines.
+pub(crate) fn grid() -> Mesh {
+    let corners = (0..8)
+        .map(|i| {
+            let coordinate = |bit| if i & bit == 0 { -0.5 } else { 0.5 };
+            vertex(coordinate(1), coordinate(2), coordinate(4))
+        })
+        .collect();
+    // Corners whose indices differ in one bit share an edge.
+    let edges = (0..8)
+        .flat_map(|i| {
+            [1, 2, 4]
+                .iter()
+                .filter(move |bit| i & **bit == 0)
+                .map(move |bit| vec![i + 1, (i | bit) + 1])
+        })
+        .collect();
+    Mesh::new(corners, None, vec![]).with_lines(edges)
+}
+
+/// A single face through the given counterclockwise corners in the xz plane.
+fn flat(corners: Vec<Vertex>) -> Mesh {
+    let normals = vec![Vertex::new(0.0, 1.0, 0.0, 0.0); corners.len()];
+    let face = (1..=corners.len()).collect();
+    Mesh::new(corners, Some(normals), vec![face])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Checks the mesh winds outward and fits in the unit cube, returning its volume.
+    fn assert_closed_and_outward(mesh: &Mesh) -> f32 {
+        let vertices = mesh.vertices();
+        let normals = mesh.normals().expect("normals");
+        let mut volume = 0.0;
+        for face in mesh.faces() {
+            for i in 1..face.len() - 1 {
+                let a = vertices[face[0] - 1].xyz();
+                let b = vertices[face[i] - 1].xyz();
+                let c = vertices[face[i + 1] - 1].xyz();
+                volume += a.dot(&b.cross(&c)) / 6.0;
+                let facet = (b - a).cross(&(c - a));
+                for corner in &[face[0], face[i], face[i + 1]] {
+                    assert!(
+                        facet.dot(&normals[corner - 1].xyz()) >= -0.0001,
+                        "face {:?} winds against its normals",
+                        face
+                    );
+                }
+            }
+        }
+        assert!(volume > 0.0, "the mesh should enclose positive volume");
+        for v in vertices {
+            assert!(v.x.abs() <= 0.5 + 0.0001);
+            assert!(v.y.abs() <= 0.5 + 0.0001);
+            assert!(v.z.abs() <= 0.5 + 0.0001);
+        }
+        volume
+    }
+
+    #[test]
+    fn flat_primitives_face_up() {
+        for mesh in &[quad(), triangle(), disc(12)] {
+            let vertices = mesh.vertices();
+            for face in mesh.faces() {
+                let (a, b, c) = (
+                    vertices[face[0] - 1].xyz(),
+                    vertices[face[1] - 1].xyz(),
+                    vertices[face[2] - 1].xyz(),
+                );
+                assert!((b - a).cross(&(c - a)).y > 0.0);
+            }
+            assert!(vertices.iter().all(|v| v.y == 0.0));
+        }
+    }
+
+    #[test]
+    fn flat_primitives_wind_with_their_normals() {
+        let area = |mesh: &Mesh| {
+            let vertices = mesh.vertices();
+            let normals = mesh.normals().expect("normals");
+            let mut area = 0.0;
+            for face in mesh.faces() {
+                for i in 1..face.len() - 1 {
+                    let a = vertices[face[0] - 1].xyz();
+                    let b = vertices[face[i] - 1].xyz();
+                    let c = vertices[face[i + 1] - 1].xyz();
+                    let facet = (b - a).cross(&(c - a));
+                    assert!(facet.x == 0.0 && facet.z == 0.0 && facet.y > 0.0);
+                    area += facet.norm() / 2.0;
+                }
+            }
+            assert!(normals
+                .iter()
+                .all(|n| *n == Vertex::new(0.0, 1.0, 0.0, 0.0)));
+            area
+        };
+        assert_eq!(
+            quad().faces().map(<[usize]>::len).collect::<Vec<_>>(),
+            vec![4]
+        );
+        assert_eq!(area(&quad()), 1.0);
+        assert_eq!(
+            triangle().faces().map(<[usize]>::len).collect::<Vec<_>>(),
+            vec![3]
+        );
+        assert_eq!(area(&triangle()), 0.5);
+        let disc = disc(64);
+        assert_eq!(disc.faces().count(), 64);
+        assert!(disc.faces().all(|face| face.len() == 3));
+        assert!((area(&disc) - PI * 0.25).abs() < 0.01);
+    }
+
+    #[test]
+    fn grids_are_cube_edges() {
+        let mesh = grid();
+        let vertices = mesh.vertices();
+        assert_eq!(mesh.faces().count(), 0);
+        let mut edges: Vec<(usize, usize)> = mesh.lines().map(|line| (line[0], line[1])).collect();
+        edges.sort();
+        edges.dedup();
+        assert_eq!(edges.len(), 12);
+        for (a, b) in edges {
+            let edge = vertices[b - 1] - vertices[a - 1];
+            assert_eq!(edge.norm(), 1.0);
+        }
+    }
+
+    #[test]
+    fn cylinder_is_unit_sized() {
+        let mesh = cylinder(24);
+        let volume = assert_closed_and_outward(&mesh);
+        assert!((volume - PI * 0.25).abs() < 0.02);
+        assert!(mesh.vertices().iter().any(|v| v.y == 0.5));
+        assert!(mesh.vertices().iter().any(|v| v.x == 0.5));
+    }
+
+    #[test]
+    fn cone_is_unit_sized() {
+        let volume = assert_closed_and_outward(&cone(24));
+        assert!((volume - PI * 0.25 / 3.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn torus_fits_unit_size() {
+        let mesh = torus(0.3, 0.1, 24, 12);
+        assert_closed_and_outward(&mesh);
+        let outer = mesh
+            .vertices()
+            .iter()
+            .map(|v| (v.x * v.x + v.z * v.z).sqrt())
+            .fold(0.0, f32::max);
+        assert!((outer - 0.5).abs() < 0.0001);
+    }
+
+    #[test]
+    fn capsule_is_unit_sized() {
+        let mesh = capsule(0.25, 16);
+        assert_closed_and_outward(&mesh);
+        let top = mesh.vertices().iter().map(|v| v.y).fold(0.0, f32::max);
+        assert!((top - 0.5).abs() < 0.0001);
+    }
+}
@@ -0,0 +1,549 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::mesh::primitives::{revolve, ProfilePoint};
+use crate::mesh::{vertex, vertex_normals, Mesh, Vertex};
+use crate::rule::{Transform, TransformArgument};
+use std::f32::consts::FRAC_PI_4;
+use std::sync::Arc;
+
+/// Profile corners turning more sharply than this get a crisp edge instead of a smooth normal.
+const SMOOTH_ANGLE: f32 = FRAC_PI_4;
+
+impl Mesh {
+    /// A prism made by extruding a polygon in the xz plane along the y axis, from `-depth / 2` to
+    /// `depth / 2`.
+    ///
+    /// The polygon is a list of `[x, z]` corners, which may be wound either way and may be
+    /// concave, but should not cross itself. The caps at each end are triangulated if `caps` is
+    /// set, and left open otherwise. Each side of the prism is flat shaded.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// let l_beam = Mesh::extrude(
+    ///     &[[0.0, 0.0], [1.0, 0.0], [1.0, 0.2], [0.2, 0.2], [0.2, 1.0], [0.0, 1.0]],
+    ///     /*depth=*/ 4.0,
+    ///     /*caps=*/ true,
+    /// );
+    /// let rule = Rule::new().push(Tf::rx(90.0), l_beam);
+    /// ````
+    pub fn extrude(polygon: &[[f32; 2]], depth: f32, caps: bool) -> Arc<Mesh> {
+        let mut polygon = distinct(polygon);
+        if polygon.len() < 3 {
+            return Mesh::from(vec![], None, vec![]);
+        }
+        if area(&polygon) < 0.0 {
+            polygon.reverse();
+        }
+
+        let mut vertices = vec![];
+        let mut normals = vec![];
+        let mut faces = vec![];
+        let (bottom, top) = (-depth / 2.0, depth / 2.0);
+        for (i, a) in polygon.iter().enumerate() {
+            let b = polygon[(i + 1) % polygon.len()];
+            let normal = Vertex::new(a[1] - b[1], 0.0, b[0] - a[0], 0.0).normalize();
+            let first = vertices.len() + 1;
+            vertices.extend_from_slice(&[
+                vertex(a[0], bottom, a[1]),
+                vertex(b[0], bottom, b[1]),
+                vertex(b[0], top, b[1]),
+                vertex(a[0], top, a[1]),
+            ]);
+            normals.extend_from_slice(&[normal; 4]);
+            faces.push((first..first + 4).collect());
+        }
+
+        if caps {
+            let triangles = triangulate(&polygon);
+            for &(height, up) in &[(top, 1.0), (bottom, -1.0)] {
+                let first = vertices.len() + 1;
+                for point in &polygon {
+                    vertices.push(vertex(point[0], height, point[1]));
+                    normals.push(Vertex::new(0.0, up, 0.0, 0.0));
+                }
+                for triangle in &triangles {
+                    let mut face: Vec<usize> = triangle.iter().map(|i| first + i).collect();
+                    if up < 0.0 {
+                        face.reverse();
+                    }
+                    faces.push(face);
+                }
+            }
+        }
+
+        Mesh::from(vertices, Some(normals), faces)
+    }
+
+    /// A surface of revolution made by revolving a profile around the y axis.
+    ///
+    /// The profile is a list of `[radius, height]` points which should run from the bottom of the
+    /// surface to the top along its outside, so a vase's profile runs up its outer wall, over the
+    /// lip, and back down its inner wall. Points with a radius of 0 close the surface on the
+    /// axis. Normals are smooth along the profile except at corners sharper than 45 degrees.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// let base = [[0.0, 0.0], [0.5, 0.0], [0.5, 0.1], [0.3, 0.2]];
+    /// let capital = [[0.3, 2.8], [0.5, 2.9], [0.5, 3.0], [0.0, 3.0]];
+    /// let profile: Vec<[f32; 2]> = base.iter().chain(&capital).cloned().collect();
+    /// let column = Mesh::lathe(&profile, /*segments=*/ 32);
+    /// let rule = Rule::new().push(Tf::s(0.5), column);
+    /// ````
+    pub fn lathe(profile: &[[f32; 2]], segments: usize) -> Arc<Mesh> {
+        let profile: Vec<[f32; 2]> = distinct(profile)
+            .into_iter()
+            .map(|[radius, height]| [radius.max(0.0), height])
+            .collect();
+        let segment_normals: Vec<[f32; 2]> = profile
+            .windows(2)
+            .map(|pair| normal_2d([pair[0], pair[1]]))
+            .collect();
+
+        let mut strips = vec![];
+        let mut strip = vec![];
+        for (i, point) in profile.iter().enumerate() {
+            let before = i.checked_sub(1).map(|i| segment_normals[i]);
+            let after = segment_normals.get(i).cloned();
+            let point = |normal: [f32; 2]| ProfilePoint::new(point[0], point[1], normal);
+            match (before, after) {
+                (Some(before), Some(after)) if angle(before, after) > SMOOTH_ANGLE => {
+                    strip.push(point(before));
+                    strips.push(strip);
+                    strip = vec![point(after)];
+                }
+                (Some(before), Some(after)) => {
+                    strip.push(point([before[0] + after[0], before[1] + after[1]]))
+                }
+                (Some(normal), None) | (None, Some(normal)) => strip.push(point(normal)),
+                (None, None) => (),
+            }
+        }
+        strips.push(strip);
+
+        Arc::new(revolve(&strips, segments.max(3)))
+    }
+}
+
+/// A builder for a single mesh made by sweeping a cross-section through a sequence of transforms.
+///
+/// The cross-section is a list of `[x, z]` points in the xz plane, like the polygon given to
+/// [Mesh::extrude][Mesh::extrude], and a copy of it is placed by each transform of the path. The
+/// copies are stitched together in order into one surface, so rules which would place a mesh at
+/// each step of a [Replicate][crate::rule::Replicate] can instead trace a continuous tube
+/// through the same steps.
+///
+/// A [tube][Sweep::tube] treats its cross-section as a polygon and is capped at each end to
+/// enclose a volume. A [ribbon][Sweep::ribbon] treats its cross-section as an open polyline; a
+/// ribbon swept up the y axis with a cross-section running along +x faces +z. Normals are smooth
+/// except where the cross-section turns more sharply than 45 degrees.
+///
+/// ````
+/// # use immense::*;
+/// let square = [[-0.1, -0.1], [0.1, -0.1], [0.1, 0.1], [-0.1, 0.1]];
+/// let spiral = Sweep::tube(&square, Replicate::n(36, vec![Tf::ry(10.0), Tf::ty(0.1)])).build();
+///
+/// let rule = Rule::new().push(Tf::tx(2.0), spiral);
+/// ````
+pub struct Sweep {
+    profile: Vec<[f32; 2]>,
+    path: Vec<Transform>,
+    closed: bool,
+    caps: bool,
+}
+
+impl Sweep {
+    /// Sweeps a polygon through the path, capping the ends.
+    pub fn tube(polygon: &[[f32; 2]], path: impl Into<TransformArgument>) -> Self {
+        let mut profile = distinct(polygon);
+        if profile.len() >= 3 && area(&profile) < 0.0 {
+            profile.reverse();
+        }
+        Self {
+            profile,
+            path: path.into().into(),
+            closed: true,
+            caps: true,
+        }
+    }
+
+    /// Sweeps a polyline through the path.
+    pub fn ribbon(polyline: &[[f32; 2]], path: impl Into<TransformArgument>) -> Self {
+        let mut profile: Vec<[f32; 2]> = vec![];
+        for point in polyline {
+            if profile.last() != Some(point) {
+                profile.push(*point);
+            }
+        }
+        Self {
+            profile,
+            path: path.into().into(),
+            closed: false,
+            caps: false,
+        }
+    }
+
+    /// Sets whether a tube is capped at its ends, which is the default. Ribbons are never capped.
+    pub fn caps(mut self, caps: bool) -> Self {
+        self.caps = caps && self.closed;
+        self
+    }
+
+    /// Stitches the cross-sections into a mesh.
+    pub fn build(self) -> Arc<Mesh> {
+        let minimum = if self.closed { 3 } else { 2 };
+        if self.profile.len() < minimum || self.path.len() < 2 {
+            return Mesh::from(vec![], None, vec![]);
+        }
+        let place = |transform: &Transform, point: [f32; 2]| {
+            transform.apply_to(vertex(point[0], 0.0, point[1]))
+        };
+
+        let mut vertices = vec![];
+        let mut faces: Vec<Vec<usize>> = vec![];
+        let (strips, wraps) = strips(&self.profile, self.closed);
+        for strip in &strips {
+            let first = vertices.len() + 1;
+            for transform in &self.path {
+                vertices.extend(strip.iter().map(|point| place(transform, *point)));
+            }
+            let index = |ring: usize, i: usize| first + ring * strip.len() + i % strip.len();
+            let edges = if wraps { strip.len() } else { strip.len() - 1 };
+            for ring in 0..self.path.len() - 1 {
+                for i in 0..edges {
+                    faces.push(vec![
+                        index(ring, i),
+                        index(ring, i + 1),
+                        index(ring + 1, i + 1),
+                        index(ring + 1, i),
+                    ]);
+                }
+            }
+        }
+
+        if self.caps {
+            let triangles = triangulate(&self.profile);
+            let ends = [(self.path.last(), false), (self.path.first(), true)];
+            for (transform, reversed) in ends.iter().cloned() {
+                let transform = transform.expect("a path of at least two transforms");
+                let first = vertices.len() + 1;
+                vertices.extend(self.profile.iter().map(|point| place(transform, *point)));
+                for triangle in &triangles {
+                    let mut face: Vec<usize> = triangle.iter().map(|i| first + i).collect();
+                    if reversed {
+                        face.reverse();
+                    }
+                    faces.push(face);
+                }
+            }
+        }
+
+        // The faces wind outward when the path runs up the y axis of each cross-section. Paths
+        // running the other way, or through mirroring transforms, turn the faces inside out.
+        let up = Vertex::new(0.0, 1.0, 0.0, 0.0);
+        let direction: f32 = self
+            .path
+            .windows(2)
+            .map(|pair| {
+                let step = pair[1].apply_to(vertex(0.0, 0.0, 0.0))
+                    - pair[0].apply_to(vertex(0.0, 0.0, 0.0));
+                step.dot(&pair[0].apply_to_normal(up))
+            })
+            .sum();
+        if direction < 0.0 {
+            faces.iter_mut().for_each(|face| face.reverse());
+        }
+
+        let normals = vertex_normals(&vertices, &faces);
+        Mesh::from(vertices, Some(normals), faces)
+    }
+}
+
+/// Splits a cross-section into smooth runs at its sharp corners, which are given separate
+/// vertices in each run they end. Returns whether the only run wraps around a closed
+/// cross-section with no sharp corners.
+fn strips(profile: &[[f32; 2]], closed: bool) -> (Vec<Vec<[f32; 2]>>, bool) {
+    let len = profile.len();
+    let sharp = |i: usize| {
+        let (before, point, after) = (
+            profile[(i + len - 1) % len],
+            profile[i],
+            profile[(i + 1) % len],
+        );
+        angle(normal_2d([before, point]), normal_2d([point, after])) > SMOOTH_ANGLE
+    };
+    let corners: Vec<usize> = if closed {
+        (0..len).filter(|&i| sharp(i)).collect()
+    } else {
+        (1..len - 1).filter(|&i| sharp(i)).collect()
+    };
+    if closed && corners.is_empty() {
+        return (vec![profile.to_vec()], true);
+    }
+
+    let (start, end) = if closed {
+        (corners[0], corners[0] + len)
+    } else {
+        (0, len - 1)
+    };
+    let mut strips = vec![];
+    let mut strip = vec![];
+    for i in start..=end {
+        strip.push(profile[i % len]);
+        if i != start && (i == end || corners.contains(&(i % len))) {
+            strips.push(strip);
+            strip = vec![profile[i % len]];
+        }
+    }
+    (strips, false)
+}
+
+/// Drops repeated points, including a last point repeating the first.
+fn distinct(points: &[[f32; 2]]) -> Vec<[f32; 2]> {
+    let mut distinct: Vec<[f32; 2]> = vec![];
+    for point in points {
+        if distinct.last() != Some(point) {
+            distinct.push(*point);
+        }
+    }
+    if distinct.len() > 1 && distinct.first() == distinct.last() {
+        distinct.pop();
+    }
+    distinct
+}
+
+/// The normal to the right of a segment, facing outward for a profile running up its outside.
+fn normal_2d([a, b]: [[f32; 2]; 2]) -> [f32; 2] {
+    let (x, y) = (b[1] - a[1], a[0] - b[0]);
+    let length = (x * x + y * y).sqrt();
+    [x / length, y / length]
+}
+
+fn angle(a: [f32; 2], b: [f32; 2]) -> f32 {
+    (a[0] * b[0] + a[1] * b[1]).clamp(-1.0, 1.0).acos()
+}
+
+/// The cross product of `b - a` and `c - a` in the xz plane, which is positive when `a`, `b` and
+/// `c` wind counterclockwise seen from above.
+fn cross(a: [f32; 2], b: [f32; 2], c: [f32; 2]) -> f32 {
+    (b[1] - a[1]) * (c[0] - a[0]) - (b[0] - a[0]) * (c[1] - a[1])
+}
+
+/// Twice the area of a polygon in the xz plane, which is positive when it winds counterclockwise
+/// seen from above.
+fn area(polygon: &[[f32; 2]]) -> f32 {
+    (1..polygon.len() - 1)
+        .map(|i| cross(polygon[0], polygon[i], polygon[i + 1]))
+        .sum()
+}
+
+/// Splits a counterclockwise polygon into triangles by clipping ears, returning indices into the
+/// polygon.
+///
+/// An ear is a convex corner whose triangle contains no other corner of the polygon, and cutting
+/// one off leaves a smaller polygon. Every simple polygon with more than three corners has two.
+pub(crate) fn triangulate(polygon: &[[f32; 2]]) -> Vec<[usize; 3]> {
+    let mut remaining: Vec<usize> = (0..polygon.len()).collect();
+    let mut triangles = vec![];
+    while remaining.len() > 3 {
+        let corners = |i: usize| {
+            let len = remaining.len();
+            [
+                remaining[(i + len - 1) % len],
+                remaining[i],
+                remaining[(i + 1) % len],
+            ]
+        };
+        let is_ear = |i: usize| {
+            let [a, b, c] = corners(i);
+            let (pa, pb, pc) = (polygon[a], polygon[b], polygon[c]);
+            cross(pa, pb, pc) > 0.0
+                && remaining
+                    .iter()
+                    .filter(|&&j| j != a && j != b && j != c)
+                    .map(|&j| polygon[j])
+                    .filter(|&p| p != pa && p != pb && p != pc)
+                    .all(|p| {
+                        cross(pa, pb, p) < 0.0 || cross(pb, pc, p) < 0.0 || cross(pc, pa, p) < 0.0
+                    })
+        };
+        // A polygon which crosses itself may have no ears, so clip something rather than loop.
+        let ear = (0..remaining.len()).find(|&i| is_ear(i)).unwrap_or(0);
+        triangles.push(corners(ear));
+        remaining.remove(ear);
+    }
+    if remaining.len() == 3 {
+        triangles.push([remaining[0], remaining[1], remaining[2]]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{Replicate, Tf};
+    use std::f32::consts::PI;
+
+    /// Checks every face winds the same way as its normals, returning the mesh's volume.
+    fn volume(mesh: &Mesh) -> f32 {
+        let vertices = mesh.vertices();
+        let normals = mesh.normals().expect("normals");
+        let mut volume = 0.0;
+        for face in mesh.faces() {
+            for i in 1..face.len() - 1 {
+                let (a, b, c) = (
+                    vertices[face[0] - 1].xyz(),
+                    vertices[face[i] - 1].xyz(),
+                    vertices[face[i + 1] - 1].xyz(),
+                );
+                volume += a.dot(&b.cross(&c)) / 6.0;
+                let facet = (b - a).cross(&(c - a));
+                for corner in face {
+                    assert!(facet.dot(&normals[corner - 1].xyz()) >= -0.0001);
+                }
+            }
+        }
+        volume
+    }
+
+    fn assert_watertight(mesh: &Mesh) {
+        let mut edges = std::collections::HashMap::new();
+        let vertices = mesh.vertices();
+        let key = |i: usize| {
+            let v = vertices[i - 1];
+            [v.x.to_bits(), v.y.to_bits(), v.z.to_bits()]
+        };
+        for face in mesh.faces() {
+            for i in 0..face.len() {
+                let edge = (key(face[i]), key(face[(i + 1) % face.len()]));
+                *edges.entry(edge).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn straight_sweeps_match_extrusions() {
+        let square = [[0.0, 0.0], [0.0, 1.0], [1.0, 1.0], [1.0, 0.0]];
+        for &step in &[0.25, -0.25] {
+            let mesh = Sweep::tube(&square, Replicate::n(5, Tf::ty(step))).build();
+            assert_eq!(mesh.faces().count(), 4 * 4 + 2 * 2);
+            assert_watertight(&mesh);
+            assert!((volume(&mesh) - 1.0).abs() < 0.0001);
+        }
+    }
+
+    #[test]
+    fn smooth_cross_sections_wrap_around() {
+        let circle: Vec<[f32; 2]> = (0..12)
+            .map(|i| {
+                let angle = 2.0 * PI * i as f32 / 12.0;
+                [0.2 * angle.cos(), 0.2 * angle.sin()]
+            })
+            .collect();
+        let path = Replicate::n(24, vec![Tf::ry(15.0), Tf::ty(0.1), Tf::tx(0.1)]);
+        let mesh = Sweep::tube(&circle, path).build();
+        assert_eq!(mesh.vertices().len(), 12 * 24 + 2 * 12);
+        assert_watertight(&mesh);
+        assert!(volume(&mesh) > 0.0);
+    }
+
+    #[test]
+    fn ribbons_are_open() {
+        let mesh = Sweep::ribbon(&[[-0.5, 0.0], [0.5, 0.0]], Replicate::n(3, Tf::ty(1.0)))
+            .caps(true)
+            .build();
+        assert_eq!(mesh.faces().count(), 2);
+        let normals = mesh.normals().expect("normals");
+        assert!(normals.iter().all(|n| (n.z - 1.0).abs() < 0.0001));
+    }
+
+    #[test]
+    fn concave_polygons_are_triangulated() {
+        // An L with its corners wound clockwise seen from above.
+        let l = [
+            [0.0, 0.0],
+            [0.0, 2.0],
+            [1.0, 2.0],
+            [1.0, 1.0],
+            [2.0, 1.0],
+            [2.0, 0.0],
+        ];
+        let mesh = Mesh::extrude(&l, 0.5, true);
+        assert_eq!(mesh.faces().filter(|face| face.len() == 3).count(), 2 * 4);
+        assert!((volume(&mesh) - 3.0 * 0.5).abs() < 0.0001);
+
+        let open = Mesh::extrude(&l, 0.5, false);
+        assert_eq!(open.faces().count(), 6);
+    }
+
+    #[test]
+    fn ears_are_not_cut_across_the_polygon() {
+        let star: Vec<[f32; 2]> = (0..10)
+            .map(|i| {
+                let angle = -2.0 * PI * i as f32 / 10.0;
+                let radius = if i % 2 == 0 { 1.0 } else { 0.4 };
+                [radius * angle.cos(), radius * angle.sin()]
+            })
+            .collect();
+        let triangles = triangulate(&star);
+        assert_eq!(triangles.len(), 8);
+        let total: f32 = triangles
+            .iter()
+            .map(|&[a, b, c]| {
+                let area = cross(star[a], star[b], star[c]);
+                assert!(area > 0.0);
+                area
+            })
+            .sum();
+        assert!((total - area(&star)).abs() < 0.0001);
+    }
+
+    #[test]
+    fn lathe_splits_sharp_corners() {
+        let mesh = Mesh::lathe(
+            &[
+                [0.0, -0.5],
+                [0.5, -0.5],
+                [0.5, 0.5],
+                [0.0, 0.5],
+                [0.0, -0.5],
+            ],
+            24,
+        );
+        // The bottom, side and top each get their own ring of vertices at the corners.
+        assert_eq!(mesh.vertices().len(), 24 * 6);
+        assert!((volume(&mesh) - PI * 0.25).abs() < 0.02);
+    }
+
+    #[test]
+    fn lathe_smooths_gentle_curves() {
+        let profile: Vec<[f32; 2]> = (0..=8)
+            .map(|i| {
+                let angle = -PI / 2.0 + PI * i as f32 / 8.0;
+                [0.5 * angle.cos(), 0.5 * angle.sin()]
+            })
+            .collect();
+        let mesh = Mesh::lathe(&profile, 16);
+        assert_eq!(mesh.vertices().len(), 16 * 9);
+        let (vertices, normals) = (mesh.vertices(), mesh.normals().expect("normals"));
+        for (v, n) in vertices.iter().zip(normals).filter(|(v, _)| v.x != 0.0) {
+            assert!((n.xyz() - v.xyz().normalize()).norm() < 0.0001);
+        }
+        assert!(volume(&mesh) > 0.0);
+    }
+}
@@ -0,0 +1,272 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::mesh::{vertex, vertex_normals, Mesh, Vertex};
+use nalgebra::Vector3;
+use std::sync::Arc;
+
+/// The corners of a grid cell, as offsets along x, y and z.
+const CORNERS: [[usize; 3]; 8] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [0, 1, 0],
+    [1, 1, 0],
+    [0, 0, 1],
+    [1, 0, 1],
+    [0, 1, 1],
+    [1, 1, 1],
+];
+
+/// The edges of a grid cell, as pairs of indices into [CORNERS].
+const EDGES: [[usize; 2]; 12] = [
+    [0, 1],
+    [2, 3],
+    [4, 5],
+    [6, 7],
+    [0, 2],
+    [1, 3],
+    [4, 6],
+    [5, 7],
+    [0, 4],
+    [1, 5],
+    [2, 6],
+    [3, 7],
+];
+
+/// A builder for meshes of the surface of a signed distance field.
+///
+/// The field is a function returning the distance from a point to the surface, which is negative
+/// inside the shape and positive outside it. Fields which only get the sign right and
+/// approximate the distance work too, though the surface will be less accurate.
+///
+/// The field is sampled on a grid of cells over its bounds, which are the unit cube around the
+/// origin unless set otherwise, and a vertex is placed in each cell the surface passes through
+/// ([surface nets](https://0fps.net/2012/07/12/smooth-voxel-terrain-part-2/)). Normals point
+/// along the gradient of the field. The mesh is closed as long as the field is positive
+/// everywhere on the bounds.
+///
+/// ````
+/// # use immense::*;
+/// // Two spheres smoothly blended together.
+/// let blob = DistanceField::new(|p| {
+///     let left = (p - vertex(-0.2, 0.0, 0.0)).xyz().norm() - 0.2;
+///     let right = (p - vertex(0.2, 0.0, 0.0)).xyz().norm() - 0.2;
+///     let blend = (0.5 + 0.5 * (right - left) / 0.1).max(0.0).min(1.0);
+///     right + (left - right) * blend - 0.1 * blend * (1.0 - blend)
+/// })
+/// .resolution(48)
+/// .build();
+///
+/// let rule = Rule::new().push(Tf::s(2.0), blob);
+/// ````
+pub struct DistanceField<F> {
+    field: F,
+    min: Vector3<f32>,
+    max: Vector3<f32>,
+    resolution: usize,
+}
+
+impl<F: Fn(Vertex) -> f32> DistanceField<F> {
+    /// Starts a mesh from a signed distance function.
+    pub fn new(field: F) -> Self {
+        Self {
+            field,
+            min: Vector3::repeat(-0.5),
+            max: Vector3::repeat(0.5),
+            resolution: 32,
+        }
+    }
+
+    /// Sets the corners of the box the field is sampled in.
+    pub fn bounds(mut self, min: Vertex, max: Vertex) -> Self {
+        self.min = min.xyz().zip_map(&max.xyz(), f32::min);
+        self.max = min.xyz().zip_map(&max.xyz(), f32::max);
+        self
+    }
+
+    /// Sets the number of cells the bounds are divided into along each axis. The default is 32.
+    pub fn resolution(mut self, resolution: usize) -> Self {
+        self.resolution = resolution.max(1);
+        self
+    }
+
+    /// Samples the field and builds a mesh of its surface.
+    pub fn build(self) -> Arc<Mesh> {
+        let n = self.resolution;
+        let cell = (self.max - self.min) / n as f32;
+        let position = |p: [usize; 3]| {
+            let p =
+                self.min + Vector3::new(p[0] as f32, p[1] as f32, p[2] as f32).component_mul(&cell);
+            vertex(p.x, p.y, p.z)
+        };
+        let sample_index = |p: [usize; 3]| (p[2] * (n + 1) + p[1]) * (n + 1) + p[0];
+        let cell_index = |p: [usize; 3]| (p[2] * n + p[1]) * n + p[0];
+
+        let mut samples = Vec::with_capacity((n + 1).pow(3));
+        for z in 0..=n {
+            for y in 0..=n {
+                for x in 0..=n {
+                    samples.push((self.field)(position([x, y, z])));
+                }
+            }
+        }
+        let sample = |p: [usize; 3]| samples[sample_index(p)];
+        let inside = |p: [usize; 3]| sample(p) < 0.0;
+
+        // Each cell the surface crosses gets a vertex at the average of the points where the
+        // surface crosses the cell's edges.
+        let mut vertices = vec![];
+        let mut cell_vertices = vec![None; n * n * n];
+        for z in 0..n {
+            for y in 0..n {
+                for x in 0..n {
+                    let corner =
+                        |i: usize| [x + CORNERS[i][0], y + CORNERS[i][1], z + CORNERS[i][2]];
+                    let crossings: Vec<Vector3<f32>> = EDGES
+                        .iter()
+                        .filter(|[a, b]| inside(corner(*a)) != inside(corner(*b)))
+                        .map(|[a, b]| {
+                            let (a, b) = (corner(*a), corner(*b));
+                            let t = sample(a) / (sample(a) - sample(b));
+                            position(a).xyz() + (position(b) - position(a)).xyz() * t
+                        })
+                        .collect();
+                    if crossings.is_empty() {
+                        continue;
+                    }
+                    let center = crossings.iter().sum::<Vector3<f32>>() / crossings.len() as f32;
+                    vertices.push(vertex(center.x, center.y, center.z));
+                    cell_vertices[cell_index([x, y, z])] = Some(vertices.len());
+                }
+            }
+        }
+
+        // Each grid edge the surface crosses gets a quad joining the vertices of the four cells
+        // around it, facing from the inside sample to the outside one.
+        let mut faces = vec![];
+        for axis in 0..3 {
+            let (u, v) = ((axis + 1) % 3, (axis + 2) % 3);
+            for z in 0..=n {
+                for y in 0..=n {
+                    for x in 0..=n {
+                        let p = [x, y, z];
+                        if p[axis] == n || p[u] == 0 || p[v] == 0 || p[u] == n || p[v] == n {
+                            continue;
+                        }
+                        let mut next = p;
+                        next[axis] += 1;
+                        if inside(p) == inside(next) {
+                            continue;
+                        }
+                        let around = |du: usize, dv: usize| {
+                            let mut c = p;
+                            c[u] -= 1 - du;
+                            c[v] -= 1 - dv;
+                            cell_vertices[cell_index(c)].expect("cell on the surface")
+                        };
+                        let mut face = vec![around(0, 0), around(1, 0), around(1, 1), around(0, 1)];
+                        if !inside(p) {
+                            face.reverse();
+                        }
+                        faces.push(face);
+                    }
+                }
+            }
+        }
+
+        let step = cell.x.min(cell.y).min(cell.z) * 0.01;
+        let gradient = |p: &Vertex| {
+            let difference = |offset: Vector3<f32>| {
+                let offset = Vertex::new(offset.x, offset.y, offset.z, 0.0);
+                ((self.field)(p + offset) - (self.field)(p - offset)) / (2.0 * step)
+            };
+            Vector3::new(
+                difference(Vector3::x() * step),
+                difference(Vector3::y() * step),
+                difference(Vector3::z() * step),
+            )
+        };
+        let mut fallback = None;
+        let normals = (0..vertices.len())
+            .map(|i| match gradient(&vertices[i]).try_normalize(0.0) {
+                Some(normal) => Vertex::new(normal.x, normal.y, normal.z, 0.0),
+                None => fallback.get_or_insert_with(|| vertex_normals(&vertices, &faces))[i],
+            })
+            .collect();
+
+        Arc::new(Mesh::new(vertices, Some(normals), faces))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::f32::consts::PI;
+
+    fn sphere(radius: f32) -> impl Fn(Vertex) -> f32 {
+        move |p| p.xyz().norm() - radius
+    }
+
+    #[test]
+    fn sphere_is_closed_and_outward() {
+        let mesh = DistanceField::new(sphere(0.4)).resolution(24).build();
+        let vertices = mesh.vertices();
+        let normals = mesh.normals().expect("normals");
+        for (v, n) in vertices.iter().zip(normals) {
+            assert!(
+                (v.xyz().norm() - 0.4).abs() < 0.01,
+                "{:?} is off the sphere",
+                v
+            );
+            assert!((n.xyz() - v.xyz().normalize()).norm() < 0.0001);
+        }
+
+        // Every edge is shared by exactly two faces which run along it in opposite directions.
+        let mut edges = HashMap::new();
+        let mut volume = 0.0;
+        for face in mesh.faces() {
+            for i in 0..face.len() {
+                *edges
+                    .entry((face[i], face[(i + 1) % face.len()]))
+                    .or_insert(0) += 1;
+            }
+            let corner = |i: usize| vertices[face[i] - 1].xyz();
+            volume += corner(0).dot(&corner(1).cross(&corner(2))) / 6.0;
+            volume += corner(0).dot(&corner(2).cross(&corner(3))) / 6.0;
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+        assert!((volume - 4.0 / 3.0 * PI * 0.4f32.powi(3)).abs() < 0.01);
+    }
+
+    #[test]
+    fn bounds_are_sampled() {
+        let mesh = DistanceField::new(|p| (p - vertex(3.0, 0.0, 0.0)).xyz().norm() - 1.0)
+            .bounds(vertex(4.5, 1.5, 1.5), vertex(1.5, -1.5, -1.5))
+            .resolution(16)
+            .build();
+        assert!(mesh.faces().count() > 0);
+        assert!(mesh.vertices().iter().all(|v| (v.x - 3.0).abs() < 1.01));
+    }
+
+    #[test]
+    fn empty_fields_have_no_faces() {
+        let mesh = DistanceField::new(sphere(2.0)).resolution(4).build();
+        assert_eq!(mesh.faces().count(), 0);
+        assert!(mesh.vertices().is_empty());
+    }
+}
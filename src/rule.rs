@@ -13,15 +13,24 @@
 // limitations under the License.
 
 mod builtin;
+mod context;
+mod deform;
+mod parallel;
 mod transforms;
 
 pub use self::builtin::*;
+pub use self::context::Context;
+pub use self::deform::{DeformSpace, Deformer};
 pub use self::transforms::*;
 
-use auto_from::auto_from;
 use crate::mesh::{Mesh, PrimitiveMesh, Vertex};
+use crate::rule::context::derive_seed;
+use crate::rule::deform::Deformation;
+use auto_from::auto_from;
 use palette::rgb::Rgb;
-use std::rc::Rc;
+use rand::{thread_rng, Rng};
+use std::ops::Range;
+use std::sync::Arc;
 
 /// A composition of subrules to expand until meshes are generated.
 #[derive(Clone)]
@@ -49,7 +58,7 @@ pub struct Rule {
 #[macro_export]
 macro_rules! rule {
     ($($transforms:expr => $subrule:expr),+ $(,)*) => ({
-        let mut rule = Rule::new();
+        let rule = Rule::new();
         $(let rule = rule.push($transforms, $subrule);)*
         rule
     });
@@ -70,22 +79,101 @@ impl Rule {
         rule
     }
 
-    pub(crate) fn mesh(mesh: Rc<Mesh>) -> Self {
+    pub(crate) fn mesh(mesh: Arc<Mesh>) -> Self {
         let mut rule = Rule::new();
         rule.invocations
             .push((None, RuleInternal::Mesh(OutputMeshSource::Dynamic(mesh))));
         rule
     }
 
+    /// Returns a rule which invokes one of the given rules, chosen at random by weight each time it
+    /// is expanded. Weights are relative, so `vec![(1.0, a), (3.0, b)]` invokes `b` three times as
+    /// often as `a`.
+    ///
+    /// The choice is drawn from the expansion's [Context][crate::rule::Context], so it is
+    /// reproducible with [generate_with_seed][Rule::generate_with_seed]. Choices without a positive
+    /// weight are never made, and if there are none the rule expands to nothing.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// let rule = Rule::new().push(
+    ///     Replicate::n(10, Tf::ty(1.1)),
+    ///     Rule::choose(vec![(1.0, cube()), (0.5, icosphere()), (0.1, Rule::new())]),
+    /// );
+    /// ````
+    pub fn choose(choices: Vec<(f32, Rule)>) -> Rule {
+        let choices = choices
+            .into_iter()
+            .map(|(weight, rule)| (weight, RuleInternal::Invocations(Arc::new(rule))))
+            .collect();
+        let mut rule = Rule::new();
+        rule.invocations
+            .push((None, RuleInternal::Choice(Arc::new(choices))));
+        rule
+    }
+
+    /// Returns a rule which expands `rule` with `deformer` applied to every mesh it generates.
+    ///
+    /// Deformers nest: those pushed deeper in the rule tree apply first, and those above them
+    /// deform the result.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// let tower = Rule::new().push(Replicate::n(16, Tf::ty(1.1)), cube());
+    /// let leaning = Rule::new().push(
+    ///     Replicate::n(4, Tf::tx(4.0)),
+    ///     Rule::deform(Deformer::bend(5.0), Rule::deform(Deformer::twist(10.0), tower)),
+    /// );
+    /// ````
+    pub fn deform(deformer: Deformer, rule: impl ToRule) -> Rule {
+        let mut rule_with_deformer = Rule::new();
+        rule_with_deformer.invocations.push((
+            None,
+            RuleInternal::Deformed(Arc::new((
+                deformer,
+                RuleInternal::Invocations(Arc::new(rule)),
+            ))),
+        ));
+        rule_with_deformer
+    }
+
+    /// Returns a rule which invokes `n` subrules computed from their index by `invocation` as they
+    /// are expanded.
+    fn replicated(
+        n: usize,
+        invocation: impl Fn(usize) -> (Option<Transform>, RuleInternal) + Send + Sync + 'static,
+    ) -> Rule {
+        let mut rule = Rule::new();
+        if n > 0 {
+            let replication = Arc::new(Replication {
+                invocation: Box::new(invocation),
+            });
+            rule.invocations
+                .push((None, RuleInternal::Replicated(replication, 0..n)));
+        }
+        rule
+    }
+
     /// Adds a subrule to the Rule.
     pub fn push(mut self, transforms: impl Into<TransformArgument>, rule: impl ToRule) -> Rule {
         match transforms.into() {
+            TransformArgument::Indexed(transforms) => {
+                let rule: Arc<dyn ToRule> = Arc::new(rule);
+                let n = transforms.len();
+                let mut replicated = Rule::replicated(n, move |i| {
+                    (
+                        Some(transforms.get(i)),
+                        RuleInternal::Invocations(rule.clone()),
+                    )
+                });
+                self.invocations.append(&mut replicated.invocations);
+            }
             TransformArgument::Single(transform) => {
                 self.invocations
-                    .push((Some(transform), RuleInternal::Invocations(Rc::new(rule))));
+                    .push((Some(transform), RuleInternal::Invocations(Arc::new(rule))));
             }
             TransformArgument::Many(ref transforms) if !transforms.is_empty() => {
-                let rule = Rc::new(rule);
+                let rule = Arc::new(rule);
                 self.invocations.append(
                     &mut transforms
                         .into_iter()
@@ -98,7 +186,7 @@ impl Rule {
 
             _ => self
                 .invocations
-                .push((None, RuleInternal::Invocations(Rc::new(rule)))),
+                .push((None, RuleInternal::Invocations(Arc::new(rule)))),
         };
         self
     }
@@ -107,20 +195,239 @@ impl Rule {
     /// all rules have been fully expanded. As an iterator the meshes are computed lazily so you can
     /// use this method and terminate with [take][std::iter::Iterator::take], or
     /// [until][std::iter::Iterator::take_while], etc if your rule tree is infinite.
+    ///
+    /// The expansion is seeded randomly. To reproduce a structure, use
+    /// [generate_with_seed][Rule::generate_with_seed].
     pub fn generate(self) -> impl Iterator<Item = OutputMesh> {
-        let root = RuleInternal::Invocations(Rc::new(self));
-        MeshIter::new(vec![(None, root)])
+        self.generate_with_seed(thread_rng().gen())
     }
+
+    /// Returns an iterator like [generate][Rule::generate], but whose expansion is seeded with
+    /// `seed`. Rules which draw their randomness from the [Context][crate::rule::Context] they are
+    /// given will expand to identical meshes for the same seed.
+    pub fn generate_with_seed(self, seed: u64) -> impl Iterator<Item = OutputMesh> {
+        self.generate_with(ExpansionConfig {
+            seed: Some(seed),
+            ..ExpansionConfig::default()
+        })
+    }
+
+    /// Returns an iterator like [generate][Rule::generate], but which expands the rule within the
+    /// limits of `config`. Use this to terminate recursive rules without tracking a depth budget
+    /// in each one.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// struct Spiral;
+    ///
+    /// impl ToRule for Spiral {
+    ///     fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
+    ///         rule![
+    ///             None => cube(),
+    ///             tf![Tf::ty(1.0), Tf::rz(20.0), Tf::s(0.9)] => Spiral,
+    ///         ]
+    ///     }
+    /// }
+    ///
+    /// let meshes = Spiral.to_rule().generate_with(ExpansionConfig {
+    ///     min_size: Some(0.1),
+    ///     ..ExpansionConfig::default()
+    /// });
+    /// # assert_eq!(meshes.count(), 22);
+    /// ````
+    pub fn generate_with(self, config: ExpansionConfig) -> impl Iterator<Item = OutputMesh> {
+        let root = self.root(&config);
+        MeshIter::new(config, vec![root])
+    }
+
+    /// Expands the rule within the limits of `config` like [generate_with][Rule::generate_with],
+    /// but splits the expansion into subtrees and expands them on all available cores.
+    ///
+    /// The meshes are the same, and in the same order, as those
+    /// [generate_with][Rule::generate_with] yields for the same seed, so the output can be written
+    /// out deterministically. Unlike [generate_with][Rule::generate_with] the expansion is not lazy
+    /// and all the meshes are computed before this returns, so infinite rules must be bounded by
+    /// the config's limits.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// let rule = Rule::new().push(
+    ///     Replicate::n(10, Tf::tx(1.1)),
+    ///     Rule::new().push(Replicate::n(10, Tf::tz(1.1)), cube()),
+    /// );
+    /// let meshes: Vec<OutputMesh> = rule.generate_parallel(ExpansionConfig::default()).collect();
+    /// # assert_eq!(meshes.len(), 100);
+    /// ````
+    pub fn generate_parallel(self, config: ExpansionConfig) -> impl Iterator<Item = OutputMesh> {
+        let root = self.root(&config);
+        parallel::expand(config, root).into_iter()
+    }
+
+    fn root(self, config: &ExpansionConfig) -> Invocation {
+        Invocation {
+            transform: None,
+            rule: RuleInternal::Invocations(Arc::new(self)),
+            seed: config.seed.unwrap_or_else(|| thread_rng().gen()),
+            depth: 0,
+            deformation: None,
+        }
+    }
+}
+
+/// Configuration for a rule expansion with [Rule::generate_with][Rule::generate_with].
+///
+/// These limits work like Structure Synth's `set maxdepth`, `set maxobjects`, and `set minsize`.
+#[derive(Clone, Debug, Default)]
+pub struct ExpansionConfig {
+    /// Seed for the expansion's [Context][crate::rule::Context]s. If unset, a random seed is used.
+    pub seed: Option<u64>,
+    /// Maximum depth of nested rule invocations. Invocations nested deeper are culled.
+    ///
+    /// The root rule is at depth 0, the rules it pushes are at depth 1, and so on. Invocations of
+    /// builtin meshes such as [cube][crate::rule::builtin::cube] count as a level too.
+    pub max_depth: Option<usize>,
+    /// Maximum number of meshes to output, after which the expansion stops.
+    pub max_meshes: Option<usize>,
+    /// Minimum size of an invocation, below which it is culled along with everything it would
+    /// expand to.
+    ///
+    /// The size is the largest factor the accumulated transform scales any axis by, so a rule is
+    /// only culled once it is small in every dimension. Builtin meshes are size 1 before scaling.
+    pub min_size: Option<f32>,
+}
+
+/// A rule waiting to be expanded, and the state of the expansion where it was invoked.
+struct Invocation {
+    transform: Option<Transform>,
+    rule: RuleInternal,
+    seed: u64,
+    depth: usize,
+    deformation: Option<Arc<Deformation>>,
 }
 
 /// An iterator that iterates over a [Rule][self::Rule]'s generated meshes.
 pub struct MeshIter {
-    rules: Vec<(Option<Transform>, RuleInternal)>,
+    config: ExpansionConfig,
+    rules: Vec<Invocation>,
+    emitted: usize,
 }
 
 impl MeshIter {
-    fn new(rules: Vec<(Option<Transform>, RuleInternal)>) -> Self {
-        Self { rules }
+    fn new(config: ExpansionConfig, rules: Vec<Invocation>) -> Self {
+        Self {
+            config,
+            rules,
+            emitted: 0,
+        }
+    }
+}
+
+impl ExpansionConfig {
+    fn culls(&self, invocation: &Invocation) -> bool {
+        if let Some(min_size) = self.min_size {
+            if invocation.transform.map(|t| t.size()).unwrap_or(1.0) < min_size {
+                return true;
+            }
+        }
+        match (&invocation.rule, self.max_depth) {
+            (RuleInternal::Invocations(_), Some(max_depth)) => invocation.depth > max_depth,
+            _ => false,
+        }
+    }
+}
+
+impl Invocation {
+    /// Expands the invocation one level. A mesh is returned, and anything else pushes the
+    /// invocations it expands to onto `rules`, in the order their rule pushed them.
+    fn expand(self, rules: &mut Vec<Invocation>) -> Option<OutputMesh> {
+        let Invocation {
+            transform,
+            rule,
+            seed,
+            depth,
+            deformation,
+        } = self;
+        match rule {
+            RuleInternal::Mesh(mesh) => {
+                return Some(OutputMesh {
+                    transform,
+                    source: mesh,
+                    deformation,
+                });
+            }
+            RuleInternal::Deformed(deformed) => {
+                let (deformer, rule) = deformed.as_ref();
+                rules.push(Invocation {
+                    transform,
+                    rule: rule.clone(),
+                    seed,
+                    depth,
+                    deformation: Deformation::push(deformation, deformer.clone(), transform),
+                });
+            }
+            RuleInternal::Choice(choices) => {
+                let mut ctx = Context::new(seed);
+                if let Some(choice) = choose_weighted(&choices, &mut ctx) {
+                    rules.push(Invocation {
+                        transform,
+                        rule: choice.clone(),
+                        seed: derive_seed(seed, 0),
+                        depth,
+                        deformation,
+                    });
+                }
+            }
+            RuleInternal::Replicated(replication, range) => {
+                if range.len() == 1 {
+                    let (sub_transform, sub_rule) = (replication.invocation)(range.start);
+                    rules.push(Invocation {
+                        transform: compose(transform, sub_transform),
+                        rule: sub_rule,
+                        seed: derive_seed(seed, range.start),
+                        depth,
+                        deformation,
+                    });
+                } else if range.len() > 1 {
+                    // Copies are split in halves so only a few wait on the stack at a time. The
+                    // later half is pushed last, like the later invocations of a rule.
+                    let middle = range.start + range.len() / 2;
+                    for half in [range.start..middle, middle..range.end] {
+                        rules.push(Invocation {
+                            transform,
+                            rule: RuleInternal::Replicated(replication.clone(), half),
+                            seed,
+                            depth,
+                            deformation: deformation.clone(),
+                        });
+                    }
+                }
+            }
+            RuleInternal::Invocations(composite_rule) => {
+                let composite_rule = composite_rule.to_rule_with(&mut Context::new(seed));
+                rules.reserve(composite_rule.invocations.len());
+                for (i, (sub_transform, sub_rule)) in
+                    composite_rule.invocations.into_iter().enumerate()
+                {
+                    rules.push(Invocation {
+                        transform: compose(transform, sub_transform),
+                        rule: sub_rule,
+                        seed: derive_seed(seed, i),
+                        depth: depth + 1,
+                        deformation: deformation.clone(),
+                    });
+                }
+            }
+        }
+        None
+    }
+}
+
+fn compose(parent: Option<Transform>, child: Option<Transform>) -> Option<Transform> {
+    match (parent, child) {
+        (None, None) => None,
+        (Some(parent), None) => Some(parent),
+        (Some(parent), Some(child)) => Some(parent.cons(child)),
+        (None, Some(child)) => Some(child),
     }
 }
 
@@ -129,17 +436,18 @@ impl MeshIter {
 pub struct OutputMesh {
     transform: Option<Transform>,
     source: OutputMeshSource,
+    deformation: Option<Arc<Deformation>>,
 }
 
 #[derive(Debug, Clone)]
-enum OutputMeshSource {
+pub(crate) enum OutputMeshSource {
     Primitive(PrimitiveMesh),
-    Dynamic(Rc<Mesh>),
+    Dynamic(Arc<Mesh>),
 }
 
 impl OutputMesh {
     pub(crate) fn color(&self) -> Rgb {
-        self.transform.unwrap_or(Transform::default()).get_color()
+        self.transform().get_color()
     }
 
     /// An iterator over the vertices that compose the mesh. Access `.x`, `.y`, and `.z`.
@@ -148,18 +456,40 @@ impl OutputMesh {
             .vertices()
             .iter()
             .map(move |v: &'a Vertex| -> Vertex {
-                self.transform.map(|t| t.apply_to(*v)).unwrap_or(*v)
+                let v = self.transform.map(|t| t.apply_to(*v)).unwrap_or(*v);
+                match self.deformation {
+                    Some(ref deformation) => deformation.apply_to(v),
+                    None => v,
+                }
             })
     }
 
     /// An iterator over the normals of each vertex if they are defined for the mesh.
+    ///
+    /// Normals are unit length and have a w of 0.
     pub fn normals<'a>(&'a self) -> Option<impl Iterator<Item = Vertex> + 'a> {
-        match self.mesh().normals() {
-            Some(ref normals) => Some(normals.iter().map(move |v: &Vertex| -> Vertex {
-                self.transform.map(|t| t.apply_to(*v)).unwrap_or(*v)
-            })),
-            None => None,
-        }
+        let transform = self.transform();
+        self.mesh().normals().map(move |normals| {
+            normals
+                .iter()
+                .zip(self.mesh().vertices())
+                .map(move |(n, v)| -> Vertex {
+                    let n = transform.apply_to_normal(*n);
+                    match self.deformation {
+                        Some(ref deformation) => {
+                            deformation.apply_to_normal(transform.apply_to(*v), n)
+                        }
+                        None => n,
+                    }
+                })
+        })
+    }
+
+    /// The texture coordinates of each vertex if they are defined for the mesh.
+    ///
+    /// Coordinates are not affected by the mesh's transform.
+    pub fn uvs(&self) -> Option<&[[f32; 2]]> {
+        self.mesh().uvs()
     }
 
     /// An iterator over the faces of the output mesh.
@@ -168,10 +498,38 @@ impl OutputMesh {
     ///
     /// * These faces are not necessarily triangles.
     /// * The vertex indices start at 1.
+    /// * The faces are wound as in the original mesh, so if the mesh is
+    ///   [mirrored][OutputMesh::mirrored] their vertex order must be reversed to keep them facing
+    ///   out.
     pub fn faces<'a>(&'a self) -> impl Iterator<Item = &'a [usize]> {
         self.mesh().faces()
     }
 
+    /// Whether the mesh's transform reflects it, as [Tf::fx][Transform::fx] does, which turns its
+    /// faces inside out unless their winding is reversed.
+    pub fn mirrored(&self) -> bool {
+        self.transform().mirrors()
+    }
+
+    /// An iterator over the polylines of the output mesh, as lists of vertex indices starting at 1.
+    pub fn lines(&self) -> impl Iterator<Item = &[usize]> {
+        self.mesh().lines()
+    }
+
+    pub(crate) fn transform(&self) -> Transform {
+        self.transform.unwrap_or_default()
+    }
+
+    /// Whether the mesh is under a [Deformer][crate::rule::Deformer], so its vertices cannot be
+    /// found from its transform alone.
+    pub(crate) fn deformed(&self) -> bool {
+        self.deformation.is_some()
+    }
+
+    pub(crate) fn source(&self) -> &OutputMeshSource {
+        &self.source
+    }
+
     pub(crate) fn mesh<'a>(&'a self) -> &'a Mesh {
         match self.source {
             OutputMeshSource::Primitive(ref primitive) => primitive.mesh(),
@@ -184,29 +542,16 @@ impl Iterator for MeshIter {
     type Item = OutputMesh;
 
     fn next(&mut self) -> Option<Self::Item> {
-        while let Some((transform, rule)) = self.rules.pop() {
-            match rule {
-                RuleInternal::Mesh(mesh) => {
-                    return Some(OutputMesh {
-                        transform,
-                        source: mesh,
-                    })
-                }
-                RuleInternal::Invocations(composite_rule) => {
-                    let composite_rule = composite_rule.to_rule();
-                    self.rules.reserve(composite_rule.invocations.len());
-                    for (sub_transform, sub_rule) in composite_rule.invocations {
-                        self.rules.push((
-                            match (transform, sub_transform) {
-                                (None, None) => None,
-                                (Some(parent), None) => Some(parent),
-                                (Some(parent), Some(child)) => Some(parent.cons(child)),
-                                (None, Some(child)) => Some(child),
-                            },
-                            sub_rule,
-                        ));
-                    }
-                }
+        if self.config.max_meshes.map(|max| self.emitted >= max) == Some(true) {
+            return None;
+        }
+        while let Some(invocation) = self.rules.pop() {
+            if self.config.culls(&invocation) {
+                continue;
+            }
+            if let Some(mesh) = invocation.expand(&mut self.rules) {
+                self.emitted += 1;
+                return Some(mesh);
             }
         }
         None
@@ -214,25 +559,341 @@ impl Iterator for MeshIter {
 }
 
 /// A trait for types that can become rules.
-pub trait ToRule: 'static {
-    fn to_rule(&self) -> Rule;
+///
+/// Implement [to_rule_with][ToRule::to_rule_with]; types which always build the same rule can
+/// ignore the context.
+pub trait ToRule: Send + Sync + 'static {
+    /// Builds the rule with the context of the expansion it is invoked in. Draw any random values
+    /// from `ctx` so the rule is reproducible when seeded.
+    fn to_rule_with(&self, ctx: &mut Context) -> Rule;
+
+    /// Builds the rule with a randomly seeded [Context][crate::rule::Context].
+    fn to_rule(&self) -> Rule {
+        self.to_rule_with(&mut Context::new(thread_rng().gen()))
+    }
 }
 
 impl ToRule for Rule {
+    fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
+        self.clone()
+    }
+
     fn to_rule(&self) -> Rule {
         self.clone()
     }
 }
 
-impl ToRule for Rc<Mesh> {
+impl ToRule for Arc<Mesh> {
+    fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
+        Rule::mesh(self.clone())
+    }
+
     fn to_rule(&self) -> Rule {
         Rule::mesh(self.clone())
     }
 }
 
+/// Chooses one of `choices` with probability proportional to its weight, skipping those without
+/// a positive weight.
+pub(crate) fn choose_weighted<'a, T>(choices: &'a [(f32, T)], ctx: &mut Context) -> Option<&'a T> {
+    let total: f32 = choices.iter().map(|(weight, _)| weight.max(0.0)).sum();
+    if total <= 0.0 {
+        return None;
+    }
+    let mut target = ctx.gen_range(0.0, total);
+    for (weight, choice) in choices.iter().filter(|(weight, _)| *weight > 0.0) {
+        if target < *weight {
+            return Some(choice);
+        }
+        target -= weight;
+    }
+    // Rounding can leave the target just past the last weight.
+    choices
+        .iter()
+        .rev()
+        .find(|(weight, _)| *weight > 0.0)
+        .map(|(_, choice)| choice)
+}
+
 #[auto_from]
 #[derive(Clone)]
 enum RuleInternal {
     Mesh(OutputMeshSource),
-    Invocations(Rc<ToRule>),
+    Invocations(Arc<dyn ToRule>),
+    Choice(Arc<Vec<(f32, RuleInternal)>>),
+    Deformed(Arc<(Deformer, RuleInternal)>),
+    /// The copies in the range of a replication, which are only computed as they are expanded.
+    Replicated(Arc<Replication>, Range<usize>),
+}
+
+struct Replication {
+    invocation: Box<dyn Fn(usize) -> (Option<Transform>, RuleInternal) + Send + Sync>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::vertex;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    const EPSILON: f32 = 0.0001;
+
+    struct RandomTower;
+
+    impl ToRule for RandomTower {
+        fn to_rule_with(&self, ctx: &mut Context) -> Rule {
+            let offset = ctx.gen_range(-1.0, 1.0);
+            if ctx.gen_bool(0.8) {
+                Rule::new()
+                    .push(Tf::tx(offset), cube())
+                    .push(Tf::ty(1.0), RandomTower)
+            } else {
+                Rule::new().push(Replicate::n(2, Tf::tz(offset)), cube())
+            }
+        }
+    }
+
+    fn expand(rule: Rule, seed: u64) -> Vec<Vec<Vertex>> {
+        rule.generate_with_seed(seed)
+            .map(|output| output.vertices().collect())
+            .collect()
+    }
+
+    fn transformed_sphere(transform: Transform) -> OutputMesh {
+        Rule::new()
+            .push(transform, sphere(2))
+            .generate()
+            .next()
+            .expect("sphere mesh")
+    }
+
+    fn assert_normals_match(output: &OutputMesh, expected: impl Fn(Vertex) -> Vertex) {
+        let normals = output.normals().expect("sphere normals");
+        for (vertex, normal) in output.vertices().zip(normals) {
+            let expected = expected(vertex).xyz().normalize();
+            assert!((normal.xyz().norm() - 1.0).abs() < EPSILON);
+            assert_eq!(normal.w, 0.0);
+            assert!(
+                (normal.xyz() - expected).norm() < EPSILON,
+                "normal {:?} at {:?} should be {:?}",
+                normal,
+                vertex,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn seeded_expansions_are_identical() {
+        let rule = || Rule::new().push(Replicate::n(5, Tf::tx(2.0)), RandomTower);
+        assert_eq!(expand(rule(), 7), expand(rule(), 7));
+        assert_ne!(expand(rule(), 7), expand(rule(), 8));
+    }
+
+    #[test]
+    fn parallel_expansion_matches_sequential_order() {
+        let rule = || {
+            Rule::new()
+                .push(Replicate::n(40, Tf::tx(2.0)), RandomTower)
+                .push(
+                    Replicate::n(40, Tf::tz(2.0)),
+                    Rule::choose(vec![(1.0, cube()), (1.0, Recursive.to_rule())]),
+                )
+        };
+        let vertices = |meshes: Vec<OutputMesh>| -> Vec<Vec<Vertex>> {
+            meshes
+                .iter()
+                .map(|output| output.vertices().collect())
+                .collect()
+        };
+        let configs = vec![
+            ExpansionConfig {
+                seed: Some(11),
+                max_depth: Some(12),
+                ..ExpansionConfig::default()
+            },
+            ExpansionConfig {
+                seed: Some(12),
+                max_meshes: Some(150),
+                ..ExpansionConfig::default()
+            },
+            ExpansionConfig {
+                seed: Some(13),
+                min_size: Some(0.2),
+                max_depth: Some(30),
+                ..ExpansionConfig::default()
+            },
+        ];
+        for config in configs {
+            let sequential: Vec<OutputMesh> = rule().generate_with(config.clone()).collect();
+            let parallel: Vec<OutputMesh> = rule().generate_parallel(config).collect();
+            assert!(!sequential.is_empty());
+            assert_eq!(vertices(sequential), vertices(parallel));
+        }
+    }
+
+    #[test]
+    fn choices_follow_weights() {
+        let rule = Rule::new().push(
+            Replicate::n(1000, Tf::tx(1.0)),
+            Rule::choose(vec![
+                (3.0, cube()),
+                (1.0, Rule::new().push(Tf::ty(5.0), cube())),
+                (0.0, icosphere()),
+                (-1.0, icosphere()),
+            ]),
+        );
+        let heights: Vec<f32> = rule
+            .generate_with_seed(3)
+            .map(|output| output.vertices().next().unwrap().y)
+            .collect();
+        let raised = heights.iter().filter(|y| **y > 1.0).count();
+        assert_eq!(heights.len(), 1000);
+        assert!(raised > 200 && raised < 300, "{} of 1000 raised", raised);
+    }
+
+    #[test]
+    fn choice_without_weight_is_empty() {
+        let rule = Rule::choose(vec![(0.0, cube())]);
+        assert_eq!(rule.generate().count(), 0);
+    }
+
+    struct Recursive;
+
+    impl ToRule for Recursive {
+        fn to_rule_with(&self, _ctx: &mut Context) -> Rule {
+            // The recursion is pushed first so that the cube is expanded before it.
+            rule![
+                vec![Tf::tx(1.0), Tf::s(0.5)] => Recursive,
+                None => cube(),
+            ]
+        }
+    }
+
+    fn expand_with(rule: Rule, config: ExpansionConfig) -> usize {
+        rule.generate_with(config).count()
+    }
+
+    #[test]
+    fn max_depth_terminates_recursion() {
+        let config = |max_depth| ExpansionConfig {
+            max_depth: Some(max_depth),
+            ..ExpansionConfig::default()
+        };
+        assert_eq!(expand_with(Recursive.to_rule(), config(0)), 0);
+        assert_eq!(expand_with(Recursive.to_rule(), config(1)), 1);
+        assert_eq!(expand_with(Recursive.to_rule(), config(2)), 2);
+        assert_eq!(expand_with(Recursive.to_rule(), config(9)), 9);
+    }
+
+    #[test]
+    fn max_meshes_stops_expansion() {
+        let config = ExpansionConfig {
+            max_meshes: Some(7),
+            ..ExpansionConfig::default()
+        };
+        assert_eq!(expand_with(Recursive.to_rule(), config), 7);
+    }
+
+    #[test]
+    fn min_size_culls_small_invocations() {
+        let config = ExpansionConfig {
+            min_size: Some(0.1),
+            ..ExpansionConfig::default()
+        };
+        // Sizes 1, 0.5, 0.25, 0.125 are kept and 0.0625 is culled.
+        assert_eq!(expand_with(Recursive.to_rule(), config), 4);
+
+        let flat = Rule::new().push(Tf::sby(1.0, 0.01, 1.0), cube());
+        let config = ExpansionConfig {
+            min_size: Some(0.1),
+            ..ExpansionConfig::default()
+        };
+        assert_eq!(expand_with(flat, config), 1);
+    }
+
+    #[test]
+    fn translated_sphere_normals_point_away_from_center() {
+        let output = transformed_sphere(Tf::t(4.0, -2.0, 1.0));
+        assert_normals_match(&output, |v| v - vertex(4.0, -2.0, 1.0));
+    }
+
+    #[test]
+    fn rotated_sphere_normals_point_away_from_center() {
+        let transform = Tf::rz(30.0).cons(Tf::rx(70.0)).cons(Tf::tx(2.0));
+        let center = transform.apply_to(vertex(0.0, 0.0, 0.0));
+        let output = transformed_sphere(transform);
+        assert_normals_match(&output, |v| v - center);
+    }
+
+    #[test]
+    fn scaled_sphere_normals_follow_ellipsoid_gradient() {
+        let (a, b, c) = (1.0, 0.5, 0.25);
+        let output = transformed_sphere(Tf::t(1.0, 2.0, 3.0).cons(Tf::sby(2.0, 1.0, 0.5)));
+        assert_normals_match(&output, |v| {
+            let v = v - vertex(1.0, 2.0, 3.0);
+            vertex(v.x / (a * a), v.y / (b * b), v.z / (c * c))
+        });
+    }
+
+    #[test]
+    fn scaled_and_rotated_sphere_normals_follow_ellipsoid_gradient() {
+        let transform = Tf::ry(45.0).cons(Tf::sby(2.0, 1.0, 0.5));
+        let center = transform.apply_to(vertex(0.0, 0.0, 0.0));
+        let output = transformed_sphere(transform);
+        let rotate = |degrees: f32, v: Vertex| Tf::ry(degrees).apply_to(v);
+        assert_normals_match(&output, |v| {
+            let local = rotate(-45.0, v - center);
+            let gradient = Vertex::new(local.x, local.y * 4.0, local.z * 16.0, 0.0);
+            rotate(45.0, gradient)
+        });
+    }
+
+    #[test]
+    fn indexed_replications_expand_like_listed_ones() {
+        let indexed = Rule::new().push(Replicate::with(5, |i| Tf::tx(i as f32 + 1.0)), cube());
+        let listed = Rule::new().push(Replicate::n(5, Tf::tx(1.0)), cube());
+        assert_eq!(expand(indexed, 0), expand(listed, 0));
+    }
+
+    #[test]
+    fn indexed_replications_are_computed_as_they_expand() {
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let rule = Rule::new().push(
+            Replicate::with(1_000_000_000, move |i| {
+                counter.fetch_add(1, Ordering::SeqCst);
+                Tf::tx(i as f32)
+            }),
+            cube(),
+        );
+        let config = ExpansionConfig {
+            max_meshes: Some(3),
+            ..ExpansionConfig::default()
+        };
+        assert_eq!(expand_with(rule, config), 3);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn indexed_subrules_expand_at_the_depth_of_listed_ones() {
+        let row = |i: usize| Rule::new().push(Replicate::n(i + 1, Tf::tx(1.1)), cube());
+        let indexed = || Replicate::with_rules(3, move |i| (Tf::ty(i as f32 * 1.1), row(i)));
+        let listed = || {
+            (0..3).fold(Rule::new(), |rule, i| {
+                rule.push(Tf::ty(i as f32 * 1.1), row(i))
+            })
+        };
+        assert_eq!(expand(indexed(), 0), expand(listed(), 0));
+        for max_depth in 0..4 {
+            let config = ExpansionConfig {
+                max_depth: Some(max_depth),
+                ..ExpansionConfig::default()
+            };
+            assert_eq!(
+                expand_with(Rule::new().push(None, indexed()), config.clone()),
+                expand_with(Rule::new().push(None, listed()), config)
+            );
+        }
+    }
 }
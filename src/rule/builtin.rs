@@ -12,9 +12,57 @@
 // See the License for the specific language governing permissions and
 // limitations under the License.
 
-use crate::mesh::{sphere_of_resolution, Mesh, PrimitiveMesh};
+use crate::mesh::{primitives, sphere_of_resolution, Mesh, PrimitiveMesh, Vertex};
 use crate::rule::Rule;
-use std::rc::Rc;
+use lazy_static::lazy_static;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, Weak};
+
+/// The parameters of a parameterized primitive. Floats are compared by their bits.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+enum Parameters {
+    Cylinder {
+        segments: usize,
+    },
+    Cone {
+        segments: usize,
+    },
+    Torus {
+        major: u32,
+        minor: u32,
+        segments: usize,
+        rings: usize,
+    },
+    Capsule {
+        radius: u32,
+        segments: usize,
+    },
+    Disc {
+        segments: usize,
+    },
+}
+
+lazy_static! {
+    // Only weak references are kept, so a mesh is freed once no rule holds it.
+    static ref PARAMETERIZED_MESHES: Mutex<HashMap<Parameters, Weak<Mesh>>> =
+        Mutex::new(HashMap::new());
+}
+
+/// Returns a rule for the mesh of a parameterized primitive, building it only if no rule with the
+/// same parameters is still alive. Rules sharing a mesh are written out once by instanced glTF
+/// export.
+fn parameterized(parameters: Parameters, build: impl FnOnce() -> Mesh) -> Rule {
+    let mut meshes = PARAMETERIZED_MESHES
+        .lock()
+        .expect("parameterized primitive cache");
+    if let Some(mesh) = meshes.get(&parameters).and_then(Weak::upgrade) {
+        return Rule::mesh(mesh);
+    }
+    meshes.retain(|_, mesh| mesh.strong_count() > 0);
+    let mesh = Arc::new(build());
+    meshes.insert(parameters, Arc::downgrade(&mesh));
+    Rule::mesh(mesh)
+}
 
 /// A cube of size 1 whose center is at the origin.
 pub fn cube() -> Rule {
@@ -26,9 +74,113 @@ pub fn icosphere() -> Rule {
     Rule::primitive(PrimitiveMesh::IcoSphere)
 }
 
+/// A cylinder of diameter 1 and height 1 whose center is at the origin and whose axis is the y
+/// axis. Its round side is made of `segments` faces.
+pub fn cylinder(segments: usize) -> Rule {
+    let segments = segments.max(3);
+    parameterized(Parameters::Cylinder { segments }, || {
+        primitives::cylinder(segments)
+    })
+}
+
+/// A cone with a base of diameter 1 and a height of 1 whose center is at the origin, pointing up
+/// the y axis. Its round side is made of `segments` faces.
+pub fn cone(segments: usize) -> Rule {
+    let segments = segments.max(3);
+    parameterized(Parameters::Cone { segments }, || primitives::cone(segments))
+}
+
+/// A torus of outer diameter 1 whose center is at the origin, lying flat around the y axis.
+///
+/// `major` is the radius of the ring and `minor` the radius of its tube. Only their ratio matters,
+/// since the torus is scaled to size 1; `torus(0.35, 0.15, ..)` has exactly those radii. The ring
+/// is made of `segments` sections, and the tube of `rings` faces around.
+pub fn torus(major: f32, minor: f32, segments: usize, rings: usize) -> Rule {
+    let (major, minor) = (major.abs(), minor.abs());
+    let (major, minor) = if major + minor > 0.0 {
+        (major, minor)
+    } else {
+        (0.5, 0.0)
+    };
+    let (segments, rings) = (segments.max(3), rings.max(3));
+    let parameters = Parameters::Torus {
+        major: major.to_bits(),
+        minor: minor.to_bits(),
+        segments,
+        rings,
+    };
+    parameterized(parameters, || {
+        primitives::torus(major, minor, segments, rings)
+    })
+}
+
+/// A capsule of height 1 whose center is at the origin and whose axis is the y axis: a cylinder
+/// of the given `radius`, at most 0.5, capped with hemispheres. It is made of `segments` faces
+/// around.
+pub fn capsule(radius: f32, segments: usize) -> Rule {
+    let radius = if radius > 0.0 { radius.min(0.5) } else { 0.0 };
+    let segments = segments.max(3);
+    let parameters = Parameters::Capsule {
+        radius: radius.to_bits(),
+        segments,
+    };
+    parameterized(parameters, || primitives::capsule(radius, segments))
+}
+
+/// A square of size 1 whose center is at the origin, lying in the xz plane.
+///
+/// The quad has no thickness and is one sided, facing up the y axis.
+pub fn quad() -> Rule {
+    Rule::primitive(PrimitiveMesh::Quad)
+}
+
+/// A disc of diameter 1 whose center is at the origin, lying in the xz plane. Its rim is made of
+/// `segments` edges.
+///
+/// The disc has no thickness and is one sided, facing up the y axis.
+pub fn disc(segments: usize) -> Rule {
+    let segments = segments.max(3);
+    parameterized(Parameters::Disc { segments }, || primitives::disc(segments))
+}
+
+/// A triangle spanning the square of size 1 whose center is at the origin, lying in the xz plane.
+/// Its tip points down the z axis.
+///
+/// The triangle has no thickness and is one sided, facing up the y axis.
+pub fn triangle() -> Rule {
+    Rule::primitive(PrimitiveMesh::Triangle)
+}
+
+/// A line segment of length 1 whose center is at the origin, along the x axis.
+///
+/// Lines have no faces, so they are only written out by
+/// [write_meshes][crate::write_meshes], as object file line elements.
+///
+/// ````
+/// # use failure::{Error};
+/// # let _ = || -> Result<(), Error> {
+/// use immense::*;
+///
+/// let wireframe = Rule::new().push(Replicate::n(2, Tf::ty(1.0)), line());
+/// let mut obj = vec![];
+/// write_meshes(ExportConfig::default(), wireframe.generate(), &mut obj)?;
+/// # assert!(String::from_utf8(obj)?.ends_with("l 1 2\nv -0.5 2 0\nv 0.5 2 0\nl 3 4\n"));
+/// # Ok(())
+/// # };
+/// ````
+pub fn line() -> Rule {
+    Rule::primitive(PrimitiveMesh::Line)
+}
+
+/// A polyline through the given points. Like [line][self::line], it is only written out by
+/// [write_meshes][crate::write_meshes].
+pub fn polyline(points: Vec<Vertex>) -> Rule {
+    Rule::mesh(Arc::new(primitives::polyline(points)))
+}
+
 /// A sphere of the given resolution. Produces 20 * 4 ^ resolution polygons to estimate the sphere.
 ///
-/// This is an expensive mesh. Try to call this function once and use the Rc wherever needed.
-pub fn sphere(resolution: usize) -> Rc<Mesh> {
-    Rc::new(sphere_of_resolution(resolution))
+/// This is an expensive mesh. Try to call this function once and use the Arc wherever needed.
+pub fn sphere(resolution: usize) -> Arc<Mesh> {
+    Arc::new(sphere_of_resolution(resolution))
 }
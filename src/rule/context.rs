@@ -0,0 +1,74 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use rand::{Error, RngCore, SeedableRng};
+use rand_xorshift::XorShiftRng;
+
+/// The state of a rule expansion, passed to [ToRule::to_rule_with][crate::rule::ToRule::to_rule_with].
+///
+/// A Context is a random number generator seeded deterministically from the seed the expansion
+/// started with, so use it instead of [thread_rng][rand::thread_rng] and your structures will be
+/// reproducible with [Rule::generate_with_seed][crate::rule::Rule::generate_with_seed].
+pub struct Context {
+    seed: u64,
+    rng: XorShiftRng,
+}
+
+impl Context {
+    /// Returns a context seeded with `seed`.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            seed,
+            rng: XorShiftRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The seed this context was created with.
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+}
+
+impl RngCore for Context {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
+
+/// Derives the seed for the subrule at `index` in a rule expanded with `seed`.
+///
+/// This is one step of SplitMix64, so sibling and nested seeds are well distributed even though
+/// indices are small and sequential.
+pub(crate) fn derive_seed(seed: u64, index: usize) -> u64 {
+    let mut z = seed.wrapping_add(
+        (index as u64)
+            .wrapping_add(1)
+            .wrapping_mul(0x9E37_79B9_7F4A_7C15),
+    );
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
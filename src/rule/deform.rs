@@ -0,0 +1,371 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::mesh::{vertex, Vertex};
+use crate::rule::Transform;
+use nalgebra::{Matrix4, Vector3};
+use noise::{Fbm, MultiFractal, NoiseFn, Seedable};
+use std::fmt;
+use std::sync::Arc;
+
+/// The step the Jacobian of a deformer is estimated with, in the deformer's space. Far from the
+/// origin it is scaled by the distance, so that it stays well above the precision of positions.
+const JACOBIAN_STEP: f32 = 0.001;
+
+/// The space a [Deformer][Deformer] reads and moves vertices in.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DeformSpace {
+    /// The space of the rule the deformer was pushed into, so the deformer follows the transforms
+    /// above it.
+    Local,
+    /// The root space of the expansion, where meshes are output.
+    World,
+}
+
+/// The default is [DeformSpace::Local][DeformSpace::Local].
+impl Default for DeformSpace {
+    fn default() -> DeformSpace {
+        DeformSpace::Local
+    }
+}
+
+/// A non-linear deformation of space, applied to every vertex of the meshes under a rule with
+/// [Rule::deform][crate::rule::Rule::deform].
+///
+/// Transforms are matrices, so they can only move meshes in straight lines. Deformers are
+/// arbitrary functions of a vertex's position, so they can bend a tower or twist a column after
+/// the rest of the structure is built. Normals are recomputed from the deformer's Jacobian, so
+/// hard edges stay hard. The built in deformers work along the y axis of their
+/// [space][DeformSpace]; transform the deformed rule to deform along another axis.
+///
+/// ````
+/// # use immense::*;
+/// let slab = Rule::new().push(Tf::sby(1.0, 0.5, 1.0), cube());
+/// let column = Rule::new().push(Replicate::n(20, Tf::ty(0.5)), slab);
+/// let twisted = Rule::deform(Deformer::twist(9.0), column.clone());
+/// let arch = Rule::deform(Deformer::bend(9.0), column);
+/// ````
+#[derive(Clone)]
+pub struct Deformer {
+    function: Arc<dyn Fn(Vertex) -> Vertex + Send + Sync>,
+    space: DeformSpace,
+}
+
+impl fmt::Debug for Deformer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Deformer")
+            .field("space", &self.space)
+            .finish()
+    }
+}
+
+impl Deformer {
+    /// A deformer which moves each vertex to `function` of its position. The w of the result is
+    /// ignored.
+    pub fn new(function: impl Fn(Vertex) -> Vertex + Send + Sync + 'static) -> Self {
+        Self {
+            function: Arc::new(function),
+            space: DeformSpace::default(),
+        }
+    }
+
+    /// Twists space about the y axis, turning each vertex `degrees` for every unit of its height.
+    pub fn twist(degrees: f32) -> Self {
+        let rate = degrees.to_radians();
+        Self::new(move |p| {
+            let (sin, cos) = (p.y * rate).sin_cos();
+            vertex(p.x * cos + p.z * sin, p.y, p.z * cos - p.x * sin)
+        })
+    }
+
+    /// Bends the y axis into an arc toward +x, turning `degrees` for every unit of height. Lines
+    /// parallel to the y axis bend around the same center, so the bent mesh keeps its thickness.
+    pub fn bend(degrees: f32) -> Self {
+        let curvature = degrees.to_radians();
+        Self::new(move |p| {
+            if curvature == 0.0 {
+                return vertex(p.x, p.y, p.z);
+            }
+            let (sin, cos) = (p.y * curvature).sin_cos();
+            let radius = 1.0 / curvature - p.x;
+            vertex(1.0 / curvature - radius * cos, radius * sin, p.z)
+        })
+    }
+
+    /// Scales x and z by `1 + rate * y`, so meshes narrow toward +y for negative rates and widen
+    /// for positive ones.
+    pub fn taper(rate: f32) -> Self {
+        Self::new(move |p| {
+            let factor = 1.0 + rate * p.y;
+            vertex(p.x * factor, p.y, p.z * factor)
+        })
+    }
+
+    /// Displaces vertices by up to `amplitude` along each axis with
+    /// [fractal Brownian motion](https://en.wikipedia.org/wiki/Fractional_Brownian_motion), a sum
+    /// of `octaves` layers of Perlin noise with the first at `frequency` cycles per unit. A single
+    /// octave is plain Perlin noise. Each seed gives a different displacement.
+    pub fn noise(amplitude: f32, frequency: f32, octaves: usize, seed: u32) -> Self {
+        let field = |seed: u32| {
+            Fbm::new()
+                .set_seed(seed)
+                .set_octaves(octaves.max(1))
+                .set_frequency(frequency as f64)
+        };
+        let (x, y, z) = (
+            field(seed),
+            field(seed.wrapping_add(1)),
+            field(seed.wrapping_add(2)),
+        );
+        Self::new(move |p| {
+            let point = [p.x as f64, p.y as f64, p.z as f64];
+            let offset = |field: &Fbm| field.get(point) as f32 * amplitude;
+            vertex(p.x + offset(&x), p.y + offset(&y), p.z + offset(&z))
+        })
+    }
+
+    /// Sets the space the deformer works in. The default is
+    /// [DeformSpace::Local][DeformSpace::Local].
+    pub fn space(mut self, space: DeformSpace) -> Self {
+        self.space = space;
+        self
+    }
+
+    fn apply(&self, position: Vertex) -> Vertex {
+        let deformed = (self.function)(position);
+        vertex(deformed.x, deformed.y, deformed.z)
+    }
+
+    /// Estimates the Jacobian of the deformer at `position` by central differences.
+    fn jacobian(&self, position: Vertex) -> Transform {
+        let length = JACOBIAN_STEP * position.xyz().norm().max(1.0);
+        let mut jacobian = Matrix4::identity();
+        for axis in 0..3 {
+            let mut step = Vector3::zeros();
+            step[axis] = length;
+            let step = Vertex::new(step.x, step.y, step.z, 0.0);
+            let column =
+                (self.apply(position + step) - self.apply(position - step)) / (2.0 * length);
+            jacobian.set_column(axis, &column);
+        }
+        Transform::matrix(jacobian)
+    }
+}
+
+/// The deformers an invocation is under, innermost first, each with the space it works in.
+#[derive(Clone, Debug)]
+pub(crate) struct Deformation {
+    deformer: Deformer,
+    /// The transform into world space of a local deformer, and its inverse.
+    frame: Option<(Transform, Transform)>,
+    outer: Option<Arc<Deformation>>,
+}
+
+impl Deformation {
+    /// Adds `deformer` inside the deformers in `outer`, working in the space of `transform` if it
+    /// is local. A local deformer in a space with a collapsed axis cannot see its meshes, and is
+    /// dropped.
+    pub(crate) fn push(
+        outer: Option<Arc<Deformation>>,
+        deformer: Deformer,
+        transform: Option<Transform>,
+    ) -> Option<Arc<Deformation>> {
+        let frame = match (deformer.space, transform) {
+            (DeformSpace::Local, Some(transform)) => match transform.inverse() {
+                Some(inverse) => Some((transform, inverse)),
+                None => return outer,
+            },
+            _ => None,
+        };
+        Some(Arc::new(Deformation {
+            deformer,
+            frame,
+            outer,
+        }))
+    }
+
+    fn links(&self) -> impl Iterator<Item = &Deformation> {
+        let mut link = Some(self);
+        std::iter::from_fn(move || {
+            let current = link?;
+            link = current.outer.as_ref().map(|outer| outer.as_ref());
+            Some(current)
+        })
+    }
+
+    /// Deforms a vertex already in world space.
+    pub(crate) fn apply_to(&self, position: Vertex) -> Vertex {
+        self.links()
+            .fold(position, |position, link| match link.frame {
+                Some((frame, inverse)) => {
+                    frame.apply_to(link.deformer.apply(inverse.apply_to(position)))
+                }
+                None => link.deformer.apply(position),
+            })
+    }
+
+    /// Deforms a world space normal at the world space `position` it was found at.
+    pub(crate) fn apply_to_normal(&self, position: Vertex, normal: Vertex) -> Vertex {
+        let mut position = position;
+        let mut normal = normal;
+        for link in self.links() {
+            match link.frame {
+                Some((frame, inverse)) => {
+                    let local = inverse.apply_to(position);
+                    let local_normal = inverse.apply_to_normal(normal);
+                    normal = frame.apply_to_normal(
+                        link.deformer.jacobian(local).apply_to_normal(local_normal),
+                    );
+                    position = frame.apply_to(link.deformer.apply(local));
+                }
+                None => {
+                    normal = link.deformer.jacobian(position).apply_to_normal(normal);
+                    position = link.deformer.apply(position);
+                }
+            }
+        }
+        normal
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rule::{cube, cylinder, sphere, OutputMesh, Rule, Tf, ToRule};
+
+    const EPSILON: f32 = 0.001;
+
+    fn output(rule: impl ToRule) -> Vec<OutputMesh> {
+        rule.to_rule().generate_with_seed(0).collect()
+    }
+
+    fn assert_close(actual: Vertex, expected: Vertex) {
+        assert!(
+            (actual.xyz() - expected.xyz()).norm() < EPSILON,
+            "{:?} should be {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn twists_turn_with_height() {
+        let rule = Rule::new().push(Tf::ty(2.0), cube());
+        let twisted = output(Rule::deform(Deformer::twist(45.0), rule.clone()));
+        let plain = output(rule);
+        for (actual, p) in twisted[0].vertices().zip(plain[0].vertices()) {
+            let turned = Tf::r_axis(Vertex::y(), 45.0 * p.y).apply_to(p);
+            assert_close(actual, turned);
+        }
+    }
+
+    #[test]
+    fn local_deformers_follow_their_rule() {
+        let frame = Tf::tx(5.0).cons(Tf::s(2.0));
+        let block = Rule::new().push(Tf::ty(1.0), cube());
+        let bent = |space| {
+            let rule = Rule::deform(Deformer::bend(90.0).space(space), block.clone());
+            output(Rule::new().push(frame, rule)).remove(0)
+        };
+        let plain = output(Rule::new().push(frame, block.clone())).remove(0);
+        let bend = Deformer::bend(90.0);
+        let (local, world) = (bent(DeformSpace::Local), bent(DeformSpace::World));
+        for ((p, local), world) in plain.vertices().zip(local.vertices()).zip(world.vertices()) {
+            let in_frame = vertex((p.x - 5.0) / 2.0, p.y / 2.0, p.z / 2.0);
+            let bent = bend.apply(in_frame);
+            assert_close(
+                local,
+                vertex(5.0 + bent.x * 2.0, bent.y * 2.0, bent.z * 2.0),
+            );
+            assert_close(world, bend.apply(p));
+        }
+    }
+
+    #[test]
+    fn inner_deformers_apply_first() {
+        let double = Deformer::new(|p| vertex(p.x * 2.0, p.y * 2.0, p.z * 2.0));
+        let shift = Deformer::new(|p| vertex(p.x + 1.0, p.y, p.z));
+        let mesh = output(Rule::deform(double, Rule::deform(shift, cube()))).remove(0);
+        let center = mesh.vertices().sum::<Vertex>() / 8.0;
+        assert_close(center, vertex(2.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn rigid_deformations_move_normals_like_transforms() {
+        let motion = Tf::t(1.0, -2.0, 0.5).cons(Tf::r_axis(vertex(1.0, 1.0, 0.0), 60.0));
+        let deformed = output(Rule::deform(
+            Deformer::new(move |p| motion.apply_to(p)),
+            Rule::new().push(Tf::sby(2.0, 1.0, 1.0), sphere(1)),
+        ));
+        let transformed = output(Rule::new().push(motion.cons(Tf::sby(2.0, 1.0, 1.0)), sphere(1)));
+        let (deformed, transformed) = (&deformed[0], &transformed[0]);
+        for (actual, expected) in deformed.vertices().zip(transformed.vertices()) {
+            assert_close(actual, expected);
+        }
+        let normals = deformed.normals().expect("normals");
+        for (actual, expected) in normals.zip(transformed.normals().expect("normals")) {
+            assert_close(actual, expected);
+        }
+    }
+
+    #[test]
+    fn normals_hold_far_from_the_origin() {
+        let motion = Tf::r_axis(vertex(1.0, 1.0, 0.0), 60.0);
+        let placement = Tf::t(2.0e4, -3.0e4, 1.0e4);
+        let deformed = output(Rule::deform(
+            Deformer::new(move |p| motion.apply_to(p)),
+            Rule::new().push(placement, sphere(1)),
+        ));
+        let transformed = output(Rule::new().push(motion.cons(placement), sphere(1)));
+        let normals = deformed[0].normals().expect("normals");
+        for (actual, expected) in normals.zip(transformed[0].normals().expect("normals")) {
+            assert!((actual - expected).norm() < 0.01);
+        }
+    }
+
+    #[test]
+    fn tapered_normals_lean_with_the_surface() {
+        let rate = -0.5;
+        let mesh = output(Rule::deform(Deformer::taper(rate), cylinder(16))).remove(0);
+        let original = mesh.mesh().normals().expect("normals");
+        let normals: Vec<Vertex> = mesh.normals().expect("normals").collect();
+        let mut sides = 0;
+        for (actual, before) in normals.iter().zip(original) {
+            assert!((actual.norm() - 1.0).abs() < EPSILON);
+            if before.y.abs() < EPSILON {
+                let expected = Vertex::new(before.x, -0.5 * rate, before.z, 0.0).normalize();
+                assert_close(*actual, expected);
+                sides += 1;
+            }
+        }
+        assert!(sides > 0);
+    }
+
+    #[test]
+    fn noise_is_seeded_and_bounded() {
+        let noisy = |seed| {
+            let rule = Rule::deform(Deformer::noise(0.1, 2.0, 4, seed), sphere(2));
+            output(rule).remove(0).vertices().collect::<Vec<_>>()
+        };
+        let plain: Vec<Vertex> = output(sphere(2)).remove(0).vertices().collect();
+        assert_eq!(noisy(1), noisy(1));
+        assert_ne!(noisy(1), noisy(2));
+        let moved = noisy(1)
+            .iter()
+            .zip(&plain)
+            .map(|(a, b)| (a - b).norm())
+            .fold(0.0, f32::max);
+        assert!(moved > 0.0 && moved < 0.1 * 3f32.sqrt() * 2.0);
+    }
+}
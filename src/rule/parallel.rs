@@ -0,0 +1,183 @@
+// Copyright 2018 The immense Authors
+//
+// Licensed under the Apache License, Version 2.0 (the "License");
+// you may not use this file except in compliance with the License.
+// You may obtain a copy of the License at
+//
+//     https://www.apache.org/licenses/LICENSE-2.0
+//
+// Unless required by applicable law or agreed to in writing, software
+// distributed under the License is distributed on an "AS IS" BASIS,
+// WITHOUT WARRANTIES OR CONDITIONS OF ANY KIND, either express or implied.
+// See the License for the specific language governing permissions and
+// limitations under the License.
+
+use crate::rule::{ExpansionConfig, Invocation, MeshIter, OutputMesh};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// How many subtrees to split the expansion into per thread, so threads that finish early can
+/// pick up more work.
+const SUBTREES_PER_THREAD: usize = 8;
+
+/// How many levels to expand while looking for enough subtrees. This bounds the split of rules
+/// which only ever invoke one subrule.
+const MAX_SPLIT_DEPTH: usize = 32;
+
+/// A part of the expansion, in output order.
+enum Work {
+    Done(OutputMesh),
+    Pending(Invocation),
+}
+
+/// How many meshes each subtree has emitted so far, shared between the threads so that a subtree
+/// stops once the subtrees before it fill the mesh limit.
+struct Budget {
+    max_meshes: Option<usize>,
+    emitted: Vec<AtomicUsize>,
+}
+
+impl Budget {
+    fn new(max_meshes: Option<usize>, subtrees: usize) -> Self {
+        Self {
+            max_meshes,
+            emitted: (0..subtrees).map(|_| AtomicUsize::new(0)).collect(),
+        }
+    }
+
+    /// Records that the subtree at `index` has emitted `count` meshes, and returns whether any
+    /// more it emits would be past the limit.
+    ///
+    /// The subtrees before it may still be expanding, but they can only emit more meshes, so the
+    /// running count is a lower bound on where its meshes fall in the output.
+    fn spent(&self, index: usize, count: usize) -> bool {
+        let max_meshes = match self.max_meshes {
+            Some(max_meshes) => max_meshes,
+            None => return false,
+        };
+        self.emitted[index].store(count, Ordering::Relaxed);
+        let before: usize = self.emitted[..index]
+            .iter()
+            .map(|emitted| emitted.load(Ordering::Relaxed))
+            .sum();
+        before + count >= max_meshes
+    }
+}
+
+/// Expands `root` across all available cores, returning the meshes in the order a
+/// [MeshIter][crate::rule::MeshIter] would yield them.
+///
+/// The iterator's stack yields everything the invocation on top expands to before it moves on to
+/// the next one, so its output is the concatenation of each invocation's own expansion from the
+/// top of the stack down. That lets subtrees be expanded independently and concatenated.
+pub(super) fn expand(config: ExpansionConfig, root: Invocation) -> Vec<OutputMesh> {
+    let threads = thread::available_parallelism()
+        .map(|n| n.get())
+        .unwrap_or(1);
+    let subtrees = split(&config, root, threads * SUBTREES_PER_THREAD);
+    let subtree_count = subtrees.len();
+    let budget = Budget::new(config.max_meshes, subtree_count);
+    let queue = Mutex::new(subtrees.into_iter().enumerate());
+
+    let mut expansions: Vec<(usize, Vec<OutputMesh>)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads.min(subtree_count))
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut expansions = vec![];
+                    loop {
+                        let next = queue.lock().expect("subtree queue").next();
+                        let (index, work) = match next {
+                            Some(next) => next,
+                            None => break,
+                        };
+                        let mut meshes = vec![];
+                        match work {
+                            Work::Done(mesh) => meshes.push(mesh),
+                            Work::Pending(invocation) => {
+                                if !budget.spent(index, 0) {
+                                    for mesh in MeshIter::new(config.clone(), vec![invocation]) {
+                                        meshes.push(mesh);
+                                        if budget.spent(index, meshes.len()) {
+                                            break;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        budget.spent(index, meshes.len());
+                        expansions.push((index, meshes));
+                    }
+                    expansions
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| worker.join().expect("expansion thread"))
+            .collect()
+    });
+
+    expansions.sort_by_key(|(index, _)| *index);
+    let mut meshes: Vec<OutputMesh> = expansions
+        .into_iter()
+        .flat_map(|(_, meshes)| meshes)
+        .collect();
+    // The subtrees expanding at the same time may together overshoot the limit.
+    if let Some(max_meshes) = config.max_meshes {
+        meshes.truncate(max_meshes);
+    }
+    meshes
+}
+
+/// Expands `root` breadth first until there are at least `target` pending subtrees, or nothing
+/// left to expand.
+fn split(config: &ExpansionConfig, root: Invocation, target: usize) -> Vec<Work> {
+    let mut frontier = vec![Work::Pending(root)];
+    for _ in 0..MAX_SPLIT_DEPTH {
+        let pending = frontier
+            .iter()
+            .filter(|work| matches!(work, Work::Pending(_)))
+            .count();
+        if pending == 0 || pending >= target {
+            break;
+        }
+
+        let mut next = Vec::with_capacity(frontier.len());
+        let mut children = vec![];
+        for work in frontier {
+            match work {
+                Work::Pending(invocation) => {
+                    if config.culls(&invocation) {
+                        continue;
+                    }
+                    if let Some(mesh) = invocation.expand(&mut children) {
+                        next.push(Work::Done(mesh));
+                    }
+                    // The last invocation pushed is the first one expanded.
+                    next.extend(children.drain(..).rev().map(Work::Pending));
+                }
+                done => next.push(done),
+            }
+        }
+        frontier = next;
+    }
+    frontier
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtrees_stop_once_the_ones_before_them_fill_the_limit() {
+        let budget = Budget::new(Some(5), 3);
+        assert!(!budget.spent(2, 4));
+        assert!(!budget.spent(0, 2));
+        assert!(budget.spent(1, 3));
+        assert!(budget.spent(2, 0));
+
+        let unlimited = Budget::new(None, 2);
+        assert!(!unlimited.spent(1, 1_000_000));
+    }
+}
@@ -13,9 +13,13 @@
 // limitations under the License.
 
 use crate::mesh::Vertex;
-use nalgebra::Matrix4;
+use crate::rule::{Rule, RuleInternal, ToRule};
+use nalgebra::{Matrix3, Matrix4, Quaternion, Rotation3, Unit, UnitQuaternion, Vector3, U1, U3};
 use palette::{encoding::srgb::Srgb, rgb::Rgb, Hsv, RgbHue};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
 use std::iter;
+use std::sync::Arc;
 
 fn identity() -> Matrix4<f32> {
     Matrix4::new(
@@ -59,16 +63,59 @@ pub type Tf = Transform;
 /// let containing_rule = Rule::new().push(Tf::s(0.5), our_translated_cube)
 /// # ;
 /// ````
-#[derive(Copy, Clone, Debug)]
+///
+/// Transforms can be serialized with [serde](https://serde.rs/). The spatial transform is written
+/// as the rows of its matrix, and the color transform as a hue in degrees, a saturation and a
+/// value.
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
 pub struct Transform {
+    #[serde(with = "matrix_rows")]
     spatial: Matrix4<f32>,
     color: ColorTransform,
 }
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 enum ColorTransform {
-    Override(Hsv),
-    Delta(Hsv),
+    Override(#[serde(with = "hsv_components")] Hsv),
+    Delta(#[serde(with = "hsv_components")] Hsv),
+}
+
+mod matrix_rows {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(
+        matrix: &Matrix4<f32>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        let mut rows = [[0.0; 4]; 4];
+        for (i, row) in rows.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = matrix[(i, j)];
+            }
+        }
+        rows.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Matrix4<f32>, D::Error> {
+        let rows = <[[f32; 4]; 4]>::deserialize(deserializer)?;
+        Ok(Matrix4::from_fn(|i, j| rows[i][j]))
+    }
+}
+
+mod hsv_components {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(color: &Hsv, serializer: S) -> Result<S::Ok, S::Error> {
+        [color.hue.to_degrees(), color.saturation, color.value].serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hsv, D::Error> {
+        let [hue, saturation, value] = <[f32; 3]>::deserialize(deserializer)?;
+        Ok(Hsv::new(hue, saturation, value))
+    }
 }
 
 impl Default for ColorTransform {
@@ -98,6 +145,25 @@ impl ColorTransform {
         }
     }
 
+    /// Interpolates between two color transforms, turning the shorter way around the hue circle.
+    /// Deltas interpolate to deltas, but if either is an override they are both applied to the
+    /// default color and interpolate to an override.
+    fn lerp(self, other: ColorTransform, t: f32) -> Self {
+        let mix = |a: Hsv, b: Hsv| {
+            Hsv::new(
+                a.hue + RgbHue::from((b.hue - a.hue).to_degrees() * t),
+                a.saturation + (b.saturation - a.saturation) * t,
+                a.value + (b.value - a.value) * t,
+            )
+        };
+        match (self, other) {
+            (ColorTransform::Delta(a), ColorTransform::Delta(b)) => {
+                ColorTransform::Delta(mix(a, b))
+            }
+            (a, b) => ColorTransform::Override(mix(a.color(), b.color())),
+        }
+    }
+
     fn color(self) -> Hsv {
         match self {
             ColorTransform::Override(color) => color,
@@ -126,6 +192,91 @@ impl Transform {
         self.spatial * vertex
     }
 
+    /// Transforms a surface normal by the inverse transpose of the linear part of the spatial
+    /// transform, so that it stays perpendicular to the transformed surface. Translations do not
+    /// affect normals. The result is renormalized and has a w of 0.
+    pub(crate) fn apply_to_normal(&self, normal: Vertex) -> Vertex {
+        let linear: Matrix3<f32> = self.spatial.fixed_slice::<U3, U3>(0, 0).into_owned();
+        // The cofactor matrix is the inverse transpose scaled by the determinant, and unlike the
+        // inverse it exists even when a scale collapses an axis.
+        let (c0, c1, c2) = (linear.column(0), linear.column(1), linear.column(2));
+        let cofactor = Matrix3::from_columns(&[c1.cross(&c2), c2.cross(&c0), c0.cross(&c1)]);
+        let transformed =
+            cofactor * Vector3::new(normal.x, normal.y, normal.z) * linear.determinant().signum();
+        let transformed = transformed.try_normalize(0.0).unwrap_or(transformed);
+        Vertex::new(transformed.x, transformed.y, transformed.z, 0.0)
+    }
+
+    /// Decomposes the spatial component into a translation, rotation, and scale, applied in reverse
+    /// order. Shear cannot be represented this way, so a sheared transform decomposes with the
+    /// nearest rotation.
+    pub(crate) fn decompose(&self) -> (Vector3<f32>, UnitQuaternion<f32>, Vector3<f32>) {
+        let translation = Vector3::new(
+            self.spatial[(0, 3)],
+            self.spatial[(1, 3)],
+            self.spatial[(2, 3)],
+        );
+        let linear: Matrix3<f32> = self.spatial.fixed_slice::<U3, U3>(0, 0).into_owned();
+        let mut scale = Vector3::new(
+            linear.column(0).norm(),
+            linear.column(1).norm(),
+            linear.column(2).norm(),
+        );
+        if linear.determinant() < 0.0 {
+            scale.x = -scale.x;
+        }
+        let mut rotation = linear;
+        for i in 0..3 {
+            if scale[i] != 0.0 {
+                let column = rotation.column(i) / scale[i];
+                rotation.set_column(i, &column);
+            }
+        }
+        let svd = rotation.svd(true, true);
+        let (mut u, v_t) = (svd.u.unwrap(), svd.v_t.unwrap());
+        if (u * v_t).determinant() < 0.0 {
+            let smallest = svd.singular_values.imin();
+            let column = -u.column(smallest);
+            u.set_column(smallest, &column);
+        }
+        let rotation = Rotation3::from_matrix_unchecked(u * v_t);
+        (
+            translation,
+            UnitQuaternion::from_rotation_matrix(&rotation),
+            scale,
+        )
+    }
+
+    /// Whether the spatial component is a translation, rotation, and scale, which
+    /// [decompose][Transform::decompose] represents without loss.
+    pub(crate) fn decomposes(&self) -> bool {
+        let (_, rotation, scale) = self.decompose();
+        let linear: Matrix3<f32> = self.spatial.fixed_slice::<U3, U3>(0, 0).into_owned();
+        let recomposed = rotation.to_rotation_matrix().matrix() * Matrix3::from_diagonal(&scale);
+        (recomposed - linear).norm() <= 0.0001 * linear.norm().max(1.0)
+    }
+
+    /// The spatial inverse of the transform, if no axis is collapsed. Its color is unchanged.
+    pub(crate) fn inverse(&self) -> Option<Transform> {
+        self.spatial.try_inverse().map(|spatial| Transform {
+            spatial,
+            ..Self::default()
+        })
+    }
+
+    /// Whether the spatial transform reflects space, which turns the faces of meshes it applies
+    /// to inside out.
+    pub(crate) fn mirrors(&self) -> bool {
+        self.spatial.fixed_slice::<U3, U3>(0, 0).determinant() < 0.0
+    }
+
+    /// The largest factor this transform scales any axis by.
+    pub(crate) fn size(&self) -> f32 {
+        (0..3)
+            .map(|i| self.spatial.fixed_slice::<U3, U1>(0, i).norm())
+            .fold(0.0, f32::max)
+    }
+
     pub(crate) fn get_color(&self) -> Rgb<Srgb, f32> {
         Rgb::from(
             ColorTransform::Override(Hsv::new(0.0, 1.0, 1.0))
@@ -182,7 +333,84 @@ impl Transform {
         }
     }
 
-    /// A rotation about the x axis.
+    /// A reflection across the plane x = 0.
+    pub fn fx() -> Self {
+        Self {
+            spatial: Scale::by(-1.0, 1.0, 1.0),
+            ..Self::default()
+        }
+    }
+
+    /// A reflection across the plane y = 0.
+    pub fn fy() -> Self {
+        Self {
+            spatial: Scale::by(1.0, -1.0, 1.0),
+            ..Self::default()
+        }
+    }
+
+    /// A reflection across the plane z = 0.
+    pub fn fz() -> Self {
+        Self {
+            spatial: Scale::by(1.0, 1.0, -1.0),
+            ..Self::default()
+        }
+    }
+
+    /// A shear along the x axis, which moves each point along x by `y` times its y coordinate
+    /// plus `z` times its z coordinate.
+    pub fn shx(y: f32, z: f32) -> Self {
+        Self {
+            spatial: Shear::by([0.0, y, z], [0.0; 3], [0.0; 3]),
+            ..Self::default()
+        }
+    }
+
+    /// A shear along the y axis, which moves each point along y by `x` times its x coordinate
+    /// plus `z` times its z coordinate.
+    pub fn shy(x: f32, z: f32) -> Self {
+        Self {
+            spatial: Shear::by([0.0; 3], [x, 0.0, z], [0.0; 3]),
+            ..Self::default()
+        }
+    }
+
+    /// A shear along the z axis, which moves each point along z by `x` times its x coordinate
+    /// plus `y` times its y coordinate.
+    pub fn shz(x: f32, y: f32) -> Self {
+        Self {
+            spatial: Shear::by([0.0; 3], [0.0; 3], [x, y, 0.0]),
+            ..Self::default()
+        }
+    }
+
+    /// An arbitrary affine transform, which maps each point `p` to `matrix * p`. The bottom row
+    /// of the matrix is ignored and taken to be `0, 0, 0, 1`.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // Squash a cube into a parallelepiped and lift it.
+    /// let slanted = Rule::new().push(
+    ///     Tf::matrix(Matrix4::new(
+    ///         1.0, 0.5, 0.0, 0.0, //
+    ///         0.0, 1.0, 0.0, 2.0, //
+    ///         0.0, 0.3, 0.8, 0.0, //
+    ///         0.0, 0.0, 0.0, 1.0,
+    ///     )),
+    ///     cube(),
+    /// );
+    /// ````
+    pub fn matrix(matrix: Matrix4<f32>) -> Self {
+        let mut spatial = matrix;
+        spatial.set_row(3, &identity().row(3));
+        Self {
+            spatial,
+            ..Self::default()
+        }
+    }
+
+    /// A rotation about the line through (0, 0.5, 0.5) parallel to the x axis. To rotate about the
+    /// x axis itself, use [r_axis][Transform::r_axis].
     pub fn rx(x: f32) -> Self {
         Self {
             spatial: Rotate::x(x),
@@ -190,7 +418,8 @@ impl Transform {
         }
     }
 
-    /// A rotation about the y axis.
+    /// A rotation about the line through (0.5, 0, 0.5) parallel to the y axis. To rotate about the
+    /// y axis itself, use [r_axis][Transform::r_axis].
     pub fn ry(y: f32) -> Self {
         Self {
             spatial: Rotate::y(y),
@@ -198,7 +427,8 @@ impl Transform {
         }
     }
 
-    /// A rotation about the z axis.
+    /// A rotation about the line through (0.5, 0.5, 0) parallel to the z axis. To rotate about the
+    /// z axis itself, use [r_axis][Transform::r_axis].
     pub fn rz(z: f32) -> Self {
         Self {
             spatial: Rotate::z(z),
@@ -206,6 +436,68 @@ impl Transform {
         }
     }
 
+    /// A rotation of `degrees` about `axis`, which passes through the origin. Positive angles turn
+    /// counterclockwise when looking back down the axis toward the origin. The axis need not be
+    /// normalized and its w is ignored. A zero axis does not rotate.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // A quarter turn about the diagonal of the unit cube.
+    /// let turned = Rule::new().push(Tf::r_axis(vertex(1.0, 1.0, 1.0), 90.0), cube());
+    /// ````
+    pub fn r_axis(axis: Vertex, degrees: f32) -> Self {
+        let rotation = match Unit::try_new(axis.xyz(), 0.0) {
+            Some(axis) => Rotation3::from_axis_angle(&axis, degrees.to_radians()),
+            None => Rotation3::identity(),
+        };
+        Self {
+            spatial: rotation.to_homogeneous(),
+            ..Self::default()
+        }
+    }
+
+    /// A rotation of `degrees` about the line through `point` along `axis`, with the same sense
+    /// as [r_axis][Transform::r_axis]. Points on the line stay where they are.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // Swing a cube about its top edge like a hinged flap.
+    /// let flap = Rule::new().push(
+    ///     Tf::rotate_about(vertex(0.0, 0.5, 0.5), vertex(1.0, 0.0, 0.0), -45.0),
+    ///     cube(),
+    /// );
+    /// ````
+    pub fn rotate_about(point: Vertex, axis: Vertex, degrees: f32) -> Self {
+        Self {
+            spatial: Translate::by(point.x, point.y, point.z)
+                * Transform::r_axis(axis, degrees).spatial
+                * Translate::by(-point.x, -point.y, -point.z),
+            ..Self::default()
+        }
+    }
+
+    /// A rotation by the quaternion `w + xi + yj + zk` about the origin. The quaternion is
+    /// normalized first, and a zero quaternion does not rotate.
+    pub fn quaternion(w: f32, x: f32, y: f32, z: f32) -> Self {
+        let rotation = UnitQuaternion::try_new(Quaternion::new(w, x, y, z), 0.0)
+            .unwrap_or_else(UnitQuaternion::identity);
+        Self {
+            spatial: rotation.to_homogeneous(),
+            ..Self::default()
+        }
+    }
+
+    /// A rotation by Euler angles in degrees about the origin: first `x` degrees about the x axis,
+    /// then `y` about the y axis and last `z` about the z axis. The axes are fixed, so this is the
+    /// same as `Tf::r_axis` about z, then y, then x, pushed in that order.
+    pub fn euler(x: f32, y: f32, z: f32) -> Self {
+        Self {
+            spatial: Rotation3::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians())
+                .to_homogeneous(),
+            ..Self::default()
+        }
+    }
+
     /// A color override that takes precedence over colors set higher in the rule tree.
     pub fn color(color: Hsv) -> Self {
         Self {
@@ -270,6 +562,73 @@ impl Default for Transform {
     }
 }
 
+/// A transform split into parts which interpolate independently. Shear is lost.
+#[derive(Copy, Clone)]
+struct Pose {
+    translation: Vector3<f32>,
+    rotation: UnitQuaternion<f32>,
+    scale: Vector3<f32>,
+    color: ColorTransform,
+}
+
+impl Pose {
+    fn of(transform: &Transform) -> Self {
+        let (translation, rotation, scale) = transform.decompose();
+        Pose {
+            translation,
+            rotation,
+            scale,
+            color: transform.color,
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        let (t, s) = (self.translation, self.scale);
+        Transform {
+            spatial: Translate::by(t.x, t.y, t.z)
+                * self.rotation.to_homogeneous()
+                * Scale::by(s.x, s.y, s.z),
+            color: self.color,
+        }
+    }
+
+    /// Spherically interpolates rotations the shorter way around.
+    fn slerp(a: &UnitQuaternion<f32>, b: &UnitQuaternion<f32>, t: f32) -> UnitQuaternion<f32> {
+        let b = if a.coords.dot(&b.coords) < 0.0 {
+            UnitQuaternion::new_unchecked(-b.into_inner())
+        } else {
+            *b
+        };
+        a.try_slerp(&b, t, 1.0e-6).unwrap_or_else(|| a.nlerp(&b, t))
+    }
+
+    fn lerp(&self, other: &Pose, t: f32) -> Pose {
+        Pose {
+            translation: self.translation.lerp(&other.translation, t),
+            rotation: Pose::slerp(&self.rotation, &other.rotation, t),
+            scale: self.scale.lerp(&other.scale, t),
+            color: self.color.lerp(other.color, t),
+        }
+    }
+
+    /// Interpolates between `b` and `c` on a Catmull-Rom spline which continues smoothly from `a`
+    /// and on to `d`. Rotations and colors interpolate between `b` and `c` only.
+    fn spline(a: &Pose, b: &Pose, c: &Pose, d: &Pose, t: f32) -> Pose {
+        let curve = |a: Vector3<f32>, b: Vector3<f32>, c: Vector3<f32>, d: Vector3<f32>| {
+            (b * 2.0
+                + (c - a) * t
+                + (a * 2.0 - b * 5.0 + c * 4.0 - d) * t * t
+                + (b * 3.0 - a - c * 3.0 + d) * t * t * t)
+                * 0.5
+        };
+        Pose {
+            translation: curve(a.translation, b.translation, c.translation, d.translation),
+            scale: curve(a.scale, b.scale, c.scale, d.scale),
+            ..b.lerp(c, t)
+        }
+    }
+}
+
 /// A TransformArgument is a transform that should be applied to the invocation of a
 /// [Rule][crate::rule::Rule].
 ///
@@ -282,6 +641,75 @@ pub enum TransformArgument {
     /// An arbitrary number of transforms (e.g. from [Replicate][self::Replicate]) that correspond
     /// to one invocation each.
     Many(Vec<Transform>),
+    /// An arbitrary number of transforms (e.g. from [Replicate::with][self::Replicate::with])
+    /// that are computed from their index as the rule expands, corresponding to one invocation
+    /// each.
+    Indexed(IndexedTransforms),
+}
+
+/// Transforms computed from their index only when they are needed, so long replications are not
+/// listed up front.
+#[derive(Clone)]
+pub struct IndexedTransforms {
+    n: usize,
+    transform: Arc<dyn Fn(usize) -> Transform + Send + Sync>,
+}
+
+impl IndexedTransforms {
+    pub(crate) fn len(&self) -> usize {
+        self.n
+    }
+
+    pub(crate) fn get(&self, i: usize) -> Transform {
+        (self.transform)(i)
+    }
+
+    /// Multiplicatively branches the transforms like [Transform::cross], without listing them.
+    fn cross(self, children: IndexedTransforms) -> Self {
+        let (parent, child, n) = (self.transform, children.transform, children.n);
+        IndexedTransforms {
+            n: self.n * n,
+            transform: Arc::new(move |i| parent(i / n).cons(child(i % n))),
+        }
+    }
+
+    /// Branches `args` in order if any of them are indexed.
+    fn cross_all(args: Vec<TransformArgument>) -> Result<Self, Vec<TransformArgument>> {
+        if !args
+            .iter()
+            .any(|arg| matches!(arg, TransformArgument::Indexed(_)))
+        {
+            return Err(args);
+        }
+        let root = IndexedTransforms::from(TransformArgument::Single(Transform::default()));
+        Ok(args
+            .into_iter()
+            .fold(root, |parents, arg| parents.cross(arg.into())))
+    }
+}
+
+impl fmt::Debug for IndexedTransforms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("IndexedTransforms")
+            .field("n", &self.n)
+            .finish()
+    }
+}
+
+impl From<TransformArgument> for IndexedTransforms {
+    fn from(argument: TransformArgument) -> Self {
+        match argument {
+            TransformArgument::Single(transform) => IndexedTransforms {
+                n: 1,
+                transform: Arc::new(move |_| transform),
+            },
+            TransformArgument::Many(transforms) => IndexedTransforms {
+                n: transforms.len(),
+                transform: Arc::new(move |i| transforms[i]),
+            },
+            TransformArgument::Indexed(transforms) => transforms,
+        }
+    }
 }
 
 /// An ergonomics macro for listing transforms that will apply in order and branch on replications.
@@ -318,6 +746,9 @@ impl Into<Vec<Transform>> for TransformArgument {
         match self {
             TransformArgument::Single(transform) => vec![transform],
             TransformArgument::Many(transforms) => transforms,
+            TransformArgument::Indexed(transforms) => {
+                (0..transforms.len()).map(|i| transforms.get(i)).collect()
+            }
         }
     }
 }
@@ -345,8 +776,14 @@ impl From<&[Transform]> for TransformArgument {
     }
 }
 
+/// A vector of arguments will be composed sequentially, branching on each argument with more than
+/// one transform. If any of them are indexed, so is the result.
 impl From<Vec<TransformArgument>> for TransformArgument {
     fn from(args: Vec<TransformArgument>) -> Self {
+        let args = match IndexedTransforms::cross_all(args) {
+            Ok(indexed) => return TransformArgument::Indexed(indexed),
+            Err(args) => args,
+        };
         let mut emitted = vec![Transform::default()];
         for arg in args {
             emitted = Transform::cross(emitted, arg.into());
@@ -372,11 +809,13 @@ impl From<Option<Transform>> for TransformArgument {
 /// (e.g. (A_1, B_1), (A_1, B_2), ..., (A_36, B_36)), so 360 total.
 impl From<Vec<Replicate>> for TransformArgument {
     fn from(replications: Vec<Replicate>) -> TransformArgument {
+        let args = replications.into_iter().map(|r| r.into()).collect();
+        let args = match IndexedTransforms::cross_all(args) {
+            Ok(indexed) => return TransformArgument::Indexed(indexed),
+            Err(args) => args,
+        };
         let mut emitted = vec![];
-        for replication in replications.into_iter().map(|r| -> Vec<Transform> {
-            let input: TransformArgument = r.into();
-            input.into()
-        }) {
+        for replication in args.into_iter().map(|arg| -> Vec<Transform> { arg.into() }) {
             emitted = if emitted.is_empty() {
                 replication
             } else {
@@ -391,36 +830,199 @@ impl From<Vec<Replicate>> for TransformArgument {
 ///
 /// The transforms will stack, so ```Replicate::n(2, Tf::x(1.0))``` on some rule will result in two
 /// invocations of the rule with ```Tf::x(1.0)``` and ```Tf::x(2.0)```.
+///
+/// Replications can also interpolate between transforms with [lerp][Replicate::lerp] and
+/// [spline][Replicate::spline]. Interpolated transforms are decomposed into a translation, a
+/// rotation and a scale which interpolate independently, so shear is lost between the ends.
+/// Rotations turn the shorter way around, and so do hues.
+///
+/// Copies which follow some other pattern can be computed from their index with
+/// [with][Replicate::with], and copies of different subrules with
+/// [with_rules][Replicate::with_rules].
 pub struct Replicate {
-    n: usize,
-    source: TransformArgument,
+    copies: Copies,
+}
+
+enum Copies {
+    /// `n` copies of each source transform, stacked.
+    Stacked { n: usize, source: TransformArgument },
+    /// Copies computed up front.
+    Listed(Vec<Transform>),
+    /// Copies computed from their index as they are expanded.
+    Indexed(IndexedTransforms),
 }
 
 impl Replicate {
     pub fn n(n: usize, source: impl Into<TransformArgument>) -> Self {
         Self {
-            n,
-            source: source.into(),
+            copies: Copies::Stacked {
+                n,
+                source: source.into(),
+            },
         }
     }
+
+    /// Replicates `n` transforms evenly interpolated from `from` to `to`, starting with `from`
+    /// and ending with `to`.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // A row of cubes which shrink, turn and fade from red to blue.
+    /// let row = Rule::new().push(
+    ///     Replicate::lerp(
+    ///         8,
+    ///         Tf::color(Hsv::new(0.0, 1.0, 1.0)),
+    ///         vec![
+    ///             Tf::tx(10.0),
+    ///             Tf::r_axis(Vertex::x(), 90.0),
+    ///             Tf::s(0.2),
+    ///             Tf::color(Hsv::new(240.0, 1.0, 1.0)),
+    ///         ],
+    ///     ),
+    ///     cube(),
+    /// );
+    /// ````
+    pub fn lerp(
+        n: usize,
+        from: impl Into<TransformArgument>,
+        to: impl Into<TransformArgument>,
+    ) -> Self {
+        Self::spline(n, vec![from.into(), to.into()])
+    }
+
+    /// Replicates `n` transforms along a smooth
+    /// [Catmull-Rom spline](https://en.wikipedia.org/wiki/Centripetal_Catmull%E2%80%93Rom_spline)
+    /// through `keys`, starting with the first key and ending with the last. Translations and
+    /// scales follow the spline, while rotations and colors interpolate between neighboring keys.
+    /// Each span between keys gets the same share of the copies. A key given as several transforms
+    /// is their composition.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // Cubes winding up through three keys.
+    /// let keys = vec![
+    ///     Tf::t(0.0, 0.0, 0.0).into(),
+    ///     vec![Tf::t(4.0, 4.0, 0.0), Tf::s(0.5)].into(),
+    ///     Tf::t(0.0, 8.0, 4.0).into(),
+    /// ];
+    /// let path = Rule::new().push(Replicate::spline(24, keys), cube());
+    /// ````
+    pub fn spline(n: usize, keys: Vec<TransformArgument>) -> Self {
+        let keys: Vec<Transform> = keys
+            .into_iter()
+            .map(|key| {
+                let transforms: Vec<Transform> = key.into();
+                Transform::coalesce(None, transforms.into_iter())
+            })
+            .collect();
+        let poses: Vec<Pose> = keys.iter().map(Pose::of).collect();
+        let spans = keys.len().saturating_sub(1);
+        let copies = (0..n)
+            .map(|i| {
+                if spans == 0 {
+                    return keys.first().cloned().unwrap_or_default();
+                }
+                let u = if n == 1 {
+                    0.0
+                } else {
+                    i as f32 * spans as f32 / (n - 1) as f32
+                };
+                let span = (u as usize).min(spans - 1);
+                let t = u - span as f32;
+                if t == 0.0 {
+                    return keys[span];
+                } else if t == 1.0 {
+                    return keys[span + 1];
+                }
+                let pose = |i: isize| poses[i.max(0).min(spans as isize) as usize];
+                let span = span as isize;
+                Pose::spline(
+                    &pose(span - 1),
+                    &pose(span),
+                    &pose(span + 1),
+                    &pose(span + 2),
+                    t,
+                )
+                .transform()
+            })
+            .collect();
+        Self {
+            copies: Copies::Listed(copies),
+        }
+    }
+
+    /// Replicates `n` transforms computed from their index by `transform`, which is called for
+    /// each copy as the rule it is applied to expands rather than up front.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // Phyllotaxis: each seed turns by the golden angle and moves out with the square root of
+    /// // its index.
+    /// let golden_angle = std::f32::consts::PI * (3.0 - 5.0f32.sqrt());
+    /// let sunflower = Rule::new().push(
+    ///     Replicate::with(500, move |i| {
+    ///         let (angle, radius) = (golden_angle * i as f32, (i as f32).sqrt());
+    ///         Tf::t(radius * angle.cos(), 0.0, radius * angle.sin())
+    ///     }),
+    ///     icosphere(),
+    /// );
+    /// # assert_eq!(sunflower.generate().count(), 500);
+    /// ````
+    pub fn with(n: usize, transform: impl Fn(usize) -> Transform + Send + Sync + 'static) -> Self {
+        Self {
+            copies: Copies::Indexed(IndexedTransforms {
+                n,
+                transform: Arc::new(transform),
+            }),
+        }
+    }
+
+    /// Returns a rule which invokes `n` subrules computed from their index by `invocation`, each
+    /// under the transform computed with it. Like [with][Replicate::with], each copy is only
+    /// computed as the rule expands.
+    ///
+    /// ````
+    /// # use immense::*;
+    /// // Rows of cubes which grow logarithmically longer and further apart.
+    /// let rows = Replicate::with_rules(100, |i| {
+    ///     let length = ((i + 1) as f32).ln().ceil() as usize;
+    ///     (
+    ///         Tf::tz(3.0 * ((i + 1) as f32).ln()),
+    ///         Rule::new().push(Replicate::n(length, Tf::tx(1.1)), cube()),
+    ///     )
+    /// });
+    /// ````
+    pub fn with_rules<R: ToRule>(
+        n: usize,
+        invocation: impl Fn(usize) -> (Transform, R) + Send + Sync + 'static,
+    ) -> Rule {
+        Rule::replicated(n, move |i| {
+            let (transform, rule) = invocation(i);
+            (Some(transform), RuleInternal::Invocations(Arc::new(rule)))
+        })
+    }
 }
 
-/// The replication will become ```n``` transforms, corresponding to one invocation each.
+/// The replication will become one transform per copy, corresponding to one invocation each.
 impl Into<TransformArgument> for Replicate {
     fn into(self) -> TransformArgument {
-        match self.source {
-            TransformArgument::Single(transform) => {
-                TransformArgument::Many((0..self.n).map(|i| transform.stack(i)).collect())
-            }
-            TransformArgument::Many(transforms) => TransformArgument::Many({
+        match self.copies {
+            Copies::Stacked {
+                n,
+                source: TransformArgument::Single(transform),
+            } => TransformArgument::Many((0..n).map(|i| transform.stack(i)).collect()),
+            Copies::Stacked { n, source } => TransformArgument::Many({
                 let mut emitted = vec![];
+                let transforms: Vec<Transform> = source.into();
                 for transform in transforms {
-                    for i in 0..self.n {
+                    for i in 0..n {
                         emitted.push(transform.stack(i));
                     }
                 }
                 emitted
             }),
+            Copies::Listed(transforms) => TransformArgument::Many(transforms),
+            Copies::Indexed(transforms) => TransformArgument::Indexed(transforms),
         }
     }
 }
@@ -467,6 +1069,22 @@ impl Scale {
     }
 }
 
+#[derive(Default, Clone, Copy, Debug)]
+struct Shear;
+
+impl Shear {
+    /// Builds a shear from how far each axis moves per unit of each coordinate. Each axis's own
+    /// factor is ignored.
+    pub fn by(x: [f32; 3], y: [f32; 3], z: [f32; 3]) -> Matrix4<f32> {
+        Matrix4::new(
+            1.0, x[1], x[2], 0.0, //
+            y[0], 1.0, y[2], 0.0, //
+            z[0], z[1], 1.0, 0.0, //
+            0.0, 0.0, 0.0, 1.0,
+        )
+    }
+}
+
 #[derive(Clone, Copy, Debug)]
 struct Rotate;
 
@@ -504,3 +1122,333 @@ impl Rotate {
             ) * Translate::by(-0.5, -0.5, 0.0)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mesh::vertex;
+
+    const EPSILON: f32 = 0.0001;
+
+    fn points() -> Vec<Vertex> {
+        vec![
+            vertex(0.0, 0.0, 0.0),
+            vertex(1.0, 0.0, 0.0),
+            vertex(0.0, 1.0, 0.0),
+            vertex(0.0, 0.0, 1.0),
+            vertex(0.3, -2.0, 1.5),
+        ]
+    }
+
+    fn assert_maps(transform: Transform, expected: impl Fn(Vector3<f32>) -> Vector3<f32>) {
+        for point in points() {
+            let actual = transform.apply_to(point);
+            let expected = expected(point.xyz());
+            assert_eq!(actual.w, 1.0);
+            assert!(
+                (actual.xyz() - expected).norm() < EPSILON,
+                "{:?} went to {:?} instead of {:?}",
+                point,
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn axis_rotations_match_nalgebra() {
+        let axis = Vector3::new(1.0, 2.0, -0.5);
+        let rotation = Rotation3::from_axis_angle(&Unit::new_normalize(axis), 70f32.to_radians());
+        assert_maps(
+            Tf::r_axis(Vertex::new(axis.x, axis.y, axis.z, 1.0), 70.0),
+            |p| rotation * p,
+        );
+        assert_maps(Tf::r_axis(Vertex::z(), 90.0), |p| {
+            Vector3::new(-p.y, p.x, p.z)
+        });
+        assert_maps(Tf::r_axis(Vertex::zeros(), 90.0), |p| p);
+    }
+
+    #[test]
+    fn pivoted_rotations_fix_their_axis() {
+        let (point, axis) = (Vector3::new(1.0, -1.0, 2.0), Vector3::new(0.0, 1.0, 1.0));
+        let rotation = Rotation3::from_axis_angle(&Unit::new_normalize(axis), -35f32.to_radians());
+        let transform = Tf::rotate_about(
+            vertex(point.x, point.y, point.z),
+            Vertex::new(axis.x, axis.y, axis.z, 0.0),
+            -35.0,
+        );
+        assert_maps(transform, |p| rotation * (p - point) + point);
+        let on_axis = point + axis * 3.0;
+        let moved = transform.apply_to(vertex(on_axis.x, on_axis.y, on_axis.z));
+        assert!((moved.xyz() - on_axis).norm() < EPSILON);
+    }
+
+    #[test]
+    fn legacy_rotations_pivot_off_the_origin() {
+        for &degrees in &[30.0, -120.0] {
+            let pivoted = |point, axis| Tf::rotate_about(point, axis, degrees);
+            let pairs = [
+                (Tf::rx(degrees), pivoted(vertex(0.0, 0.5, 0.5), Vertex::x())),
+                (Tf::ry(degrees), pivoted(vertex(0.5, 0.0, 0.5), Vertex::y())),
+                (Tf::rz(degrees), pivoted(vertex(0.5, 0.5, 0.0), Vertex::z())),
+            ];
+            for (legacy, pivoted) in pairs.iter() {
+                assert_maps(*legacy, |p| {
+                    pivoted.apply_to(Vertex::new(p.x, p.y, p.z, 1.0)).xyz()
+                });
+            }
+        }
+    }
+
+    #[test]
+    fn reflections_mirror_one_axis() {
+        assert_maps(Tf::fx(), |p| Vector3::new(-p.x, p.y, p.z));
+        assert_maps(Tf::fy(), |p| Vector3::new(p.x, -p.y, p.z));
+        assert_maps(Tf::fz(), |p| Vector3::new(p.x, p.y, -p.z));
+        assert!(Tf::fx().mirrors());
+        assert!(Tf::fx().cons(Tf::tz(2.0)).cons(Tf::s(0.5)).mirrors());
+        assert!(!Tf::fx().cons(Tf::fy()).mirrors());
+        assert!(Tf::sby(1.0, -2.0, 1.0).mirrors());
+        assert!(!Tf::r_axis(vertex(1.0, 1.0, 0.0), 135.0).mirrors());
+    }
+
+    #[test]
+    fn shears_move_along_one_axis() {
+        assert_maps(Tf::shx(0.5, -1.0), |p| {
+            Vector3::new(p.x + 0.5 * p.y - p.z, p.y, p.z)
+        });
+        assert_maps(Tf::shy(2.0, 0.25), |p| {
+            Vector3::new(p.x, p.y + 2.0 * p.x + 0.25 * p.z, p.z)
+        });
+        assert_maps(Tf::shz(-0.5, 3.0), |p| {
+            Vector3::new(p.x, p.y, p.z - 0.5 * p.x + 3.0 * p.y)
+        });
+        assert!(!Tf::shx(4.0, 4.0).mirrors());
+    }
+
+    #[test]
+    fn matrices_are_affine() {
+        let linear = Matrix3::new(1.0, 0.5, 0.0, 0.0, -1.0, 0.0, 0.3, 0.0, 2.0);
+        let translation = Vector3::new(1.0, 2.0, 3.0);
+        let mut matrix = linear.to_homogeneous();
+        matrix.set_column(
+            3,
+            &Vertex::new(translation.x, translation.y, translation.z, 1.0),
+        );
+        matrix.set_row(3, &Matrix4::repeat(7.0).row(3));
+        let transform = Tf::matrix(matrix);
+        assert_maps(transform, |p| linear * p + translation);
+        assert!(transform.mirrors());
+    }
+
+    /// Rebuilds a transform from its decomposition.
+    fn recompose(transform: Transform) -> Transform {
+        let (translation, rotation, scale) = transform.decompose();
+        let spatial = Matrix4::new_translation(&translation)
+            * rotation.to_homogeneous()
+            * Matrix4::new_nonuniform_scaling(&scale);
+        Tf::matrix(spatial)
+    }
+
+    #[test]
+    fn decompositions_round_trip() {
+        let transforms = [
+            Tf::default(),
+            Tf::t(1.0, -2.0, 3.0),
+            Tf::sby(1.0, 2.0, 0.5),
+            Tf::r_axis(vertex(1.0, 2.0, -0.5), 70.0)
+                .cons(Tf::t(0.5, 0.0, -1.0))
+                .cons(Tf::sby(3.0, 0.25, 1.5)),
+            Tf::rx(30.0).cons(Tf::ry(-45.0)).cons(Tf::s(2.0)),
+        ];
+        for transform in transforms.iter() {
+            assert_same(recompose(*transform), *transform);
+        }
+    }
+
+    #[test]
+    fn decompositions_keep_their_rotation() {
+        let axis = Vector3::new(1.0, 2.0, -0.5);
+        let rotation = UnitQuaternion::from_axis_angle(&Unit::new_normalize(axis), 1.2);
+        let transform = Tf::t(1.0, 2.0, 3.0)
+            .cons(Tf::r_axis(
+                Vertex::new(axis.x, axis.y, axis.z, 0.0),
+                1.2f32.to_degrees(),
+            ))
+            .cons(Tf::sby(2.0, 3.0, 4.0));
+        let (translation, decomposed, scale) = transform.decompose();
+        assert!((translation - Vector3::new(1.0, 2.0, 3.0)).norm() < EPSILON);
+        let rotation_error =
+            decomposed.to_rotation_matrix().matrix() - rotation.to_rotation_matrix().matrix();
+        assert!(rotation_error.norm() < EPSILON);
+        assert!((scale - Vector3::new(2.0, 3.0, 4.0)).norm() < EPSILON);
+    }
+
+    #[test]
+    fn only_shears_do_not_decompose() {
+        assert!(Tf::default().decomposes());
+        assert!(Tf::t(1.0, 2.0, 3.0)
+            .cons(Tf::r_axis(vertex(1.0, 2.0, -0.5), 70.0))
+            .cons(Tf::sby(3.0, 0.25, -1.5))
+            .decomposes());
+        assert!(Tf::sby(0.0, 1.0, 1.0).decomposes());
+        assert!(!Tf::shx(0.5, 0.0).decomposes());
+        assert!(!Tf::rz(30.0)
+            .cons(Tf::sby(2.0, 1.0, 1.0))
+            .cons(Tf::rz(30.0))
+            .decomposes());
+    }
+
+    #[test]
+    fn mirrored_decompositions_have_one_negative_scale() {
+        let transforms = [
+            Tf::fx(),
+            Tf::fz(),
+            Tf::sby(1.0, -2.0, 1.0),
+            Tf::rz(60.0).cons(Tf::fy()).cons(Tf::sby(2.0, 0.5, 3.0)),
+        ];
+        for transform in transforms.iter() {
+            let (_, rotation, scale) = transform.decompose();
+            assert_eq!(scale.iter().filter(|s| **s < 0.0).count(), 1);
+            assert!((rotation.to_rotation_matrix().matrix().determinant() - 1.0).abs() < EPSILON);
+            assert_same(recompose(*transform), *transform);
+        }
+    }
+
+    fn replicated(replicate: Replicate) -> Vec<Transform> {
+        let argument: TransformArgument = replicate.into();
+        argument.into()
+    }
+
+    fn assert_same(actual: Transform, expected: Transform) {
+        assert_maps(actual, |p| {
+            expected.apply_to(Vertex::new(p.x, p.y, p.z, 1.0)).xyz()
+        });
+    }
+
+    #[test]
+    fn lerps_decompose_and_end_exactly() {
+        let from = Tf::tz(1.0);
+        let to = Tf::t(4.0, 0.0, 1.0)
+            .cons(Tf::r_axis(Vertex::y(), 90.0))
+            .cons(Tf::s(3.0));
+        let copies = replicated(Replicate::lerp(3, from, to));
+        assert_eq!(copies.len(), 3);
+        assert_eq!(copies[0].spatial, from.spatial);
+        assert_eq!(copies[2].spatial, to.spatial);
+        assert_same(
+            copies[1],
+            Tf::t(2.0, 0.0, 1.0)
+                .cons(Tf::r_axis(Vertex::y(), 45.0))
+                .cons(Tf::s(2.0)),
+        );
+    }
+
+    #[test]
+    fn lerps_turn_the_short_way() {
+        let copies = replicated(Replicate::lerp(
+            3,
+            Tf::r_axis(Vertex::z(), 170.0),
+            Tf::r_axis(Vertex::z(), -170.0),
+        ));
+        assert_same(copies[1], Tf::r_axis(Vertex::z(), 180.0));
+
+        let hues = replicated(Replicate::lerp(3, Tf::hue(350.0), Tf::hue(10.0)));
+        assert_eq!(hues[1].get_color(), Tf::hue(0.0).get_color());
+
+        let colors = replicated(Replicate::lerp(
+            5,
+            Tf::color(Hsv::new(340.0, 1.0, 0.2)),
+            Tf::color(Hsv::new(20.0, 0.6, 1.0)),
+        ));
+        let expected = Tf::color(Hsv::new(0.0, 0.8, 0.6)).get_color();
+        let actual = colors[2].get_color();
+        assert!((actual.red - expected.red).abs() < EPSILON);
+        assert!((actual.green - expected.green).abs() < EPSILON);
+        assert!((actual.blue - expected.blue).abs() < EPSILON);
+    }
+
+    #[test]
+    fn splines_pass_through_their_keys() {
+        let keys = [
+            Tf::tx(0.0),
+            Tf::tx(2.0),
+            Tf::tx(4.0).cons(Tf::s(2.0)),
+            Tf::tx(6.0),
+        ];
+        let copies = replicated(Replicate::spline(
+            7,
+            keys.iter().map(|key| (*key).into()).collect(),
+        ));
+        assert_eq!(copies.len(), 7);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(copies[i * 2].spatial, key.spatial);
+        }
+        // Between evenly spaced keys in a line, the spline follows the line.
+        assert_same(copies[3], Tf::tx(3.0).cons(Tf::s(1.5625)));
+        assert_eq!(
+            replicated(Replicate::spline(3, vec![Tf::ty(1.0).into()])).len(),
+            3
+        );
+    }
+
+    #[test]
+    fn quaternions_match_nalgebra() {
+        let quaternion = Quaternion::new(0.5, -1.0, 2.0, 0.25);
+        let rotation = UnitQuaternion::from_quaternion(quaternion);
+        assert_maps(Tf::quaternion(0.5, -1.0, 2.0, 0.25), |p| rotation * p);
+        assert_maps(Tf::quaternion(0.0, 0.0, 0.0, 0.0), |p| p);
+    }
+
+    #[test]
+    fn euler_angles_match_nalgebra_and_compose_about_fixed_axes() {
+        let (x, y, z) = (20.0f32, -75.0f32, 130.0f32);
+        let rotation =
+            UnitQuaternion::from_euler_angles(x.to_radians(), y.to_radians(), z.to_radians());
+        assert_maps(Tf::euler(x, y, z), |p| rotation * p);
+        let composed = Tf::r_axis(Vertex::z(), z)
+            .cons(Tf::r_axis(Vertex::y(), y))
+            .cons(Tf::r_axis(Vertex::x(), x));
+        assert_maps(Tf::euler(x, y, z), |p| {
+            composed.apply_to(Vertex::new(p.x, p.y, p.z, 1.0)).xyz()
+        });
+    }
+
+    #[test]
+    fn indexed_replications_branch_like_listed_ones() {
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let counter = calls.clone();
+        let indexed: TransformArgument = tf![
+            Tf::ry(30.0),
+            Replicate::with(3, move |i| {
+                counter.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                Tf::tx(i as f32 + 1.0)
+            }),
+            Replicate::n(2, Tf::ty(1.0)),
+        ]
+        .into();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        let listed: TransformArgument = tf![
+            Tf::ry(30.0),
+            Replicate::n(3, Tf::tx(1.0)),
+            Replicate::n(2, Tf::ty(1.0)),
+        ]
+        .into();
+        let (indexed, listed): (Vec<Transform>, Vec<Transform>) = match (indexed, listed) {
+            (TransformArgument::Indexed(indexed), TransformArgument::Many(listed)) => {
+                (TransformArgument::Indexed(indexed).into(), listed)
+            }
+            arguments => panic!(
+                "expected indexed and listed transforms, got {:?}",
+                arguments
+            ),
+        };
+        assert_eq!(indexed.len(), 6);
+        assert_eq!(listed.len(), 6);
+        for (indexed, listed) in indexed.into_iter().zip(listed) {
+            assert_same(indexed, listed);
+        }
+    }
+}